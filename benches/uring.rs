@@ -0,0 +1,93 @@
+//! Echo round trips over loopback, epoll path versus io_uring path.
+//!
+//! Run with `cargo bench --features uring --bench uring`.
+
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use udpflow::{UdpSocket, UdpListener, UdpStreamRemote};
+use udpflow::uring::{UringListener, UringStreamRemote};
+
+const EPOLL_BIND: &str = "127.0.0.1:20000";
+const URING_BIND: &str = "127.0.0.1:20001";
+const ROUNDS: usize = 50_000;
+const SIZE: usize = 1200;
+
+fn main() {
+    let epoll = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(epoll());
+    report("epoll", epoll);
+
+    let uring = tokio_uring::start(uring());
+    report("io_uring", uring);
+}
+
+fn report(name: &str, elapsed: Duration) {
+    let rate = ROUNDS as f64 / elapsed.as_secs_f64();
+    println!(
+        "{:>8}: {} round trips of {} bytes in {:?}, {:.0}/s",
+        name, ROUNDS, SIZE, elapsed, rate
+    );
+}
+
+async fn epoll() -> Duration {
+    let addr: SocketAddr = EPOLL_BIND.parse().unwrap();
+    let listener = UdpListener::new(UdpSocket::bind(addr).await.unwrap());
+    tokio::spawn(async move {
+        let mut buf = vec![0u8; 0x2000];
+        while let Ok((mut stream, _)) = listener.accept(&mut buf).await {
+            tokio::spawn(async move {
+                let mut buf = vec![0u8; 0x2000];
+                while let Ok(n @ 1..) = stream.read(&mut buf).await {
+                    let _ = stream.write(&buf[..n]).await;
+                }
+            });
+        }
+    });
+
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut stream = UdpStreamRemote::new(socket, addr);
+    let msg = vec![0x55u8; SIZE];
+    let mut buf = vec![0u8; 0x2000];
+
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        stream.write_all(&msg).await.unwrap();
+        let n = stream.read(&mut buf).await.unwrap();
+        assert_eq!(n, SIZE);
+    }
+    start.elapsed()
+}
+
+async fn uring() -> Duration {
+    let addr: SocketAddr = URING_BIND.parse().unwrap();
+    let listener = UringListener::bind(addr).await.unwrap();
+    tokio_uring::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio_uring::spawn(async move {
+                let mut buf = vec![0u8; 0x2000];
+                while let Ok(n @ 1..) = stream.recv(&mut buf).await {
+                    let _ = stream.send(buf[..n].to_vec()).await;
+                }
+            });
+        }
+    });
+
+    let socket = tokio_uring::net::UdpSocket::bind("127.0.0.1:0".parse().unwrap()).await.unwrap();
+    let mut stream = UringStreamRemote::new(socket, addr);
+    let mut msg = vec![0x55u8; SIZE];
+    let mut buf = vec![0u8; 0x2000];
+
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        let (res, b) = stream.send(msg).await;
+        msg = b;
+        res.unwrap();
+        let n = stream.recv(&mut buf).await.unwrap();
+        assert_eq!(n, SIZE);
+    }
+    start.elapsed()
+}
@@ -0,0 +1,708 @@
+//! Reliable and ordered byte stream over datagram streams.
+//!
+//! ## Protocol Specification
+//!
+//! ```text
+//! +-----+-----+-----+-----+----------+
+//! | CMD | SEQ | UNA | WND |   DATA   |
+//! +-----+-----+-----+-----+----------+
+//! |  1  |  4  |  4  |  2  | Variable |
+//! +-----+-----+-----+-----+----------+
+//! ```
+//! All integers are unsigned and in big endian byte order.
+//!
+//! - CMD is one of `PUSH(0)`, `ACK(1)` and `FIN(2)`.
+//! - SEQ is the sequence number of a `PUSH` or `FIN` segment.
+//! - UNA is the next sequence number expected by the sender, which acknowledges
+//!   all segments before it.
+//! - WND is the free receive window of the sender, in segments.
+//!
+//! DATA of an `ACK` segment is a list of selective acknowledgement blocks,
+//! each block is a pair of 32-bit sequence numbers `[START, END)`.
+//!
+
+use std::io::{Result, Error, ErrorKind};
+use std::collections::{BTreeMap, VecDeque};
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+
+use tokio::io::{ReadBuf, AsyncRead, AsyncWrite};
+
+use crate::time::{sleep, Sleep, Instant};
+use crate::DatagramStream;
+use crate::util::extend_seq;
+
+const HEADER: usize = 11;
+const CMD_PUSH: u8 = 0;
+const CMD_ACK: u8 = 1;
+const CMD_FIN: u8 = 2;
+const MAX_SACK_BLOCKS: usize = 16;
+const INITIAL_RTO: Duration = Duration::from_millis(500);
+
+/// Reliability options.
+#[derive(Debug, Clone, Copy)]
+pub struct ArqConfig {
+    /// Max payload of a segment.
+    pub mss: usize,
+    /// Max number of unacknowledged segments.
+    pub send_window: u16,
+    /// Max number of received segments that are not yet read.
+    pub recv_window: u16,
+    /// Lower bound of the retransmission timeout.
+    pub min_rto: Duration,
+    /// Upper bound of the retransmission timeout.
+    pub max_rto: Duration,
+    /// Retransmit a segment after it is skipped by this many acknowledgements.
+    pub fast_resend: u32,
+    /// Give up after a segment is retransmitted this many times.
+    pub max_retransmits: u32,
+    /// Enable congestion control, otherwise only flow control is applied.
+    pub congestion_control: bool,
+}
+
+impl Default for ArqConfig {
+    fn default() -> Self {
+        Self {
+            mss: 1200,
+            send_window: 256,
+            recv_window: 256,
+            min_rto: Duration::from_millis(100),
+            max_rto: Duration::from_secs(10),
+            fast_resend: 3,
+            max_retransmits: 20,
+            congestion_control: true,
+        }
+    }
+}
+
+/// Transmission statistics.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ArqStats {
+    /// Segments sent for the first time.
+    pub sent: u64,
+    /// Segments retransmitted after timeout.
+    pub retransmitted: u64,
+    /// Segments retransmitted after being skipped by acknowledgements.
+    pub fast_retransmitted: u64,
+    /// Smoothed round trip time.
+    pub srtt: Option<Duration>,
+    /// Congestion window, in segments.
+    pub cwnd: u32,
+}
+
+struct Segment {
+    seq: u64,
+    cmd: u8,
+    data: Vec<u8>,
+    xmit: u32,
+    ts: Instant,
+    rto: Duration,
+    resend_at: Instant,
+    skipped: u32,
+    fast: bool,
+    sacked: bool,
+}
+
+/// Wakers of the read and write sides, which may be polled by different tasks,
+/// e.g. after [`split`](tokio::io::split).
+///
+/// The inner stream and the timer are registered with a waker that wakes both.
+#[derive(Default)]
+struct Wakers {
+    rd: Mutex<Option<Waker>>,
+    wr: Mutex<Option<Waker>>,
+}
+
+impl Wakers {
+    fn register(slot: &Mutex<Option<Waker>>, waker: &Waker) {
+        let mut slot = slot.lock().unwrap();
+        if !slot.as_ref().is_some_and(|x| x.will_wake(waker)) {
+            *slot = Some(waker.clone());
+        }
+    }
+}
+
+impl Wake for Wakers {
+    fn wake(self: Arc<Self>) { self.wake_by_ref() }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        for slot in [&self.rd, &self.wr] {
+            if let Some(waker) = slot.lock().unwrap().take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+/// Reliable and ordered byte stream, over a lossy datagram stream.
+///
+/// Lost segments are recovered by selective acknowledgements and retransmissions,
+/// with the timeout estimated from round trip time. The sending rate is limited
+/// by the peer's receive window and the congestion window.
+///
+/// The stream makes progress only when it is polled, so it should be continuously
+/// read or flushed, e.g. by [`copy_bidirectional`](tokio::io::copy_bidirectional).
+/// `poll_flush` completes after all data is acknowledged, `poll_shutdown` sends a `FIN`
+/// then waits for it to be acknowledged. The read and write sides may be polled
+/// from different tasks.
+pub struct Reliable<S> {
+    inner: S,
+    conf: ArqConfig,
+    timer: Pin<Box<Sleep>>,
+    rbuf: Vec<u8>,
+    wbuf: Vec<u8>,
+    wbuf_pending: bool,
+    wakers: Arc<Wakers>,
+    io_waker: Waker,
+    closed: bool,
+    error: Option<ErrorKind>,
+    stats: ArqStats,
+    // send
+    snd_queue: VecDeque<Segment>,
+    snd_nxt: u64,
+    fin_sent: bool,
+    rmt_wnd: u16,
+    cwnd: u32,
+    cwnd_incr: u32,
+    ssthresh: u32,
+    recover: u64,
+    srtt: Option<Duration>,
+    rttvar: Duration,
+    rto: Duration,
+    // recv
+    rcv_nxt: u64,
+    rcv_ooo: BTreeMap<u64, (u8, Vec<u8>)>,
+    rcv_queue: VecDeque<Vec<u8>>,
+    rcv_offset: usize,
+    fin_received: bool,
+    ack_pending: bool,
+}
+
+impl<S: DatagramStream + Unpin> Reliable<S> {
+    /// Create from a datagram stream, both sides must use the same `mss`.
+    pub fn new(inner: S, conf: ArqConfig) -> Self {
+        assert!(conf.mss > 0 && conf.send_window > 0 && conf.recv_window > 0);
+        let wakers = Arc::new(Wakers::default());
+        Self {
+            inner,
+            conf,
+            timer: Box::pin(sleep(Duration::ZERO)),
+            rbuf: vec![0u8; std::cmp::max(HEADER + conf.mss, HEADER + MAX_SACK_BLOCKS * 8)],
+            wbuf: Vec::with_capacity(HEADER + conf.mss),
+            wbuf_pending: false,
+            wakers: wakers.clone(),
+            io_waker: Waker::from(wakers),
+            closed: false,
+            error: None,
+            stats: ArqStats::default(),
+            snd_queue: VecDeque::new(),
+            snd_nxt: 0,
+            fin_sent: false,
+            rmt_wnd: conf.recv_window,
+            cwnd: 1,
+            cwnd_incr: 0,
+            ssthresh: conf.send_window as u32,
+            recover: 0,
+            srtt: None,
+            rttvar: Duration::ZERO,
+            rto: INITIAL_RTO.clamp(conf.min_rto, conf.max_rto),
+            rcv_nxt: 0,
+            rcv_ooo: BTreeMap::new(),
+            rcv_queue: VecDeque::new(),
+            rcv_offset: 0,
+            fin_received: false,
+            ack_pending: false,
+        }
+    }
+
+    /// Get transmission statistics.
+    #[inline]
+    pub fn stats(&self) -> ArqStats {
+        ArqStats {
+            srtt: self.srtt,
+            cwnd: self.cwnd,
+            ..self.stats
+        }
+    }
+
+    /// Get inner stream.
+    #[inline]
+    pub const fn get_ref(&self) -> &S { &self.inner }
+
+    /// Get inner stream.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut S { &mut self.inner }
+
+    /// Free receive window to advertise.
+    #[inline]
+    fn rcv_wnd(&self) -> u16 {
+        let used = self.rcv_ooo.len() + self.rcv_queue.len();
+        (self.conf.recv_window as usize).saturating_sub(used) as u16
+    }
+
+    /// Number of segments which are sent but not acknowledged.
+    #[inline]
+    fn inflight(&self) -> u32 {
+        self.snd_queue.iter().filter(|x| x.xmit > 0 && !x.sacked).count() as u32
+    }
+
+    #[inline]
+    fn error(&self) -> Error {
+        match self.error {
+            Some(kind) => Error::new(kind, "reliable stream is broken"),
+            None => ErrorKind::BrokenPipe.into(),
+        }
+    }
+
+    #[inline]
+    fn wake(&self) { self.wakers.wake_by_ref() }
+
+    /// Finish sending the buffered packet.
+    fn poll_send_buffered(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        if self.wbuf_pending {
+            ready!(self.inner.poll_send(cx, &self.wbuf))?;
+            self.wbuf_pending = false;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn encode(&mut self, cmd: u8, seq: u64) {
+        self.wbuf.clear();
+        self.wbuf.push(cmd);
+        self.wbuf.extend_from_slice(&(seq as u32).to_be_bytes());
+        self.wbuf.extend_from_slice(&(self.rcv_nxt as u32).to_be_bytes());
+        self.wbuf.extend_from_slice(&self.rcv_wnd().to_be_bytes());
+        self.wbuf_pending = true;
+    }
+
+    fn encode_ack(&mut self) {
+        self.encode(CMD_ACK, 0);
+        let mut blocks = 0;
+        let mut iter = self.rcv_ooo.keys().copied().peekable();
+        while let Some(start) = iter.next() {
+            let mut end = start + 1;
+            while iter.next_if_eq(&end).is_some() {
+                end += 1;
+            }
+            self.wbuf.extend_from_slice(&(start as u32).to_be_bytes());
+            self.wbuf.extend_from_slice(&(end as u32).to_be_bytes());
+            blocks += 1;
+            if blocks == MAX_SACK_BLOCKS {
+                break;
+            }
+        }
+        self.ack_pending = false;
+    }
+
+    fn update_rtt(&mut self, rtt: Duration) {
+        // RFC 6298
+        match self.srtt {
+            None => {
+                self.srtt = Some(rtt);
+                self.rttvar = rtt / 2;
+            }
+            Some(srtt) => {
+                let delta = srtt.abs_diff(rtt);
+                self.rttvar = (self.rttvar * 3 + delta) / 4;
+                self.srtt = Some((srtt * 7 + rtt) / 8);
+            }
+        }
+        let rto = self.srtt.unwrap() + self.rttvar * 4;
+        self.rto = rto.clamp(self.conf.min_rto, self.conf.max_rto);
+    }
+
+    fn on_loss(&mut self) {
+        if self.conf.congestion_control {
+            self.ssthresh = std::cmp::max(self.inflight() / 2, 2);
+            self.cwnd = self.ssthresh;
+            self.cwnd_incr = 0;
+        }
+    }
+
+    fn on_acked(&mut self, acked: u32) {
+        if !self.conf.congestion_control {
+            return;
+        }
+        for _ in 0..acked {
+            if self.cwnd < self.ssthresh {
+                // slow start
+                self.cwnd += 1;
+            } else {
+                // congestion avoidance
+                self.cwnd_incr += 1;
+                if self.cwnd_incr >= self.cwnd {
+                    self.cwnd_incr = 0;
+                    self.cwnd += 1;
+                }
+            }
+        }
+        self.cwnd = std::cmp::min(self.cwnd, self.conf.send_window as u32);
+    }
+
+    /// Handle acknowledgements.
+    fn on_una(&mut self, una: u64, now: Instant) {
+        let mut acked = 0;
+        while let Some(seg) = self.snd_queue.front() {
+            if seg.seq >= una || seg.xmit == 0 {
+                break;
+            }
+            let seg = self.snd_queue.pop_front().unwrap();
+            // Karn's algorithm
+            if seg.xmit == 1 {
+                self.update_rtt(now - seg.ts);
+            }
+            acked += 1;
+        }
+        self.on_acked(acked);
+    }
+
+    fn on_sack(&mut self, blocks: &[u8]) {
+        let mut max_sacked = None;
+        let mut acked = 0;
+        for block in blocks.chunks_exact(8) {
+            let base = self.snd_queue.front().map_or(self.snd_nxt, |x| x.seq);
+            let start = u32::from_be_bytes(block[..4].try_into().unwrap());
+            let end = u32::from_be_bytes(block[4..].try_into().unwrap());
+            let (start, end) = match (extend_seq(base, start), extend_seq(base, end)) {
+                (Some(start), Some(end)) => (start, end),
+                _ => continue,
+            };
+            for seg in self.snd_queue.iter_mut() {
+                if seg.seq >= start && seg.seq < end && seg.xmit > 0 && !seg.sacked {
+                    seg.sacked = true;
+                    acked += 1;
+                }
+            }
+            max_sacked = std::cmp::max(max_sacked, Some(end));
+        }
+        self.on_acked(acked);
+
+        let max_sacked = match max_sacked {
+            Some(x) => x,
+            None => return,
+        };
+        let now = Instant::now();
+        let mut lost = false;
+        for seg in self.snd_queue.iter_mut() {
+            if seg.seq >= max_sacked {
+                break;
+            }
+            if seg.sacked || seg.xmit == 0 {
+                continue;
+            }
+            seg.skipped += 1;
+            if seg.skipped >= self.conf.fast_resend {
+                seg.skipped = 0;
+                seg.fast = true;
+                seg.resend_at = now;
+                if seg.seq >= self.recover {
+                    lost = true;
+                }
+            }
+        }
+        if lost {
+            self.recover = self.snd_nxt;
+            self.on_loss();
+        }
+    }
+
+    /// Handle an incoming packet.
+    fn on_packet(&mut self, pkt: &[u8]) {
+        if pkt.len() < HEADER {
+            return;
+        }
+        let now = Instant::now();
+        let cmd = pkt[0];
+        let seq = u32::from_be_bytes(pkt[1..5].try_into().unwrap());
+        let una = u32::from_be_bytes(pkt[5..9].try_into().unwrap());
+        let wnd = u16::from_be_bytes(pkt[9..11].try_into().unwrap());
+        let data = &pkt[HEADER..];
+
+        let base = self.snd_queue.front().map_or(self.snd_nxt, |x| x.seq);
+        if let Some(una) = extend_seq(base, una) {
+            if una <= self.snd_nxt {
+                self.on_una(una, now);
+            }
+        }
+        self.rmt_wnd = wnd;
+
+        match cmd {
+            CMD_ACK => self.on_sack(data),
+            CMD_PUSH | CMD_FIN => {
+                self.ack_pending = true;
+                let seq = match extend_seq(self.rcv_nxt, seq) {
+                    Some(seq) => seq,
+                    None => return,
+                };
+                // duplicated, or out of window
+                if seq < self.rcv_nxt || seq >= self.rcv_nxt + self.conf.recv_window as u64 {
+                    return;
+                }
+                self.rcv_ooo.entry(seq).or_insert_with(|| (cmd, data.to_vec()));
+
+                // move in-order segments
+                while let Some((cmd, data)) = self.rcv_ooo.remove(&self.rcv_nxt) {
+                    self.rcv_nxt += 1;
+                    if cmd == CMD_FIN {
+                        self.fin_received = true;
+                        break;
+                    }
+                    if !data.is_empty() {
+                        self.rcv_queue.push_back(data);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Receive packets, send segments, and handle timers.
+    ///
+    /// The caller registers its waker in [`Wakers`] first.
+    fn poll_io(&mut self) -> Result<()> {
+        if self.error.is_some() {
+            return Err(self.error());
+        }
+        let waker = self.io_waker.clone();
+        let cx = &mut Context::from_waker(&waker);
+
+        // receive
+        let mut progress = false;
+        while !self.closed {
+            let mut rbuf = ReadBuf::new(&mut self.rbuf);
+            match self.inner.poll_recv(cx, &mut rbuf) {
+                Poll::Ready(Ok(())) if rbuf.filled().is_empty() => self.closed = true,
+                Poll::Ready(Ok(())) => {
+                    let n = rbuf.filled().len();
+                    let pkt = self.rbuf[..n].to_vec();
+                    self.on_packet(&pkt);
+                    progress = true;
+                }
+                Poll::Ready(Err(e)) => {
+                    self.error = Some(e.kind());
+                    return Err(e);
+                }
+                Poll::Pending => break,
+            }
+        }
+        if progress || self.closed {
+            self.wake();
+        }
+
+        if let Err(e) = self.poll_output(cx) {
+            self.error = Some(e.kind());
+            self.wake();
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn poll_output(&mut self, cx: &mut Context<'_>) -> Result<()> {
+        let now = Instant::now();
+
+        if self.poll_send_buffered(cx)?.is_pending() {
+            return Ok(());
+        }
+
+        // acknowledgement
+        if self.ack_pending {
+            self.encode_ack();
+            if self.poll_send_buffered(cx)?.is_pending() {
+                return Ok(());
+            }
+        }
+
+        // retransmission
+        let due: Vec<usize> = (0..self.snd_queue.len())
+            .filter(|&i| {
+                let seg = &self.snd_queue[i];
+                seg.xmit > 0 && !seg.sacked && seg.resend_at <= now
+            })
+            .collect();
+        if due.iter().any(|&i| self.snd_queue[i].xmit > self.conf.max_retransmits) {
+            return Err(ErrorKind::TimedOut.into());
+        }
+        if due.iter().any(|&i| !self.snd_queue[i].fast) && self.conf.congestion_control {
+            self.ssthresh = std::cmp::max(self.inflight() / 2, 2);
+            self.cwnd = 1;
+            self.cwnd_incr = 0;
+        }
+        for i in due {
+            let seg = &mut self.snd_queue[i];
+            // fast retransmission does not back off
+            if seg.fast {
+                seg.fast = false;
+                self.stats.fast_retransmitted += 1;
+            } else {
+                seg.rto = std::cmp::min(seg.rto * 2, self.conf.max_rto);
+                self.stats.retransmitted += 1;
+            }
+            seg.xmit += 1;
+            seg.ts = now;
+            seg.resend_at = now + seg.rto;
+            let (cmd, seq) = (seg.cmd, seg.seq);
+            self.encode(cmd, seq);
+            self.wbuf.extend_from_slice(&self.snd_queue[i].data);
+            if self.poll_send_buffered(cx)?.is_pending() {
+                return Ok(());
+            }
+        }
+
+        // new segments
+        let cwnd = if self.conf.congestion_control { self.cwnd } else { u32::MAX };
+        let mut inflight = self.inflight();
+        // zero window probe
+        let wnd = std::cmp::max(std::cmp::min(cwnd, self.rmt_wnd as u32), (inflight == 0) as u32);
+        for i in 0..self.snd_queue.len() {
+            if inflight >= wnd {
+                break;
+            }
+            let seg = &self.snd_queue[i];
+            if seg.xmit > 0 {
+                continue;
+            }
+            let (cmd, seq) = (seg.cmd, seg.seq);
+            self.encode(cmd, seq);
+            let rto = self.rto;
+            let seg = &mut self.snd_queue[i];
+            self.wbuf.extend_from_slice(&seg.data);
+            seg.xmit = 1;
+            seg.ts = now;
+            seg.rto = rto;
+            seg.resend_at = now + rto;
+            inflight += 1;
+            self.stats.sent += 1;
+            if self.poll_send_buffered(cx)?.is_pending() {
+                return Ok(());
+            }
+        }
+
+        // register timer
+        let deadline = self
+            .snd_queue
+            .iter()
+            .filter(|x| x.xmit > 0 && !x.sacked)
+            .map(|x| x.resend_at)
+            .min();
+        if let Some(deadline) = deadline {
+            self.timer.as_mut().reset(deadline);
+            if self.timer.as_mut().poll(cx).is_ready() {
+                cx.waker().wake_by_ref();
+            }
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, cmd: u8, data: Vec<u8>) {
+        let now = Instant::now();
+        self.snd_queue.push_back(Segment {
+            seq: self.snd_nxt,
+            cmd,
+            data,
+            xmit: 0,
+            ts: now,
+            rto: self.rto,
+            resend_at: now,
+            skipped: 0,
+            fast: false,
+            sacked: false,
+        });
+        self.snd_nxt += 1;
+    }
+}
+
+impl<S: DatagramStream + Unpin> AsyncRead for Reliable<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        Wakers::register(&this.wakers.rd, cx.waker());
+        let res = this.poll_io();
+
+        let free = this.rcv_wnd();
+        let mut read = false;
+        while buf.remaining() > 0 {
+            let data = match this.rcv_queue.front() {
+                Some(x) => x,
+                None => break,
+            };
+            let n = std::cmp::min(data.len() - this.rcv_offset, buf.remaining());
+            buf.put_slice(&data[this.rcv_offset..this.rcv_offset + n]);
+            this.rcv_offset += n;
+            if this.rcv_offset == data.len() {
+                this.rcv_queue.pop_front();
+                this.rcv_offset = 0;
+            }
+            read = true;
+        }
+
+        if read {
+            // window update
+            if free == 0 && this.rcv_wnd() > 0 {
+                this.ack_pending = true;
+                // an error is kept, then returned by the next call
+                let _ = this.poll_io();
+            }
+            return Poll::Ready(Ok(()));
+        }
+
+        res?;
+        // EOF
+        if this.fin_received || this.closed {
+            return Poll::Ready(Ok(()));
+        }
+
+        Poll::Pending
+    }
+}
+
+impl<S: DatagramStream + Unpin> AsyncWrite for Reliable<S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        let this = self.get_mut();
+        Wakers::register(&this.wakers.wr, cx.waker());
+        this.poll_io()?;
+        if this.closed || this.fin_sent {
+            return Poll::Ready(Err(this.error()));
+        }
+
+        let free = (this.conf.send_window as usize).saturating_sub(this.snd_queue.len());
+        if free == 0 {
+            return Poll::Pending;
+        }
+
+        let mut written = 0;
+        for chunk in buf.chunks(this.conf.mss).take(free) {
+            this.push(CMD_PUSH, chunk.to_vec());
+            written += chunk.len();
+        }
+        // an error is kept, then returned by the next call
+        let _ = this.poll_io();
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        Wakers::register(&this.wakers.wr, cx.waker());
+        this.poll_io()?;
+        if this.snd_queue.is_empty() {
+            return Poll::Ready(Ok(()));
+        }
+        if this.closed {
+            return Poll::Ready(Err(this.error()));
+        }
+        Poll::Pending
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        if !self.fin_sent {
+            self.fin_sent = true;
+            self.push(CMD_FIN, Vec::new());
+        }
+        self.poll_flush(cx)
+    }
+}
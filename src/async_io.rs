@@ -0,0 +1,120 @@
+//! Udp socket of `async-io`, which drives smol and async-std.
+//!
+//! Requires the `runtime-smol` or `runtime-async-std` feature.
+//!
+//! The backend is chosen by the socket type, so this works no matter
+//! whether `runtime-tokio` is also enabled by another crate.
+//!
+//! ```
+//! use udpflow::{SockOpts, UdpStreamRemote};
+//! use udpflow::async_io::{self, UdpSocket};
+//! # fn f() -> std::io::Result<()> {
+//! let socket = async_io::bind("127.0.0.1:0".parse().unwrap(), &SockOpts::default())?;
+//! let stream: UdpStreamRemote<UdpSocket> =
+//!     UdpStreamRemote::new(socket, "127.0.0.1:10000".parse().unwrap());
+//! # Ok(())
+//! # }
+//! ```
+//!
+
+use std::io::{Result, ErrorKind};
+use std::net::SocketAddr;
+use std::time::Instant;
+use std::task::{Context, Poll};
+
+use tokio::io::ReadBuf;
+
+use crate::{DatagramSocket, SockOpts, RecvMeta};
+
+/// Udp socket of async-io.
+pub type UdpSocket = ::async_io::Async<std::net::UdpSocket>;
+
+/// Create a udp socket, apply options then bind it to `addr`.
+#[inline]
+pub fn bind(addr: SocketAddr, opts: &SockOpts) -> Result<UdpSocket> {
+    UdpSocket::new(opts.bind_std(addr)?)
+}
+
+impl DatagramSocket for UdpSocket {
+    fn poll_recv_from(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<SocketAddr>> {
+        loop {
+            match self.get_ref().recv_from(buf.initialize_unfilled()) {
+                Ok((n, addr)) => {
+                    buf.advance(n);
+                    return Poll::Ready(Ok(addr));
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    ready!(self.poll_readable(cx))?;
+                }
+                Err(e) => return Poll::Ready(Err(e)),
+            }
+        }
+    }
+
+    fn poll_send_to(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+        target: &SocketAddr,
+    ) -> Poll<Result<usize>> {
+        loop {
+            match self.get_ref().send_to(buf, *target) {
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    ready!(self.poll_writable(cx))?;
+                }
+                x => return Poll::Ready(x),
+            }
+        }
+    }
+
+    #[inline]
+    fn local_addr(&self) -> Result<SocketAddr> { self.get_ref().local_addr() }
+
+    #[cfg(unix)]
+    #[inline]
+    fn as_raw_fd(&self) -> Option<std::os::fd::RawFd> {
+        Some(std::os::fd::AsRawFd::as_raw_fd(self.get_ref()))
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn poll_recv_meta(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(SocketAddr, RecvMeta)>> {
+        loop {
+            match crate::meta::linux::recvmsg(self.get_ref(), buf) {
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    ready!(self.poll_readable(cx))?;
+                }
+                x => return Poll::Ready(x),
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[inline]
+    fn enable_txtime(&self) -> Result<()> { crate::pacing::linux::enable(self.get_ref()) }
+
+    #[cfg(target_os = "linux")]
+    fn poll_send_at(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+        target: &SocketAddr,
+        at: Instant,
+    ) -> Poll<Result<usize>> {
+        loop {
+            match crate::pacing::linux::sendmsg(self.get_ref(), buf, *target, at) {
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    ready!(self.poll_writable(cx))?;
+                }
+                x => return Poll::Ready(x),
+            }
+        }
+    }
+}
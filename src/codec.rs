@@ -0,0 +1,194 @@
+//! UoT codec for `tokio_util::codec`.
+//!
+//! Requires the `codec` feature.
+//!
+//! [`UotCodec`] speaks the same wire format as [`UotStream`](crate::UotStream),
+//! so that `Framed<TcpStream, UotCodec>` is a `Stream + Sink` of datagrams.
+//!
+//! ```
+//! use bytes::Bytes;
+//! use futures::{SinkExt, StreamExt};
+//! use tokio::net::TcpStream;
+//! use tokio_util::codec::Framed;
+//! use udpflow::codec::UotCodec;
+//! async {
+//!     let stream = TcpStream::connect("127.0.0.1:8080").await.unwrap();
+//!     let mut framed = Framed::new(stream, UotCodec::new());
+//!     framed.send(Bytes::from_static(b"Ciallo")).await.unwrap();
+//!     let frame = framed.next().await.unwrap().unwrap();
+//! };
+//! ```
+//!
+
+use std::io::{Error, ErrorKind};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::frame::{FrameConfig, Oversized};
+
+/// Codec of the UoT format.
+///
+/// [`FrameConfig::small_buffer`] does not apply, each item is a whole frame.
+#[derive(Debug, Clone, Default)]
+pub struct UotCodec {
+    conf: FrameConfig,
+    skip: u64,
+}
+
+impl UotCodec {
+    /// Create with the default [`FrameConfig`].
+    #[inline]
+    pub fn new() -> Self { Self::default() }
+
+    /// Create with framing options.
+    #[inline]
+    pub const fn with_config(conf: FrameConfig) -> Self { Self { conf, skip: 0 } }
+
+    /// Get framing options.
+    #[inline]
+    pub const fn config(&self) -> &FrameConfig { &self.conf }
+}
+
+impl Decoder for UotCodec {
+    type Item = BytesMut;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<BytesMut>, Error> {
+        loop {
+            // discard an oversized frame
+            if self.skip > 0 {
+                let n = std::cmp::min(self.skip, src.len() as u64);
+                src.advance(n as usize);
+                self.skip -= n;
+                if self.skip > 0 {
+                    return Ok(None);
+                }
+            }
+
+            let prefix = self.conf.prefix;
+            if src.len() < prefix.min_size() {
+                return Ok(None);
+            }
+            let size = prefix.size(src[0]);
+            if src.len() < size {
+                return Ok(None);
+            }
+
+            let length = prefix.decode(&src[..size]);
+            if length > self.conf.max_frame() as u64 {
+                match self.conf.oversized {
+                    Oversized::Skip => {
+                        src.advance(size);
+                        self.skip = length;
+                        continue;
+                    }
+                    Oversized::Reject => {
+                        return Err(Error::new(ErrorKind::InvalidData, "frame too large"));
+                    }
+                }
+            }
+
+            let length = length as usize;
+            if src.len() < size + length {
+                src.reserve(size + length - src.len());
+                return Ok(None);
+            }
+            src.advance(size);
+            return Ok(Some(src.split_to(length)));
+        }
+    }
+}
+
+impl Encoder<Bytes> for UotCodec {
+    type Error = Error;
+
+    fn encode(&mut self, item: Bytes, dst: &mut BytesMut) -> Result<(), Error> {
+        if item.len() > self.conf.max_frame() {
+            return Err(Error::new(ErrorKind::InvalidInput, "datagram too large"));
+        }
+        let mut len = [0u8; 8];
+        let size = self.conf.prefix.encode(item.len(), &mut len);
+        dst.reserve(size + item.len());
+        dst.put_slice(&len[..size]);
+        dst.put_slice(&item);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::frame::{ByteOrder, LengthPrefix};
+
+    #[test]
+    fn codec() {
+        let dummy: Vec<u8> = (0..70_000u32).map(|x| x as u8).collect();
+        let sizes = [0, 1, 0x3f, 0x40, 0x3fff, 0x4000, 65535, 70_000];
+        for prefix in [
+            LengthPrefix::U16(ByteOrder::Big),
+            LengthPrefix::U32(ByteOrder::Little),
+            LengthPrefix::Varint,
+        ] {
+            let conf = FrameConfig {
+                prefix,
+                max_frame: 70_000,
+                ..FrameConfig::default()
+            };
+            let mut codec = UotCodec::with_config(conf);
+            let max = conf.max_frame();
+
+            let mut wire = BytesMut::new();
+            for &n in sizes.iter().filter(|&&n| n <= max) {
+                codec.encode(Bytes::copy_from_slice(&dummy[..n]), &mut wire).unwrap();
+            }
+            let err = codec.encode(Bytes::from(vec![0u8; max + 1]), &mut wire).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+            // fed byte by byte
+            let mut src = BytesMut::new();
+            let mut frames = Vec::new();
+            for b in wire {
+                src.put_u8(b);
+                while let Some(frame) = codec.decode(&mut src).unwrap() {
+                    frames.push(frame);
+                }
+            }
+            let expect: Vec<_> = sizes.iter().filter(|&&n| n <= max).collect();
+            assert_eq!(frames.len(), expect.len());
+            for (frame, &&n) in frames.iter().zip(expect.iter()) {
+                assert_eq!(&frame[..], &dummy[..n]);
+            }
+        }
+    }
+
+    #[test]
+    fn oversized() {
+        let mut src = BytesMut::new();
+        for n in [4usize, 100, 4] {
+            src.put_u16(n as u16);
+            src.put_slice(&vec![n as u8; n]);
+        }
+        let conf = FrameConfig {
+            max_frame: 8,
+            ..FrameConfig::default()
+        };
+
+        let mut codec = UotCodec::with_config(conf);
+        let mut buf = src.clone();
+        assert_eq!(&codec.decode(&mut buf).unwrap().unwrap()[..], &[4u8; 4]);
+        let err = codec.decode(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        let mut codec = UotCodec::with_config(FrameConfig {
+            oversized: Oversized::Skip,
+            ..conf
+        });
+        let mut buf = src.split_to(20);
+        assert_eq!(&codec.decode(&mut buf).unwrap().unwrap()[..], &[4u8; 4]);
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+        buf.unsplit(src);
+        assert_eq!(&codec.decode(&mut buf).unwrap().unwrap()[..], &[4u8; 4]);
+        assert!(codec.decode(&mut buf).unwrap().is_none());
+    }
+}
@@ -0,0 +1,308 @@
+//! Per-datagram compression.
+//!
+//! Requires the `lz4` or `zstd` feature.
+//!
+//! ## Protocol Specification
+//!
+//! ```text
+//! +------+----------+
+//! | FLAG |   DATA   |
+//! +------+----------+
+//! |  1   | Variable |
+//! +------+----------+
+//! ```
+//! FLAG is `RAW(0)`, `LZ4(1)` or `ZSTD(2)`. A datagram is sent raw if it is smaller
+//! than the threshold, or does not shrink after compression. The receiver decompresses
+//! with the algorithm marked by FLAG, regardless of its own configuration.
+//!
+
+use std::io::{Result, Error, ErrorKind};
+use std::task::{Context, Poll};
+
+use tokio::io::ReadBuf;
+
+use crate::DatagramStream;
+
+const RAW: u8 = 0;
+#[cfg(feature = "lz4")]
+const LZ4: u8 = 1;
+#[cfg(feature = "zstd")]
+const ZSTD: u8 = 2;
+const MAX_DATAGRAM: usize = 0x10000;
+
+/// Compression algorithm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    /// LZ4 block format, requires the `lz4` feature.
+    #[cfg(feature = "lz4")]
+    Lz4,
+    /// Zstandard with a compression level, requires the `zstd` feature.
+    #[cfg(feature = "zstd")]
+    Zstd(i32),
+}
+
+/// Compression options.
+#[derive(Debug, Clone, Copy)]
+pub struct CompressConfig {
+    /// Algorithm for outgoing datagrams.
+    pub algorithm: Algorithm,
+    /// Datagrams smaller than this are sent raw.
+    pub threshold: usize,
+}
+
+impl CompressConfig {
+    /// Create with an algorithm.
+    #[inline]
+    pub const fn new(algorithm: Algorithm) -> Self {
+        Self {
+            algorithm,
+            threshold: 64,
+        }
+    }
+}
+
+/// Compression statistics.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CompressStats {
+    /// Datagrams sent compressed.
+    pub compressed: u64,
+    /// Datagrams sent raw.
+    pub passthrough: u64,
+    /// Bytes passed to send, before compression.
+    pub bytes_in: u64,
+    /// Bytes sent to the inner stream, including headers.
+    pub bytes_out: u64,
+    /// Received datagrams that could not be decompressed.
+    pub malformed: u64,
+}
+
+impl CompressStats {
+    /// Ratio of sent bytes to input bytes, lower is better, 1.0 if nothing is sent.
+    pub fn ratio(&self) -> f64 {
+        if self.bytes_in == 0 {
+            return 1.0;
+        }
+        self.bytes_out as f64 / self.bytes_in as f64
+    }
+}
+
+/// Compress each datagram with the configured [`Algorithm`].
+///
+/// Datagrams below the threshold, or which do not shrink, are sent raw. Packets which
+/// could not be decompressed are silently dropped, and counted in [`CompressStats`].
+pub struct Compressed<S> {
+    inner: S,
+    conf: CompressConfig,
+    #[cfg(feature = "zstd")]
+    cctx: Option<zstd::bulk::Compressor<'static>>,
+    #[cfg(feature = "zstd")]
+    dctx: Option<zstd::bulk::Decompressor<'static>>,
+    wbuf: Vec<u8>,
+    wbuf_pending: bool,
+    rbuf: Vec<u8>,
+    plain: Vec<u8>,
+    stats: CompressStats,
+}
+
+impl<S: DatagramStream> Compressed<S> {
+    /// Create from a datagram stream.
+    pub fn new(inner: S, conf: CompressConfig) -> Self {
+        Self {
+            inner,
+            conf,
+            #[cfg(feature = "zstd")]
+            cctx: None,
+            #[cfg(feature = "zstd")]
+            dctx: None,
+            wbuf: Vec::new(),
+            wbuf_pending: false,
+            rbuf: vec![0u8; MAX_DATAGRAM],
+            plain: vec![0u8; MAX_DATAGRAM],
+            stats: CompressStats::default(),
+        }
+    }
+
+    /// Get compression statistics.
+    #[inline]
+    pub const fn stats(&self) -> CompressStats { self.stats }
+
+    /// Get inner stream.
+    #[inline]
+    pub const fn get_ref(&self) -> &S { &self.inner }
+
+    /// Get inner stream.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut S { &mut self.inner }
+
+    /// Unwrap inner stream.
+    #[inline]
+    pub fn into_inner(self) -> S { self.inner }
+
+    /// Compress `buf` into `wbuf` after the flag, return false if it should be sent raw.
+    fn compress(&mut self, buf: &[u8]) -> bool {
+        match self.conf.algorithm {
+            #[cfg(feature = "lz4")]
+            Algorithm::Lz4 => {
+                self.wbuf.resize(1 + lz4_flex::block::get_maximum_output_size(buf.len()), 0);
+                match lz4_flex::block::compress_into(buf, &mut self.wbuf[1..]) {
+                    Ok(n) if n < buf.len() => {
+                        self.wbuf[0] = LZ4;
+                        self.wbuf.truncate(1 + n);
+                        true
+                    }
+                    _ => false,
+                }
+            }
+            #[cfg(feature = "zstd")]
+            Algorithm::Zstd(level) => {
+                // contexts are created lazily and reused
+                if self.cctx.is_none() {
+                    self.cctx = zstd::bulk::Compressor::new(level).ok();
+                }
+                let Some(cctx) = self.cctx.as_mut() else {
+                    return false;
+                };
+                // the output must be smaller than the input
+                self.wbuf.resize(std::cmp::max(buf.len(), 1), 0);
+                match cctx.compress_to_buffer(buf, &mut self.wbuf[1..]) {
+                    Ok(n) => {
+                        self.wbuf[0] = ZSTD;
+                        self.wbuf.truncate(1 + n);
+                        true
+                    }
+                    Err(_) => false,
+                }
+            }
+        }
+    }
+
+    /// Decompress a packet in `rbuf` into `plain`, return the length.
+    fn decompress(&mut self, n: usize) -> Option<usize> {
+        match self.rbuf[0] {
+            #[cfg(feature = "lz4")]
+            LZ4 => lz4_flex::block::decompress_into(&self.rbuf[1..n], &mut self.plain).ok(),
+            #[cfg(feature = "zstd")]
+            ZSTD => {
+                if self.dctx.is_none() {
+                    self.dctx = zstd::bulk::Decompressor::new().ok();
+                }
+                let dctx = self.dctx.as_mut()?;
+                dctx.decompress_to_buffer(&self.rbuf[1..n], &mut self.plain[..]).ok()
+            }
+            _ => None,
+        }
+    }
+}
+
+impl<S: DatagramStream> DatagramStream for Compressed<S> {
+    fn poll_recv(&mut self, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<Result<()>> {
+        loop {
+            let mut rbuf = ReadBuf::new(&mut self.rbuf);
+            ready!(self.inner.poll_recv(cx, &mut rbuf))?;
+            let n = rbuf.filled().len();
+
+            // EOF
+            if n == 0 {
+                return Poll::Ready(Ok(()));
+            }
+
+            // truncate
+            if self.rbuf[0] == RAW {
+                let n = std::cmp::min(n - 1, buf.remaining());
+                buf.put_slice(&self.rbuf[1..1 + n]);
+                return Poll::Ready(Ok(()));
+            }
+            match self.decompress(n) {
+                Some(m) => {
+                    let m = std::cmp::min(m, buf.remaining());
+                    buf.put_slice(&self.plain[..m]);
+                    return Poll::Ready(Ok(()));
+                }
+                None => self.stats.malformed += 1,
+            }
+        }
+    }
+
+    fn poll_send(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<()>> {
+        if !self.wbuf_pending {
+            if buf.len() >= MAX_DATAGRAM {
+                return Poll::Ready(Err(Error::new(ErrorKind::InvalidInput, "datagram too large")));
+            }
+            if buf.len() < self.conf.threshold || !self.compress(buf) {
+                self.wbuf.clear();
+                self.wbuf.push(RAW);
+                self.wbuf.extend_from_slice(buf);
+                self.stats.passthrough += 1;
+            } else {
+                self.stats.compressed += 1;
+            }
+            self.stats.bytes_in += buf.len() as u64;
+            self.stats.bytes_out += self.wbuf.len() as u64;
+            self.wbuf_pending = true;
+        }
+
+        let res = ready!(self.inner.poll_send(cx, &self.wbuf));
+        self.wbuf_pending = false;
+        Poll::Ready(res)
+    }
+
+    #[inline]
+    fn reset_timeout(&mut self) { self.inner.reset_timeout() }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testutil::Queue;
+
+    fn algorithms() -> Vec<Algorithm> {
+        vec![
+            #[cfg(feature = "lz4")]
+            Algorithm::Lz4,
+            #[cfg(feature = "zstd")]
+            Algorithm::Zstd(3),
+        ]
+    }
+
+    #[tokio::test]
+    async fn compress() {
+        let mut buf = vec![0u8; 0x10000];
+        let text = b"GET /index.html HTTP/1.1\r\nHost: example.com\r\n\r\n".repeat(20);
+        // xorshift, incompressible
+        let mut x = 0x2545f4914f6cdd1du64;
+        let noise: Vec<u8> = (0..1000)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 7;
+                x ^= x << 17;
+                x as u8
+            })
+            .collect();
+
+        for algorithm in algorithms() {
+            let conf = CompressConfig::new(algorithm);
+            let mut stream = Compressed::new(Queue::new(), conf);
+            for msg in [&b""[..], b"Ciallo", &text, &noise] {
+                stream.send(msg).await.unwrap();
+                let n = stream.recv(&mut buf).await.unwrap();
+                assert_eq!(&buf[..n], msg);
+            }
+
+            let stats = stream.stats();
+            assert_eq!(stats.compressed, 1);
+            assert_eq!(stats.passthrough, 3);
+            assert_eq!(stats.bytes_in, (text.len() + noise.len() + 6) as u64);
+
+            // unknown flag
+            stream.send(&text).await.unwrap();
+            stream.get_mut().0[0][0] = 7;
+            assert_eq!(stream.recv(&mut buf).await.unwrap(), 0);
+            assert_eq!(stream.stats().malformed, 1);
+
+            let conf = CompressConfig::new(algorithm);
+            let mut stream = Compressed::new(Queue::new(), conf);
+            stream.send(&text).await.unwrap();
+            assert!(stream.stats().ratio() < 0.5);
+        }
+    }
+}
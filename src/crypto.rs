@@ -0,0 +1,468 @@
+//! Authenticated encryption with pre-shared keys.
+//!
+//! Requires the `crypto` feature.
+//!
+//! ## Protocol Specification
+//!
+//! ```text
+//! +-----+------+---------+------------+-----+
+//! | KID | SALT | COUNTER | CIPHERTEXT | TAG |
+//! +-----+------+---------+------------+-----+
+//! |  1  |  16  |    8    |  Variable  | 16  |
+//! +-----+------+---------+------------+-----+
+//! ```
+//! KID selects one of the pre-shared keys. SALT is read from the OS random source when
+//! a stream is created, so each direction of each session has its own SALT. Packets are
+//! encrypted with a subkey, which is keyed BLAKE2b of the pre-shared key with SALT as the
+//! salt. COUNTER starts from 0 and increases by 1 for each packet, the nonce is 4 zero
+//! bytes followed by COUNTER, so a nonce is never reused under the same subkey.
+//! The header is authenticated as associated data.
+//!
+//! Replay windows are kept for 8 sessions of the peer, which are told apart by SALT.
+//! A new session replaces the least recently used window, but never the one of the
+//! session which received the last packet, nor the one which accepted the most packets.
+//! Packets of a replaced session, or of a session before this stream was created,
+//! could still be replayed into it. Use the noise layer if that matters.
+//!
+
+use std::io::{Result, Error, ErrorKind};
+use std::collections::{HashMap, VecDeque};
+use std::task::{Context, Poll};
+
+use tokio::io::ReadBuf;
+
+use chacha20poly1305::ChaCha20Poly1305;
+use chacha20poly1305::aead::{AeadInPlace, KeyInit, Nonce, Tag};
+use aes_gcm::Aes256Gcm;
+use blake2::Blake2bMac;
+use blake2::digest::Mac;
+use blake2::digest::consts::U32;
+
+use crate::DatagramStream;
+use crate::replay::ReplayWindow;
+
+const SALT: usize = 16;
+const HEADER: usize = 1 + SALT + 8;
+const TAG: usize = 16;
+const PERSONA: &[u8] = b"udpflow-crypto";
+/// Sessions of the peer whose replay windows are kept.
+const SESSIONS: usize = 8;
+
+/// Replay window of a session of the peer.
+struct Session {
+    salt: [u8; SALT],
+    window: ReplayWindow,
+    accepted: u64,
+}
+
+/// AEAD algorithm, both use 256-bit keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cipher {
+    ChaCha20Poly1305,
+    Aes256Gcm,
+}
+
+/// Decryption statistics.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CryptoStats {
+    /// Packets that are malformed or fail authentication.
+    pub dropped: u64,
+    /// Packets with an unknown key id.
+    pub unknown_key: u64,
+    /// Packets that are replayed or too old.
+    pub replayed: u64,
+}
+
+enum Key {
+    ChaCha(Box<ChaCha20Poly1305>),
+    Aes(Box<Aes256Gcm>),
+}
+
+impl Key {
+    /// Derive the subkey of a session from a pre-shared key.
+    fn derive(cipher: Cipher, psk: &[u8; 32], salt: &[u8; SALT]) -> Self {
+        let mac = Blake2bMac::<U32>::new_with_salt_and_personal(psk, salt, PERSONA).unwrap();
+        let key: [u8; 32] = mac.finalize().into_bytes().into();
+        match cipher {
+            Cipher::ChaCha20Poly1305 => Key::ChaCha(Box::new(ChaCha20Poly1305::new(&key.into()))),
+            Cipher::Aes256Gcm => Key::Aes(Box::new(Aes256Gcm::new(&key.into()))),
+        }
+    }
+
+    #[inline]
+    fn nonce(counter: u64) -> [u8; 12] {
+        let mut nonce = [0u8; 12];
+        nonce[4..].copy_from_slice(&counter.to_be_bytes());
+        nonce
+    }
+
+    fn encrypt(&self, counter: u64, aad: &[u8], buf: &mut [u8]) -> Option<[u8; TAG]> {
+        let nonce = Self::nonce(counter);
+        let nonce = Nonce::<ChaCha20Poly1305>::from_slice(&nonce);
+        let tag = match self {
+            Key::ChaCha(x) => x.encrypt_in_place_detached(nonce, aad, buf),
+            Key::Aes(x) => x.encrypt_in_place_detached(nonce, aad, buf),
+        };
+        tag.ok().map(Into::into)
+    }
+
+    fn decrypt(&self, counter: u64, aad: &[u8], buf: &mut [u8], tag: &[u8]) -> bool {
+        let nonce = Self::nonce(counter);
+        let nonce = Nonce::<ChaCha20Poly1305>::from_slice(&nonce);
+        let tag = Tag::<ChaCha20Poly1305>::from_slice(tag);
+        match self {
+            Key::ChaCha(x) => x.decrypt_in_place_detached(nonce, aad, buf, tag).is_ok(),
+            Key::Aes(x) => x.decrypt_in_place_detached(nonce, aad, buf, tag).is_ok(),
+        }
+    }
+}
+
+/// Encrypt each datagram with a pre-shared key.
+///
+/// Packets which could not be decrypted are silently dropped. Keys are identified
+/// by an 8-bit id, to rotate keys, add the new key on both sides first,
+/// then switch the sending key, then remove the old key.
+pub struct Encrypted<S> {
+    inner: S,
+    cipher: Cipher,
+    keys: HashMap<u8, [u8; 32]>,
+    // send
+    send_id: u8,
+    send_key: Key,
+    salt: [u8; SALT],
+    counter: u64,
+    wbuf: Vec<u8>,
+    wbuf_pending: bool,
+    // recv
+    rbuf: Vec<u8>,
+    recv_key: Option<(u8, [u8; SALT], Key)>,
+    // least recently used first
+    windows: VecDeque<Session>,
+    stats: CryptoStats,
+}
+
+impl<S: DatagramStream> Encrypted<S> {
+    /// Create from a datagram stream, with a key that is used for both directions.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the OS random source is not available.
+    pub fn new(inner: S, cipher: Cipher, id: u8, key: &[u8; 32]) -> Self {
+        let mut salt = [0u8; SALT];
+        getrandom::getrandom(&mut salt).expect("failed to read OS random source");
+        let mut keys = HashMap::new();
+        keys.insert(id, *key);
+        Self {
+            inner,
+            cipher,
+            keys,
+            send_id: id,
+            send_key: Key::derive(cipher, key, &salt),
+            salt,
+            counter: 0,
+            wbuf: Vec::new(),
+            wbuf_pending: false,
+            rbuf: vec![0u8; 0x10000],
+            recv_key: None,
+            windows: VecDeque::new(),
+            stats: CryptoStats::default(),
+        }
+    }
+
+    /// Accept packets encrypted with this key.
+    pub fn add_key(&mut self, id: u8, key: &[u8; 32]) {
+        self.keys.insert(id, *key);
+        if id == self.send_id {
+            self.send_key = Key::derive(self.cipher, key, &self.salt);
+        }
+        if self.recv_key.as_ref().is_some_and(|x| x.0 == id) {
+            self.recv_key = None;
+        }
+    }
+
+    /// Stop accepting packets encrypted with this key, the sending key could not be removed.
+    pub fn remove_key(&mut self, id: u8) -> bool {
+        if id == self.send_id || self.keys.remove(&id).is_none() {
+            return false;
+        }
+        if self.recv_key.as_ref().is_some_and(|x| x.0 == id) {
+            self.recv_key = None;
+        }
+        true
+    }
+
+    /// Encrypt outgoing packets with a previously added key.
+    pub fn set_send_key(&mut self, id: u8) -> Result<()> {
+        let key = match self.keys.get(&id) {
+            Some(x) => x,
+            None => return Err(Error::new(ErrorKind::NotFound, "unknown key id")),
+        };
+        self.send_key = Key::derive(self.cipher, key, &self.salt);
+        self.send_id = id;
+        Ok(())
+    }
+
+    /// Get decryption statistics.
+    #[inline]
+    pub const fn stats(&self) -> CryptoStats { self.stats }
+
+    /// Get inner stream.
+    #[inline]
+    pub const fn get_ref(&self) -> &S { &self.inner }
+
+    /// Get inner stream.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut S { &mut self.inner }
+
+    /// Unwrap inner stream.
+    #[inline]
+    pub fn into_inner(self) -> S { self.inner }
+
+    /// Decrypt a packet in `rbuf` in place, return the range of plaintext.
+    fn decrypt(&mut self, n: usize) -> Option<std::ops::Range<usize>> {
+        if n < HEADER + TAG {
+            self.stats.dropped += 1;
+            return None;
+        }
+        let (header, body) = self.rbuf[..n].split_at_mut(HEADER);
+        let (data, tag) = body.split_at_mut(n - HEADER - TAG);
+        let id = header[0];
+        let salt: [u8; SALT] = header[1..1 + SALT].try_into().unwrap();
+        let counter = u64::from_be_bytes(header[1 + SALT..].try_into().unwrap());
+
+        let psk = match self.keys.get(&id) {
+            Some(x) => x,
+            None => {
+                self.stats.unknown_key += 1;
+                return None;
+            }
+        };
+        let session = self.windows.iter().position(|x| x.salt == salt);
+        if session.is_some_and(|i| !self.windows[i].window.check(counter)) {
+            self.stats.replayed += 1;
+            return None;
+        }
+
+        // a new session or key of the peer
+        let derived = match &self.recv_key {
+            Some((x, y, _)) if *x == id && *y == salt => None,
+            _ => Some(Key::derive(self.cipher, psk, &salt)),
+        };
+        let key = match &derived {
+            Some(x) => x,
+            None => &self.recv_key.as_ref().unwrap().2,
+        };
+        if !key.decrypt(counter, header, data, tag) {
+            self.stats.dropped += 1;
+            return None;
+        }
+        let last = self.recv_key.as_ref().map(|x| x.1);
+        if let Some(key) = derived {
+            self.recv_key = Some((id, salt, key));
+        }
+
+        let mut session = match session {
+            Some(i) => self.windows.remove(i).unwrap(),
+            None => {
+                if self.windows.len() == SESSIONS {
+                    self.evict(last);
+                }
+                Session {
+                    salt,
+                    window: ReplayWindow::new(),
+                    accepted: 0,
+                }
+            }
+        };
+        session.window.update(counter);
+        session.accepted += 1;
+        self.windows.push_back(session);
+        Some(HEADER..n - TAG)
+    }
+
+    /// Drop the least recently used window, except for the session of the last packet
+    /// and the one which accepted the most packets.
+    fn evict(&mut self, last: Option<[u8; SALT]>) {
+        let busiest = (0..self.windows.len()).max_by_key(|&i| self.windows[i].accepted);
+        let victim = (0..self.windows.len())
+            .find(|&i| Some(i) != busiest && Some(self.windows[i].salt) != last);
+        if let Some(i) = victim {
+            self.windows.remove(i);
+        }
+    }
+}
+
+impl<S: DatagramStream> DatagramStream for Encrypted<S> {
+    fn poll_recv(&mut self, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<Result<()>> {
+        loop {
+            let mut rbuf = ReadBuf::new(&mut self.rbuf);
+            ready!(self.inner.poll_recv(cx, &mut rbuf))?;
+            let n = rbuf.filled().len();
+
+            // EOF
+            if n == 0 {
+                return Poll::Ready(Ok(()));
+            }
+
+            // truncate
+            if let Some(range) = self.decrypt(n) {
+                let n = std::cmp::min(range.len(), buf.remaining());
+                buf.put_slice(&self.rbuf[range.start..range.start + n]);
+                return Poll::Ready(Ok(()));
+            }
+        }
+    }
+
+    fn poll_send(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<()>> {
+        if !self.wbuf_pending {
+            self.wbuf.clear();
+            self.wbuf.push(self.send_id);
+            self.wbuf.extend_from_slice(&self.salt);
+            self.wbuf.extend_from_slice(&self.counter.to_be_bytes());
+            self.wbuf.extend_from_slice(buf);
+
+            let (header, data) = self.wbuf.split_at_mut(HEADER);
+            let tag = self
+                .send_key
+                .encrypt(self.counter, header, data)
+                .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "datagram too large"))?;
+            self.wbuf.extend_from_slice(&tag);
+            self.counter += 1;
+            self.wbuf_pending = true;
+        }
+
+        let res = ready!(self.inner.poll_send(cx, &self.wbuf));
+        self.wbuf_pending = false;
+        Poll::Ready(res)
+    }
+
+    #[inline]
+    fn reset_timeout(&mut self) { self.inner.reset_timeout() }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testutil::Queue;
+
+    const KEY1: [u8; 32] = [1u8; 32];
+    const KEY2: [u8; 32] = [2u8; 32];
+
+    #[tokio::test]
+    async fn encrypt() {
+        let mut buf = vec![0u8; 0x10000];
+        for cipher in [Cipher::ChaCha20Poly1305, Cipher::Aes256Gcm] {
+            let mut stream = Encrypted::new(Queue::new(), cipher, 1, &KEY1);
+            for size in [0usize, 1, 1200] {
+                let msg: Vec<u8> = (0..size).map(|x| x as u8).collect();
+                stream.send(&msg).await.unwrap();
+                assert_eq!(stream.get_ref().0[0].len(), HEADER + size + TAG);
+                assert!(size < 16 || stream.get_ref().0[0][HEADER..HEADER + size] != msg[..]);
+
+                // replayed
+                let pkt = stream.get_ref().0[0].clone();
+                stream.get_mut().0.push_back(pkt);
+                let n = stream.recv(&mut buf).await.unwrap();
+                assert_eq!(&buf[..n], &msg);
+                assert_eq!(stream.recv(&mut buf).await.unwrap(), 0);
+            }
+            assert_eq!(stream.stats().replayed, 3);
+
+            // tampered
+            stream.send(b"Ciallo").await.unwrap();
+            stream.get_mut().0[0][HEADER] ^= 1;
+            assert_eq!(stream.recv(&mut buf).await.unwrap(), 0);
+            assert_eq!(stream.stats().dropped, 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn rotate() {
+        let mut buf = vec![0u8; 0x10000];
+        let cipher = Cipher::ChaCha20Poly1305;
+        let mut alice = Encrypted::new(Queue::new(), cipher, 1, &KEY1);
+        let mut bob = Encrypted::new(Queue::new(), cipher, 1, &KEY1);
+
+        alice.add_key(2, &KEY2);
+        bob.add_key(2, &KEY2);
+        alice.set_send_key(2).unwrap();
+        assert!(alice.remove_key(1));
+        assert!(alice.set_send_key(1).is_err());
+
+        alice.send(b"Ciallo").await.unwrap();
+        let pkt = alice.get_mut().0.pop_front().unwrap();
+        bob.get_mut().0.push_back(pkt.clone());
+        let n = bob.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"Ciallo");
+
+        // old key is removed
+        bob.remove_key(2);
+        bob.get_mut().0.push_back(pkt);
+        assert_eq!(bob.recv(&mut buf).await.unwrap(), 0);
+        assert_eq!(bob.stats().unknown_key, 1);
+    }
+
+    #[tokio::test]
+    async fn sessions() {
+        let mut buf = vec![0u8; 0x10000];
+        let cipher = Cipher::Aes256Gcm;
+        let mut alice = Encrypted::new(Queue::new(), cipher, 1, &KEY1);
+        let mut bob = Encrypted::new(Queue::new(), cipher, 1, &KEY1);
+
+        // each direction has its own subkey
+        alice.send(b"Ciallo").await.unwrap();
+        bob.send(b"Ciallo").await.unwrap();
+        let pkt1 = alice.get_mut().0.pop_front().unwrap();
+        let pkt2 = bob.get_mut().0.pop_front().unwrap();
+        assert_eq!(pkt1[1 + SALT..HEADER], pkt2[1 + SALT..HEADER]);
+        assert_ne!(pkt1[HEADER..], pkt2[HEADER..]);
+
+        bob.get_mut().0.push_back(pkt1.clone());
+        let n = bob.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"Ciallo");
+
+        // the peer restarts
+        let mut alice = Encrypted::new(Queue::new(), cipher, 1, &KEY1);
+        alice.send(b"Ciallo").await.unwrap();
+        let pkt3 = alice.get_mut().0.pop_front().unwrap();
+        bob.get_mut().0.push_back(pkt3);
+        let n = bob.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"Ciallo");
+
+        // packets of the previous session are still rejected
+        bob.get_mut().0.push_back(pkt1);
+        assert_eq!(bob.recv(&mut buf).await.unwrap(), 0);
+        assert_eq!(bob.stats().replayed, 1);
+    }
+
+    #[tokio::test]
+    async fn replay_live_session() {
+        let mut buf = vec![0u8; 0x10000];
+        let cipher = Cipher::ChaCha20Poly1305;
+        let mut alice = Encrypted::new(Queue::new(), cipher, 1, &KEY1);
+        let mut bob = Encrypted::new(Queue::new(), cipher, 1, &KEY1);
+
+        alice.send(b"Ciallo").await.unwrap();
+        alice.send(b"Ciallo").await.unwrap();
+        let live = alice.get_ref().0.clone();
+        bob.get_mut().0.extend(alice.get_mut().0.drain(..));
+        for _ in 0..2 {
+            let n = bob.recv(&mut buf).await.unwrap();
+            assert_eq!(&buf[..n], b"Ciallo");
+        }
+
+        // recorded packets of older sessions
+        for _ in 0..SESSIONS {
+            let mut old = Encrypted::new(Queue::new(), cipher, 1, &KEY1);
+            old.send(b"Ciallo").await.unwrap();
+            bob.get_mut().0.extend(old.get_mut().0.drain(..));
+            let n = bob.recv(&mut buf).await.unwrap();
+            assert_eq!(&buf[..n], b"Ciallo");
+        }
+
+        // the live session is not pushed out
+        bob.get_mut().0.extend(live);
+        assert_eq!(bob.recv(&mut buf).await.unwrap(), 0);
+        assert_eq!(bob.stats().replayed, 2);
+    }
+}
@@ -0,0 +1,87 @@
+use std::io::Result;
+use std::future::{Future, poll_fn};
+use std::task::{Context, Poll};
+
+use tokio::io::ReadBuf;
+
+/// Message-oriented stream, which preserves datagram boundaries.
+///
+/// Unlike `AsyncRead` and `AsyncWrite`, each call carries exactly one datagram:
+///
+/// - `poll_recv` fills the buffer with one datagram. If the buffer is too small,
+///   the datagram is truncated and the rest is discarded, just like `recv_from`.
+///   Nothing filled indicates `EOF` (or a read timeout).
+///
+/// - `poll_send` either sends the entire datagram, or nothing at all.
+///
+/// It is implemented for [`UdpStreamLocal`](super::UdpStreamLocal),
+/// [`UdpStreamRemote`](super::UdpStreamRemote), [`UotStream`](super::UotStream)
+/// and the unix datagram streams, so that datagrams can be moved between udp and uot
+/// without caring which is which.
+pub trait DatagramStream {
+    /// Attempt to receive a datagram.
+    fn poll_recv(&mut self, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<Result<()>>;
+
+    /// Attempt to send a datagram.
+    ///
+    /// If `Pending` is returned, the same datagram must be provided on the next call.
+    /// A stream which wraps another one keeps its encoded datagram until it is sent,
+    /// rather than encoding the datagram again, so the inner stream sees the same one.
+    fn poll_send(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<()>>;
+
+    /// Reset read timer, if there is one.
+    ///
+    /// This is used to share the same idle timeout between two streams,
+    /// see [`relay`](super::relay).
+    fn reset_timeout(&mut self) {}
+
+    /// Receive a datagram, return its length.
+    fn recv<'a>(&'a mut self, buf: &'a mut [u8]) -> impl Future<Output = Result<usize>> + 'a
+    where
+        Self: Sized,
+    {
+        poll_fn(move |cx| {
+            let mut buf = ReadBuf::new(buf);
+            ready!(self.poll_recv(cx, &mut buf))?;
+            Poll::Ready(Ok(buf.filled().len()))
+        })
+    }
+
+    /// Send a datagram.
+    fn send<'a>(&'a mut self, buf: &'a [u8]) -> impl Future<Output = Result<()>> + 'a
+    where
+        Self: Sized,
+    {
+        poll_fn(move |cx| self.poll_send(cx, buf))
+    }
+}
+
+impl<T: DatagramStream + ?Sized> DatagramStream for &mut T {
+    #[inline]
+    fn poll_recv(&mut self, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<Result<()>> {
+        (**self).poll_recv(cx, buf)
+    }
+
+    #[inline]
+    fn poll_send(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<()>> {
+        (**self).poll_send(cx, buf)
+    }
+
+    #[inline]
+    fn reset_timeout(&mut self) { (**self).reset_timeout() }
+}
+
+impl<T: DatagramStream + ?Sized> DatagramStream for Box<T> {
+    #[inline]
+    fn poll_recv(&mut self, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<Result<()>> {
+        (**self).poll_recv(cx, buf)
+    }
+
+    #[inline]
+    fn poll_send(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<()>> {
+        (**self).poll_send(cx, buf)
+    }
+
+    #[inline]
+    fn reset_timeout(&mut self) { (**self).reset_timeout() }
+}
@@ -0,0 +1,591 @@
+//! Forward error correction.
+//!
+//! ## Protocol Specification
+//!
+//! ```text
+//! +-------+-------+---+---+----------+
+//! | GROUP | INDEX | K | M |   DATA   |
+//! +-------+-------+---+---+----------+
+//! |   4   |   1   | 1 | 1 | Variable |
+//! +-------+-------+---+---+----------+
+//! ```
+//! GROUP is a 32-bit unsigned integer in big endian byte order.
+//! Every K datagrams form a group, which is followed by M parity packets.
+//! INDEX starts from 0, data packets come first, then parity packets.
+//!
+//! DATA of a data packet is the original datagram. Parity is computed over shards,
+//! a shard is a datagram prefixed with its 16-bit length, padded with zeros to the
+//! longest one in the group. Parity is generated with a systematic Reed-Solomon code
+//! over GF(256), with a Cauchy matrix, so any K packets of a group recover all data.
+//!
+
+use std::io::{Result, Error, ErrorKind};
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+use std::task::{Context, Poll};
+
+use tokio::io::ReadBuf;
+
+use crate::time::Instant;
+use crate::DatagramStream;
+
+const HEADER: usize = 7;
+const MAX_SHARDS: usize = 256;
+
+/// Error correction options.
+#[derive(Debug, Clone, Copy)]
+pub struct FecConfig {
+    /// Number of datagrams in a group, K.
+    pub data_shards: u8,
+    /// Number of parity packets of a group, M.
+    pub parity_shards: u8,
+    /// Incomplete groups are dropped after this period.
+    pub timeout: Duration,
+    /// Max number of incomplete groups, the oldest ones are dropped when exceeded.
+    pub max_groups: usize,
+}
+
+impl Default for FecConfig {
+    fn default() -> Self {
+        Self {
+            data_shards: 8,
+            parity_shards: 2,
+            timeout: Duration::from_secs(1),
+            max_groups: 64,
+        }
+    }
+}
+
+/// Recovery statistics.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FecStats {
+    /// Lost datagrams recovered from parity.
+    pub recovered: u64,
+    /// Lost datagrams that could not be recovered.
+    pub unrecoverable: u64,
+    /// Malformed packets.
+    pub malformed: u64,
+}
+
+struct Group {
+    k: usize,
+    m: usize,
+    shards: Vec<Option<Vec<u8>>>,
+    delivered: Vec<bool>,
+    received: usize,
+    complete: bool,
+    deadline: Instant,
+}
+
+impl Group {
+    /// Number of lost datagrams, if the group is dropped now.
+    fn lost(&self) -> usize {
+        if self.complete {
+            return 0;
+        }
+        // parity is sent after the group is full,
+        // otherwise only count holes before the last datagram
+        let sent = if self.shards[self.k..].iter().any(Option::is_some) {
+            self.k
+        } else {
+            self.delivered.iter().rposition(|x| *x).map_or(0, |x| x + 1)
+        };
+        self.delivered[..sent].iter().filter(|x| !**x).count()
+    }
+}
+
+/// Send parity packets along with datagrams, and recover lost datagrams from them.
+///
+/// Datagrams are sent and delivered immediately, recovered datagrams are delivered
+/// once enough packets of a group arrive, so they may be out of order. Parity of a group
+/// is sent with its last datagram, the trailing datagrams of an incomplete group are
+/// not protected.
+pub struct Fec<S> {
+    inner: S,
+    conf: FecConfig,
+    // send
+    group: u32,
+    shards: Vec<Vec<u8>>,
+    parity: Vec<Vec<u8>>,
+    parity_sent: usize,
+    data_sent: bool,
+    wbuf: Vec<u8>,
+    // recv
+    rbuf: Vec<u8>,
+    groups: HashMap<u32, Group>,
+    recovered: VecDeque<Vec<u8>>,
+    stats: FecStats,
+}
+
+impl<S: DatagramStream> Fec<S> {
+    /// Create from a datagram stream.
+    pub fn new(inner: S, conf: FecConfig) -> Self {
+        assert!(conf.data_shards > 0);
+        assert!(conf.data_shards as usize + conf.parity_shards as usize <= MAX_SHARDS);
+        Self {
+            inner,
+            conf,
+            group: 0,
+            shards: Vec::with_capacity(conf.data_shards as usize),
+            parity: Vec::new(),
+            parity_sent: 0,
+            data_sent: false,
+            wbuf: Vec::new(),
+            rbuf: vec![0u8; 0x10000],
+            groups: HashMap::new(),
+            recovered: VecDeque::new(),
+            stats: FecStats::default(),
+        }
+    }
+
+    /// Get recovery statistics.
+    #[inline]
+    pub const fn stats(&self) -> FecStats { self.stats }
+
+    /// Get inner stream.
+    #[inline]
+    pub const fn get_ref(&self) -> &S { &self.inner }
+
+    /// Get inner stream.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut S { &mut self.inner }
+
+    /// Unwrap inner stream.
+    #[inline]
+    pub fn into_inner(self) -> S { self.inner }
+
+    fn encode(&mut self, index: usize, data: &[u8]) {
+        self.wbuf.clear();
+        self.wbuf.extend_from_slice(&self.group.to_be_bytes());
+        self.wbuf.extend_from_slice(&[index as u8, self.conf.data_shards, self.conf.parity_shards]);
+        self.wbuf.extend_from_slice(data);
+    }
+
+    fn next_group(&mut self) {
+        self.group = self.group.wrapping_add(1);
+        self.shards.clear();
+        self.parity.clear();
+        self.parity_sent = 0;
+    }
+
+    fn drop_group(&mut self, id: u32) {
+        if let Some(group) = self.groups.remove(&id) {
+            self.stats.unrecoverable += group.lost() as u64;
+        }
+    }
+
+    /// Store a packet, return the datagram if it is not yet delivered.
+    fn on_packet(
+        &mut self,
+        id: u32,
+        index: usize,
+        k: usize,
+        m: usize,
+        data: &[u8],
+    ) -> Option<Vec<u8>> {
+        let now = Instant::now();
+
+        // drop expired groups
+        let expired: Vec<u32> =
+            self.groups.iter().filter(|(_, x)| x.deadline <= now).map(|(id, _)| *id).collect();
+        for id in expired {
+            self.drop_group(id);
+        }
+
+        // drop the oldest groups
+        if !self.groups.contains_key(&id) {
+            while self.groups.len() >= self.conf.max_groups {
+                let oldest = match self.groups.iter().min_by_key(|(_, x)| x.deadline) {
+                    Some((id, _)) => *id,
+                    None => break,
+                };
+                self.drop_group(oldest);
+            }
+        }
+
+        let timeout = self.conf.timeout;
+        let group = self.groups.entry(id).or_insert_with(|| Group {
+            k,
+            m,
+            shards: vec![None; k + m],
+            delivered: vec![false; k],
+            received: 0,
+            complete: false,
+            deadline: now + timeout,
+        });
+
+        if group.k != k || group.m != m || (index >= k && data.len() < 2) {
+            self.stats.malformed += 1;
+            return None;
+        }
+
+        // duplicated
+        if group.complete || group.shards[index].is_some() {
+            return None;
+        }
+
+        let mut ret = None;
+        if index < k {
+            group.delivered[index] = true;
+            let mut shard = Vec::with_capacity(2 + data.len());
+            shard.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            shard.extend_from_slice(data);
+            group.shards[index] = Some(shard);
+            ret = Some(data.to_vec());
+        } else {
+            group.shards[index] = Some(data.to_vec());
+        }
+        group.received += 1;
+
+        if group.delivered.iter().all(|x| *x) {
+            group.complete = true;
+        } else if group.received >= k {
+            let (recovered, malformed) = recover(group);
+            self.stats.recovered += recovered.len() as u64;
+            self.stats.malformed += malformed;
+            self.recovered.extend(recovered);
+            group.complete = true;
+        }
+
+        // only keep the id to filter late packets
+        if group.complete {
+            group.shards = Vec::new();
+        }
+        ret
+    }
+}
+
+/// Reconstruct lost datagrams of a group, with at least K shards.
+fn recover(group: &mut Group) -> (Vec<Vec<u8>>, u64) {
+    let k = group.k;
+    let size = match group.shards[k..].iter().flatten().next() {
+        Some(x) => x.len(),
+        None => return (Vec::new(), 0),
+    };
+
+    // pick K shards, prefer data
+    let rows: Vec<usize> =
+        (0..k + group.m).filter(|&i| group.shards[i].is_some()).take(k).collect();
+    let matrix: Vec<Vec<u8>> = rows.iter().map(|&r| gf::row(k, r)).collect();
+    let inverse = match gf::invert(matrix) {
+        Some(x) => x,
+        None => return (Vec::new(), 0),
+    };
+
+    let mut recovered = Vec::new();
+    let mut malformed = 0;
+    let missing: Vec<usize> = (0..k).filter(|&j| !group.delivered[j]).collect();
+    for j in missing {
+        let mut shard = vec![0u8; size];
+        for (i, &r) in rows.iter().enumerate() {
+            let src = group.shards[r].as_ref().unwrap();
+            let n = std::cmp::min(src.len(), size);
+            gf::mul_add(&mut shard[..n], inverse[j][i], &src[..n]);
+        }
+        group.delivered[j] = true;
+        let len = u16::from_be_bytes([shard[0], shard[1]]) as usize;
+        if 2 + len > size {
+            malformed += 1;
+            continue;
+        }
+        shard.truncate(2 + len);
+        shard.drain(..2);
+        recovered.push(shard);
+    }
+    (recovered, malformed)
+}
+
+impl<S: DatagramStream> DatagramStream for Fec<S> {
+    fn poll_recv(&mut self, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<Result<()>> {
+        loop {
+            // truncate
+            if let Some(pkt) = self.recovered.pop_front() {
+                let n = std::cmp::min(pkt.len(), buf.remaining());
+                buf.put_slice(&pkt[..n]);
+                return Poll::Ready(Ok(()));
+            }
+
+            let mut rbuf = ReadBuf::new(&mut self.rbuf);
+            ready!(self.inner.poll_recv(cx, &mut rbuf))?;
+            let n = rbuf.filled().len();
+
+            // EOF
+            if n == 0 {
+                return Poll::Ready(Ok(()));
+            }
+
+            let pkt = &self.rbuf[..n];
+            if pkt.len() < HEADER {
+                self.stats.malformed += 1;
+                continue;
+            }
+
+            let id = u32::from_be_bytes(pkt[..4].try_into().unwrap());
+            let index = pkt[4] as usize;
+            let k = pkt[5] as usize;
+            let m = pkt[6] as usize;
+            if k == 0 || k + m > MAX_SHARDS || index >= k + m {
+                self.stats.malformed += 1;
+                continue;
+            }
+
+            let data = pkt[HEADER..].to_vec();
+            if let Some(pkt) = self.on_packet(id, index, k, m, &data) {
+                let n = std::cmp::min(pkt.len(), buf.remaining());
+                buf.put_slice(&pkt[..n]);
+                return Poll::Ready(Ok(()));
+            }
+        }
+    }
+
+    fn poll_send(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<()>> {
+        if buf.len() > u16::MAX as usize {
+            return Poll::Ready(Err(Error::new(ErrorKind::InvalidInput, "datagram too large")));
+        }
+
+        let k = self.conf.data_shards as usize;
+        if !self.data_sent {
+            self.encode(self.shards.len(), buf);
+            ready!(self.inner.poll_send(cx, &self.wbuf))?;
+            self.data_sent = true;
+
+            let mut shard = Vec::with_capacity(2 + buf.len());
+            shard.extend_from_slice(&(buf.len() as u16).to_be_bytes());
+            shard.extend_from_slice(buf);
+            self.shards.push(shard);
+            if self.shards.len() == k {
+                self.parity = parity(&self.shards, self.conf.parity_shards as usize);
+            }
+        }
+
+        while self.parity_sent < self.parity.len() {
+            let parity = std::mem::take(&mut self.parity[self.parity_sent]);
+            self.encode(k + self.parity_sent, &parity);
+            self.parity[self.parity_sent] = parity;
+            match ready!(self.inner.poll_send(cx, &self.wbuf)) {
+                Ok(()) => self.parity_sent += 1,
+                Err(e) => {
+                    self.data_sent = false;
+                    self.next_group();
+                    return Poll::Ready(Err(e));
+                }
+            }
+        }
+
+        self.data_sent = false;
+        if self.shards.len() == k {
+            self.next_group();
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    #[inline]
+    fn reset_timeout(&mut self) { self.inner.reset_timeout() }
+}
+
+/// Compute M parity shards.
+fn parity(shards: &[Vec<u8>], m: usize) -> Vec<Vec<u8>> {
+    let size = shards.iter().map(Vec::len).max().unwrap_or(0);
+    (0..m)
+        .map(|i| {
+            let mut parity = vec![0u8; size];
+            for (j, shard) in shards.iter().enumerate() {
+                gf::mul_add(&mut parity[..shard.len()], gf::cauchy(shards.len(), i, j), shard);
+            }
+            parity
+        })
+        .collect()
+}
+
+/// Arithmetic over GF(256), with the polynomial 0x11d.
+mod gf {
+    const fn tables() -> ([u8; 512], [u8; 256]) {
+        let mut exp = [0u8; 512];
+        let mut log = [0u8; 256];
+        let mut x: u16 = 1;
+        let mut i = 0;
+        while i < 255 {
+            exp[i] = x as u8;
+            log[x as usize] = i as u8;
+            x <<= 1;
+            if x & 0x100 != 0 {
+                x ^= 0x11d;
+            }
+            i += 1;
+        }
+        while i < 512 {
+            exp[i] = exp[i - 255];
+            i += 1;
+        }
+        (exp, log)
+    }
+
+    static EXP: [u8; 512] = tables().0;
+    static LOG: [u8; 256] = tables().1;
+
+    #[inline]
+    pub fn mul(a: u8, b: u8) -> u8 {
+        if a == 0 || b == 0 {
+            return 0;
+        }
+        EXP[LOG[a as usize] as usize + LOG[b as usize] as usize]
+    }
+
+    #[inline]
+    pub fn inv(a: u8) -> u8 {
+        debug_assert!(a != 0);
+        EXP[255 - LOG[a as usize] as usize]
+    }
+
+    /// `dst += c * src`
+    pub fn mul_add(dst: &mut [u8], c: u8, src: &[u8]) {
+        if c == 0 {
+            return;
+        }
+        for (d, s) in dst.iter_mut().zip(src) {
+            *d ^= mul(c, *s);
+        }
+    }
+
+    /// Coefficient of parity `i` and data `j`, `1 / (x_i + y_j)`, with
+    /// `x_i = k + i` and `y_j = j`, any square submatrix is invertible.
+    #[inline]
+    pub fn cauchy(k: usize, i: usize, j: usize) -> u8 { inv((k + i) as u8 ^ j as u8) }
+
+    /// Row `r` of the encoding matrix, identity rows followed by cauchy rows.
+    pub fn row(k: usize, r: usize) -> Vec<u8> {
+        (0..k).map(|c| if r < k { (r == c) as u8 } else { cauchy(k, r - k, c) }).collect()
+    }
+
+    /// Gauss-Jordan elimination.
+    pub fn invert(mut matrix: Vec<Vec<u8>>) -> Option<Vec<Vec<u8>>> {
+        let n = matrix.len();
+        let mut inverse: Vec<Vec<u8>> =
+            (0..n).map(|r| (0..n).map(|c| (r == c) as u8).collect()).collect();
+
+        for col in 0..n {
+            let pivot = (col..n).find(|&r| matrix[r][col] != 0)?;
+            matrix.swap(col, pivot);
+            inverse.swap(col, pivot);
+
+            let scale = inv(matrix[col][col]);
+            for c in 0..n {
+                matrix[col][c] = mul(matrix[col][c], scale);
+                inverse[col][c] = mul(inverse[col][c], scale);
+            }
+
+            for r in (0..n).filter(|&r| r != col) {
+                let factor = matrix[r][col];
+                if factor == 0 {
+                    continue;
+                }
+                for c in 0..n {
+                    matrix[r][c] ^= mul(factor, matrix[col][c]);
+                    inverse[r][c] ^= mul(factor, inverse[col][c]);
+                }
+            }
+        }
+        Some(inverse)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testutil::Queue;
+
+    fn fec(k: u8, m: u8) -> Fec<Queue> {
+        let conf = FecConfig {
+            data_shards: k,
+            parity_shards: m,
+            ..Default::default()
+        };
+        Fec::new(Queue::new(), conf)
+    }
+
+    async fn recv_all(stream: &mut Fec<Queue>) -> Vec<Vec<u8>> {
+        let mut buf = vec![0u8; 0x10000];
+        let mut msgs = Vec::new();
+        loop {
+            let n = stream.recv(&mut buf).await.unwrap();
+            if n == 0 {
+                break;
+            }
+            msgs.push(buf[..n].to_vec());
+        }
+        msgs.sort();
+        msgs
+    }
+
+    #[tokio::test]
+    async fn recover() {
+        let mut stream = fec(4, 2);
+        let msgs: Vec<Vec<u8>> = (1..=4).map(|x| vec![x as u8; x * 100]).collect();
+        for msg in msgs.iter() {
+            stream.send(msg).await.unwrap();
+        }
+        assert_eq!(stream.get_ref().0.len(), 6);
+
+        // lose 2 datagrams
+        stream.get_mut().0.remove(2);
+        stream.get_mut().0.remove(0);
+        assert_eq!(recv_all(&mut stream).await, msgs);
+        assert_eq!(stream.stats().recovered, 2);
+
+        // lose 1 datagram and 1 parity
+        for msg in msgs.iter() {
+            stream.send(msg).await.unwrap();
+        }
+        stream.get_mut().0.remove(5);
+        stream.get_mut().0.remove(3);
+        assert_eq!(recv_all(&mut stream).await, msgs);
+        assert_eq!(stream.stats().recovered, 3);
+        assert_eq!(stream.stats().unrecoverable, 0);
+    }
+
+    #[tokio::test]
+    async fn malformed() {
+        let mut stream = fec(4, 2);
+        let mut buf = vec![0u8; 0x10000];
+
+        // k + m exceeds 256
+        for index in [0u8, 250] {
+            let mut pkt = vec![0, 0, 0, 1, index, 200, 100];
+            pkt.extend_from_slice(&[0u8; 8]);
+            stream.get_mut().0.push_back(pkt);
+        }
+        assert_eq!(stream.recv(&mut buf).await.unwrap(), 0);
+        assert_eq!(stream.stats().malformed, 2);
+    }
+
+    #[tokio::test]
+    async fn unrecoverable() {
+        let mut stream = fec(4, 1);
+        let msgs: Vec<Vec<u8>> = (1..=4).map(|x| vec![x as u8; x]).collect();
+        for msg in msgs.iter() {
+            stream.send(msg).await.unwrap();
+        }
+
+        // lose 2 datagrams
+        stream.get_mut().0.remove(3);
+        stream.get_mut().0.remove(1);
+        assert_eq!(recv_all(&mut stream).await.len(), 2);
+        assert_eq!(stream.stats().recovered, 0);
+
+        // evicted by later groups
+        for _ in 0..FecConfig::default().max_groups * 4 {
+            stream.send(b"Ciallo").await.unwrap();
+        }
+        recv_all(&mut stream).await;
+        assert_eq!(stream.stats().unrecoverable, 2);
+    }
+
+    #[test]
+    fn invert() {
+        for (k, m) in [(1, 1), (4, 2), (10, 4), (200, 56)] {
+            // pick the last K rows of [I; C]
+            let matrix: Vec<Vec<u8>> = (m..k + m).map(|r| gf::row(k, r)).collect();
+            assert!(gf::invert(matrix).is_some());
+        }
+    }
+}
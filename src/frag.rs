@@ -0,0 +1,342 @@
+//! Application-level fragmentation.
+//!
+//! ## Protocol Specification
+//!
+//! ```text
+//! +------+-------+-------+----------+
+//! |  ID  | INDEX | COUNT |   DATA   |
+//! +------+-------+-------+----------+
+//! |  4   |   1   |   1   | Variable |
+//! +------+-------+-------+----------+
+//! ```
+//! ID is a 32-bit unsigned integer in big endian byte order, which identifies a message.
+//! A message is split into COUNT fragments, INDEX starts from 0.
+//!
+
+use std::io::{Result, Error, ErrorKind};
+use std::collections::HashMap;
+use std::time::Duration;
+use std::task::{Context, Poll};
+
+use tokio::io::ReadBuf;
+
+use crate::time::Instant;
+use crate::DatagramStream;
+
+const HEADER: usize = 6;
+const MAX_FRAGMENTS: usize = u8::MAX as usize;
+// fragments of a peer with a larger mtu
+const MAX_FRAGMENT_SIZE: usize = 0x10000;
+
+/// Fragmentation options.
+#[derive(Debug, Clone, Copy)]
+pub struct FragConfig {
+    /// Max size of a sent fragment, including the header.
+    /// Received fragments may be larger, up to 64 KiB.
+    pub mtu: usize,
+    /// Incomplete messages are dropped after this period.
+    pub timeout: Duration,
+    /// Max bytes of incomplete messages, the oldest ones are dropped when exceeded.
+    pub max_pending: usize,
+}
+
+impl Default for FragConfig {
+    fn default() -> Self {
+        Self {
+            mtu: 1200,
+            timeout: Duration::from_secs(5),
+            max_pending: 0x100000,
+        }
+    }
+}
+
+/// Reassembly statistics.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FragStats {
+    /// Messages reassembled from more than one fragment.
+    pub reassembled: u64,
+    /// Incomplete messages dropped after timeout.
+    pub expired: u64,
+    /// Incomplete messages dropped due to the memory limit.
+    pub evicted: u64,
+    /// Malformed fragments.
+    pub malformed: u64,
+}
+
+struct Partial {
+    frags: Vec<Option<Vec<u8>>>,
+    received: usize,
+    size: usize,
+    deadline: Instant,
+}
+
+/// Split large messages into fragments under the MTU, and reassemble them on the other side.
+///
+/// A message up to `255 * (mtu - 6)` bytes could be sent, `poll_send` fails with
+/// an `InvalidInput` error if it is larger.
+pub struct Fragmented<S> {
+    inner: S,
+    conf: FragConfig,
+    // send
+    id: u32,
+    index: usize,
+    wbuf: Vec<u8>,
+    // recv
+    rbuf: Vec<u8>,
+    pending: HashMap<u32, Partial>,
+    pending_size: usize,
+    stats: FragStats,
+}
+
+impl<S: DatagramStream> Fragmented<S> {
+    /// Create from a datagram stream.
+    pub fn new(inner: S, conf: FragConfig) -> Self {
+        assert!(conf.mtu > HEADER);
+        Self {
+            inner,
+            conf,
+            id: 0,
+            index: 0,
+            wbuf: Vec::with_capacity(conf.mtu),
+            rbuf: vec![0u8; MAX_FRAGMENT_SIZE],
+            pending: HashMap::new(),
+            pending_size: 0,
+            stats: FragStats::default(),
+        }
+    }
+
+    /// Max size of a message.
+    #[inline]
+    pub const fn max_message_size(&self) -> usize { MAX_FRAGMENTS * (self.conf.mtu - HEADER) }
+
+    /// Get reassembly statistics.
+    #[inline]
+    pub const fn stats(&self) -> FragStats { self.stats }
+
+    /// Get inner stream.
+    #[inline]
+    pub const fn get_ref(&self) -> &S { &self.inner }
+
+    /// Get inner stream.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut S { &mut self.inner }
+
+    /// Unwrap inner stream.
+    #[inline]
+    pub fn into_inner(self) -> S { self.inner }
+
+    fn drop_partial(&mut self, id: u32) {
+        if let Some(partial) = self.pending.remove(&id) {
+            self.pending_size -= partial.size;
+        }
+    }
+
+    /// Store a fragment, return the message once it is complete.
+    fn reassemble(&mut self, id: u32, index: usize, count: usize, data: Vec<u8>) -> Option<Vec<u8>> {
+        let now = Instant::now();
+
+        // drop expired messages
+        let expired: Vec<u32> =
+            self.pending.iter().filter(|(_, x)| x.deadline <= now).map(|(id, _)| *id).collect();
+        for id in expired {
+            self.drop_partial(id);
+            self.stats.expired += 1;
+        }
+
+        // drop the oldest messages
+        while self.pending_size + data.len() > self.conf.max_pending {
+            let oldest = match self.pending.iter().min_by_key(|(_, x)| x.deadline) {
+                Some((id, _)) => *id,
+                None => return None,
+            };
+            self.drop_partial(oldest);
+            self.stats.evicted += 1;
+        }
+
+        let timeout = self.conf.timeout;
+        let partial = self.pending.entry(id).or_insert_with(|| Partial {
+            frags: vec![None; count],
+            received: 0,
+            size: 0,
+            deadline: now + timeout,
+        });
+
+        if partial.frags.len() != count {
+            self.stats.malformed += 1;
+            return None;
+        }
+
+        // duplicated
+        if partial.frags[index].is_some() {
+            return None;
+        }
+
+        partial.received += 1;
+        partial.size += data.len();
+        self.pending_size += data.len();
+        partial.frags[index] = Some(data);
+
+        if partial.received < count {
+            return None;
+        }
+
+        let partial = self.pending.remove(&id).unwrap();
+        self.pending_size -= partial.size;
+        self.stats.reassembled += 1;
+        Some(partial.frags.into_iter().flatten().flatten().collect())
+    }
+}
+
+impl<S: DatagramStream> DatagramStream for Fragmented<S> {
+    fn poll_recv(&mut self, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<Result<()>> {
+        loop {
+            let mut rbuf = ReadBuf::new(&mut self.rbuf);
+            ready!(self.inner.poll_recv(cx, &mut rbuf))?;
+            let pkt = rbuf.filled();
+
+            // EOF
+            if pkt.is_empty() {
+                return Poll::Ready(Ok(()));
+            }
+
+            if pkt.len() < HEADER {
+                self.stats.malformed += 1;
+                continue;
+            }
+
+            let id = u32::from_be_bytes(pkt[..4].try_into().unwrap());
+            let index = pkt[4] as usize;
+            let count = pkt[5] as usize;
+            if index >= count {
+                self.stats.malformed += 1;
+                continue;
+            }
+
+            // truncate
+            if count == 1 {
+                let n = std::cmp::min(pkt.len() - HEADER, buf.remaining());
+                buf.put_slice(&pkt[HEADER..HEADER + n]);
+                return Poll::Ready(Ok(()));
+            }
+
+            let data = pkt[HEADER..].to_vec();
+            if let Some(msg) = self.reassemble(id, index, count, data) {
+                let n = std::cmp::min(msg.len(), buf.remaining());
+                buf.put_slice(&msg[..n]);
+                return Poll::Ready(Ok(()));
+            }
+        }
+    }
+
+    fn poll_send(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<()>> {
+        if buf.len() > self.max_message_size() {
+            return Poll::Ready(Err(Error::new(ErrorKind::InvalidInput, "message too large")));
+        }
+
+        let size = self.conf.mtu - HEADER;
+        let count = std::cmp::max(1, buf.len().div_ceil(size));
+
+        while self.index < count {
+            let index = self.index;
+            let data = &buf[index * size..std::cmp::min(buf.len(), (index + 1) * size)];
+
+            self.wbuf.clear();
+            self.wbuf.extend_from_slice(&self.id.to_be_bytes());
+            self.wbuf.extend_from_slice(&[index as u8, count as u8]);
+            self.wbuf.extend_from_slice(data);
+
+            if let Err(e) = ready!(self.inner.poll_send(cx, &self.wbuf)) {
+                self.index = 0;
+                self.id = self.id.wrapping_add(1);
+                return Poll::Ready(Err(e));
+            }
+            self.index += 1;
+        }
+
+        self.index = 0;
+        self.id = self.id.wrapping_add(1);
+        Poll::Ready(Ok(()))
+    }
+
+    #[inline]
+    fn reset_timeout(&mut self) { self.inner.reset_timeout() }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testutil::Queue;
+
+    fn fragmented(conf: FragConfig) -> Fragmented<Queue> {
+        Fragmented::new(Queue::new(), conf)
+    }
+
+    #[tokio::test]
+    async fn reassemble() {
+        let mut stream = fragmented(FragConfig::default());
+        let mut buf = vec![0u8; 0x10000];
+        for size in [0usize, 1, 1193, 1194, 1195, 0x8000, 0xffff] {
+            let msg: Vec<u8> = (0..size).map(|x| x as u8).collect();
+            stream.send(&msg).await.unwrap();
+            assert_eq!(stream.get_ref().0.len(), std::cmp::max(1, size.div_ceil(1194)));
+            for pkt in stream.get_ref().0.iter() {
+                assert!(pkt.len() <= 1200);
+            }
+
+            // out of order
+            stream.get_mut().0.make_contiguous().reverse();
+            let n = stream.recv(&mut buf).await.unwrap();
+            assert_eq!(&buf[..n], &msg);
+        }
+        assert_eq!(stream.stats().reassembled, 3);
+
+        let oversized = vec![0u8; stream.max_message_size() + 1];
+        let err = stream.send(&oversized).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[tokio::test]
+    async fn larger_peer_mtu() {
+        let mut sender = fragmented(FragConfig::default());
+        let mut receiver = fragmented(FragConfig {
+            mtu: 106,
+            ..Default::default()
+        });
+        let mut buf = vec![0u8; 0x10000];
+
+        let msg: Vec<u8> = (0..3000).map(|x| x as u8).collect();
+        sender.send(&msg).await.unwrap();
+        receiver.get_mut().0.extend(sender.get_mut().0.drain(..));
+        let n = receiver.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], &msg);
+    }
+
+    #[tokio::test]
+    async fn drop_incomplete() {
+        let mut stream = fragmented(FragConfig {
+            mtu: 106,
+            timeout: Duration::from_millis(10),
+            max_pending: 1000,
+        });
+        let mut buf = vec![0u8; 0x10000];
+
+        // first fragment is lost
+        stream.send(&[1u8; 300]).await.unwrap();
+        stream.get_mut().0.pop_front();
+        assert_eq!(stream.recv(&mut buf).await.unwrap(), 0);
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        stream.send(&[2u8; 300]).await.unwrap();
+        let n = stream.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], &[2u8; 300]);
+        assert_eq!(stream.stats().expired, 1);
+
+        // exceeds memory limit
+        stream.send(&[3u8; 1000]).await.unwrap();
+        stream.get_mut().0.pop_back();
+        stream.send(&[4u8; 300]).await.unwrap();
+        let n = stream.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], &[4u8; 300]);
+        assert_eq!(stream.stats().evicted, 1);
+    }
+}
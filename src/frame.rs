@@ -9,64 +9,490 @@
 //! |  2   | Variable |
 //! +------+----------+
 //! ```
-//! LEN is a 16-bit unsigned integer in big endian byte order.
+//! By default, LEN is a 16-bit unsigned integer in big endian byte order.
+//! It could also be a 32-bit unsigned integer, in either byte order,
+//! or a QUIC variable-length integer, see [`LengthPrefix`].
 //!
 
-use std::io::Result;
+use std::io::{Result, Error, ErrorKind};
 use std::io::IoSlice;
+use std::future::poll_fn;
+use std::collections::VecDeque;
 use std::pin::Pin;
 use std::task::{Context, Poll};
 use tokio::io::BufReader;
 
-use tokio::io::{ReadBuf, AsyncRead, AsyncWrite};
+use tokio::io::{ReadBuf, AsyncRead, AsyncBufRead, AsyncWrite};
+
+use crate::DatagramStream;
 
 const MAX_DATAGRAM_PAYLOAD: usize = 65507;
-macro_rules! ready {
-    ($e:expr $(,)?) => {
-        match $e {
-            Poll::Ready(t) => t,
-            Poll::Pending => return Poll::Pending,
+
+const MAX_VARINT: u64 = (1 << 62) - 1;
+
+/// Byte order of a fixed-size `LEN`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ByteOrder {
+    #[default]
+    Big,
+    Little,
+}
+
+/// Encoding of `LEN`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LengthPrefix {
+    /// 16-bit unsigned integer.
+    U16(ByteOrder),
+    /// 32-bit unsigned integer.
+    U32(ByteOrder),
+    /// QUIC variable-length integer (RFC 9000, section 16), 1, 2, 4 or 8 bytes.
+    Varint,
+}
+
+impl Default for LengthPrefix {
+    fn default() -> Self { LengthPrefix::U16(ByteOrder::Big) }
+}
+
+impl LengthPrefix {
+    /// The largest length it could carry.
+    pub const fn max_len(&self) -> u64 {
+        match self {
+            LengthPrefix::U16(_) => u16::MAX as u64,
+            LengthPrefix::U32(_) => u32::MAX as u64,
+            LengthPrefix::Varint => MAX_VARINT,
+        }
+    }
+
+    /// Bytes to read before the size of `LEN` is known.
+    pub(crate) const fn min_size(&self) -> usize {
+        match self {
+            LengthPrefix::U16(_) => 2,
+            LengthPrefix::U32(_) => 4,
+            LengthPrefix::Varint => 1,
+        }
+    }
+
+    /// Size of `LEN`, given its first byte.
+    pub(crate) const fn size(&self, first: u8) -> usize {
+        match self {
+            LengthPrefix::Varint => 1 << (first >> 6),
+            _ => self.min_size(),
         }
-    };
+    }
+
+    /// Encode `len` into `out`, return the size of `LEN`.
+    pub(crate) fn encode(&self, len: usize, out: &mut [u8; 8]) -> usize {
+        match self {
+            LengthPrefix::U16(order) => out[..2].copy_from_slice(&match order {
+                ByteOrder::Big => (len as u16).to_be_bytes(),
+                ByteOrder::Little => (len as u16).to_le_bytes(),
+            }),
+            LengthPrefix::U32(order) => out[..4].copy_from_slice(&match order {
+                ByteOrder::Big => (len as u32).to_be_bytes(),
+                ByteOrder::Little => (len as u32).to_le_bytes(),
+            }),
+            LengthPrefix::Varint => {
+                let (size, tag) = match len {
+                    0..=0x3f => (1, 0u64),
+                    0x40..=0x3fff => (2, 1),
+                    0x4000..=0x3fff_ffff => (4, 2),
+                    _ => (8, 3),
+                };
+                let x = (len as u64) | (tag << (size * 8 - 2));
+                out[..size].copy_from_slice(&x.to_be_bytes()[8 - size..]);
+                return size;
+            }
+        }
+        self.min_size()
+    }
+
+    /// Decode a complete `LEN`.
+    pub(crate) fn decode(&self, buf: &[u8]) -> u64 {
+        match self {
+            LengthPrefix::U16(ByteOrder::Big) => u16::from_be_bytes([buf[0], buf[1]]) as u64,
+            LengthPrefix::U16(ByteOrder::Little) => u16::from_le_bytes([buf[0], buf[1]]) as u64,
+            LengthPrefix::U32(ByteOrder::Big) => {
+                u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]) as u64
+            }
+            LengthPrefix::U32(ByteOrder::Little) => {
+                u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]) as u64
+            }
+            LengthPrefix::Varint => {
+                let mut x = (buf[0] & 0x3f) as u64;
+                for b in &buf[1..] {
+                    x = (x << 8) | *b as u64;
+                }
+                x
+            }
+        }
+    }
+}
+
+/// What to do with a received frame whose `LEN` exceeds `max_frame` of [`FrameConfig`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Oversized {
+    /// Fail with `InvalidData`, then the stream is at EOF.
+    #[default]
+    Reject,
+    /// Discard the frame, and continue with the next one.
+    Skip,
+}
+
+/// What a `Read` call does when the buffer is smaller than the frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SmallBuffer {
+    /// Deliver the remainder in subsequent `Read` calls,
+    /// [`UotStream::frame_remaining`] tells whether a frame is continued.
+    ///
+    /// A frame which fits in the buffer is never split.
+    #[default]
+    Continue,
+    /// Fill the buffer and discard the rest of the frame.
+    Truncate,
+    /// Fail with `InvalidInput`, the frame is kept for the next `Read` call.
+    Error,
+}
+
+/// Framing options of [`UotStream`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameConfig {
+    /// Encoding of `LEN`.
+    pub prefix: LengthPrefix,
+    /// Maximum size of `DATA`, clamped to what `prefix` could carry.
+    ///
+    /// Sending a larger frame fails with `InvalidInput`, and nothing is sent.
+    pub max_frame: usize,
+    /// Handling of received frames larger than `max_frame`.
+    pub oversized: Oversized,
+    /// Handling of `Read` calls with a buffer smaller than the frame.
+    ///
+    /// Except for [`SmallBuffer::Continue`], a `Read` call returns exactly one frame.
+    pub small_buffer: SmallBuffer,
+}
+
+impl Default for FrameConfig {
+    fn default() -> Self {
+        Self {
+            prefix: LengthPrefix::default(),
+            max_frame: MAX_DATAGRAM_PAYLOAD,
+            oversized: Oversized::Reject,
+            small_buffer: SmallBuffer::Continue,
+        }
+    }
+}
+
+impl FrameConfig {
+    #[inline]
+    pub(crate) fn max_frame(&self) -> usize {
+        std::cmp::min(self.max_frame as u64, self.prefix.max_len()) as usize
+    }
 }
 
 #[derive(Debug)]
 enum State {
-    Len,
-    Data(u16),
+    Len(u8),
+    Data(usize),
+    Skip(u64),
+    /// A complete frame is held in `frame`.
+    Full,
     Fin,
 }
 
 impl State {
     #[inline]
-    pub const fn new() -> Self { State::Len }
+    pub const fn new() -> Self { State::Len(0) }
 }
 
 /// Framed UoT stream.
 ///
 /// This is a simple wrapper over the underlying IO source.
 ///
-/// A `Read` call always waits for a complete frame, and then decapsulate it.
+/// A `Read` call returns data of a single frame, see [`SmallBuffer`] for what happens
+/// if the buffer is smaller than the frame. [`read_frame`](Self::read_frame) returns
+/// a complete frame as an owned buffer.
 ///
 /// A `Write` call will encapsulate the buffer in a frame. It only ensures `LEN` is sent,
 /// the left `DATA` may be partially sent, which requires subsequent `Write` calls
 /// to finish sending the entire frame. Usually this could be achieved by `write_all`.
+///
+/// It also implements [`DatagramStream`], where each frame is received
+/// or sent as a whole. Do not mix it with `Read` or `Write` calls.
+///
+/// [`send_frame`](Self::send_frame) queues owned frames, which is cancel safe.
 pub struct UotStream<T> {
     rd: State,
     wr: State,
+    len: [u8; 8],
+    frame: Vec<u8>,
+    wqueue: VecDeque<Vec<u8>>,
+    wcursor: usize,
+    conf: FrameConfig,
     buf: BufReader<T>,
 }
 
 impl<T: AsyncRead + AsyncWrite + Unpin> UotStream<T> {
+    /// Create from underlying IO source, with the default [`FrameConfig`].
+    #[inline]
+    pub fn new(io: T) -> Self { Self::with_config(io, FrameConfig::default()) }
+
     /// Create from underlying IO source.
     #[inline]
-    pub fn new(io: T) -> Self {
+    pub fn with_config(io: T, conf: FrameConfig) -> Self {
         Self {
             rd: State::new(),
-            wr: State::Data(0),
+            wr: State::new(),
+            len: [0u8; 8],
+            frame: Vec::new(),
+            wqueue: VecDeque::new(),
+            wcursor: 0,
+            conf,
             buf: BufReader::new(io),
         }
     }
+
+    /// Get framing options.
+    #[inline]
+    pub const fn config(&self) -> &FrameConfig { &self.conf }
+
+    /// Read `LEN` of the next frame, oversized frames are skipped or rejected.
+    fn poll_read_len(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        loop {
+            let cursor = match self.rd {
+                State::Len(cursor) => cursor as usize,
+                State::Skip(0) => {
+                    self.rd = State::Len(0);
+                    continue;
+                }
+                State::Skip(left) => {
+                    let n = ready!(Pin::new(&mut self.buf).poll_fill_buf(cx))?.len();
+                    if n == 0 {
+                        self.rd = State::Fin;
+                        break;
+                    }
+                    let n = std::cmp::min(n as u64, left);
+                    Pin::new(&mut self.buf).consume(n as usize);
+                    self.rd = State::Skip(left - n);
+                    continue;
+                }
+                _ => break,
+            };
+            let prefix = self.conf.prefix;
+            let size = match cursor {
+                0 => prefix.min_size(),
+                _ => prefix.size(self.len[0]),
+            };
+            let mut read_buf = ReadBuf::new(&mut self.len[cursor..size]);
+            ready!(Pin::new(&mut self.buf).poll_read(cx, &mut read_buf))?;
+            let n = read_buf.filled().len();
+            if n == 0 {
+                self.rd = State::Fin;
+                break;
+            }
+            if cursor + n < prefix.size(self.len[0]) {
+                self.rd = State::Len((cursor + n) as u8);
+                continue;
+            }
+
+            let length = prefix.decode(&self.len[..cursor + n]);
+            if length <= self.conf.max_frame() as u64 {
+                self.rd = State::Data(length as usize);
+                continue;
+            }
+            match self.conf.oversized {
+                Oversized::Skip => self.rd = State::Skip(length),
+                Oversized::Reject => {
+                    self.rd = State::Fin;
+                    return Poll::Ready(Err(Error::new(ErrorKind::InvalidData, "frame too large")));
+                }
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// Bytes of the current frame which are not read yet.
+    ///
+    /// With [`SmallBuffer::Continue`], a non-zero value after a `Read` call
+    /// means the next `Read` call continues the same frame.
+    pub fn frame_remaining(&self) -> usize {
+        match self.rd {
+            State::Data(length) => length + self.frame.len(),
+            State::Full => self.frame.len(),
+            _ => 0,
+        }
+    }
+
+    /// Read a complete frame into `frame`, return `false` on EOF.
+    fn poll_fill_frame(&mut self, cx: &mut Context<'_>) -> Poll<Result<bool>> {
+        loop {
+            match self.rd {
+                State::Len(_) | State::Skip(_) => ready!(self.poll_read_len(cx))?,
+                State::Data(0) => {
+                    self.rd = State::Full;
+                    return Poll::Ready(Ok(true));
+                }
+                State::Data(length) => {
+                    let start = self.frame.len();
+                    self.frame.reserve_exact(length);
+                    self.frame.resize(start + length, 0);
+                    let mut read_buf = ReadBuf::new(&mut self.frame[start..]);
+                    let res = Pin::new(&mut self.buf).poll_read(cx, &mut read_buf);
+                    let n = read_buf.filled().len();
+                    self.frame.truncate(start + n);
+                    ready!(res)?;
+                    if n == 0 {
+                        self.rd = State::Fin;
+                        self.frame.clear();
+                        return Poll::Ready(Ok(false));
+                    }
+                    self.rd = State::Data(length - n);
+                }
+                State::Full => return Poll::Ready(Ok(true)),
+                State::Fin => return Poll::Ready(Ok(false)),
+            }
+        }
+    }
+
+    /// Drop the complete frame held in `frame`.
+    #[inline]
+    fn consume_frame(&mut self) {
+        self.rd = State::Len(0);
+        self.frame.clear();
+    }
+
+    /// Poll version of [`read_frame`](Self::read_frame).
+    pub fn poll_read_frame(&mut self, cx: &mut Context<'_>) -> Poll<Result<Option<Vec<u8>>>> {
+        if !ready!(self.poll_fill_frame(cx))? {
+            return Poll::Ready(Ok(None));
+        }
+        self.rd = State::Len(0);
+        Poll::Ready(Ok(Some(std::mem::take(&mut self.frame))))
+    }
+
+    /// Read a complete frame into an exactly sized buffer, `None` on EOF.
+    ///
+    /// Unlike a `Read` call, an empty frame is distinguishable from EOF.
+    /// It is cancel safe, a partially received frame is kept for the next call.
+    #[inline]
+    pub async fn read_frame(&mut self) -> Result<Option<Vec<u8>>> {
+        poll_fn(|cx| self.poll_read_frame(cx)).await
+    }
+
+    /// `Read` a complete frame, according to [`FrameConfig::small_buffer`].
+    fn poll_read_whole(&mut self, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<Result<()>> {
+        loop {
+            if !ready!(self.poll_fill_frame(cx))? {
+                return Poll::Ready(Ok(()));
+            }
+            // an empty frame is indistinguishable from EOF
+            if self.frame.is_empty() {
+                self.consume_frame();
+                continue;
+            }
+
+            let n = std::cmp::min(self.frame.len(), buf.remaining());
+            match self.conf.small_buffer {
+                SmallBuffer::Error if n < self.frame.len() => {
+                    return Poll::Ready(Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        "buffer too small for frame",
+                    )));
+                }
+                SmallBuffer::Continue if n < self.frame.len() => {
+                    buf.put_slice(&self.frame[..n]);
+                    self.frame.drain(..n);
+                }
+                _ => {
+                    // truncate
+                    buf.put_slice(&self.frame[..n]);
+                    self.consume_frame();
+                }
+            }
+            return Poll::Ready(Ok(()));
+        }
+    }
+
+    /// Queue a frame, which is written by [`poll_flush_frames`](Self::poll_flush_frames).
+    ///
+    /// Once accepted, the frame is either written completely, or the stream fails.
+    pub fn start_send_frame(&mut self, frame: Vec<u8>) -> Result<()> {
+        if frame.len() > self.conf.max_frame() {
+            return Err(Error::new(ErrorKind::InvalidInput, "datagram too large"));
+        }
+        match self.wr {
+            State::Len(0) => {}
+            State::Fin => return Err(ErrorKind::WriteZero.into()),
+            _ => return Err(Error::other("a frame is partially written")),
+        }
+        self.wqueue.push_back(frame);
+        Ok(())
+    }
+
+    /// Write all queued frames.
+    ///
+    /// If it fails, queued frames are dropped and the stream is not writable any more.
+    pub fn poll_flush_frames(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        while let Some(frame) = self.wqueue.front() {
+            let mut len = [0u8; 8];
+            let size = self.conf.prefix.encode(frame.len(), &mut len);
+            let cursor = self.wcursor;
+            let res = if cursor < size {
+                let iovec = &[IoSlice::new(&len[cursor..size]), IoSlice::new(frame)][..];
+                ready!(Pin::new(&mut self.buf).poll_write_vectored(cx, iovec))
+            } else {
+                ready!(Pin::new(&mut self.buf).poll_write(cx, &frame[cursor - size..]))
+            };
+
+            let n = match res {
+                Ok(0) => Err(ErrorKind::WriteZero.into()),
+                x => x,
+            };
+            let n = match n {
+                Ok(n) => n,
+                Err(e) => {
+                    self.wr = State::Fin;
+                    self.wqueue.clear();
+                    self.wcursor = 0;
+                    return Poll::Ready(Err(e));
+                }
+            };
+
+            self.wcursor += n;
+            if self.wcursor == size + frame.len() {
+                self.wqueue.pop_front();
+                self.wcursor = 0;
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// Send a frame as a whole.
+    ///
+    /// It is cancel safe. Once polled, the frame is queued, a cancelled frame is
+    /// written by the next call to `send_frame`, `Write` or `flush`.
+    pub async fn send_frame(&mut self, frame: Vec<u8>) -> Result<()> {
+        self.start_send_frame(frame)?;
+        poll_fn(|cx| self.poll_flush_frames(cx)).await
+    }
+
+    /// Number of queued frames, which are not completely written yet.
+    #[inline]
+    pub fn queued_frames(&self) -> usize { self.wqueue.len() }
+
+    /// Write `LEN` followed by `DATA`, return the size of `LEN` and the bytes written.
+    ///
+    /// `cursor` is the part of `LEN` which is already written.
+    fn poll_write_frame(
+        &mut self,
+        cx: &mut Context<'_>,
+        cursor: usize,
+        buf: &[u8],
+    ) -> Poll<Result<(usize, usize)>> {
+        let mut len = [0u8; 8];
+        let size = self.conf.prefix.encode(buf.len(), &mut len);
+        let iovec = &[IoSlice::new(&len[cursor..size]), IoSlice::new(buf)][..];
+        let n = ready!(Pin::new(&mut self.buf).poll_write_vectored(cx, iovec))?;
+        Poll::Ready(Ok((size, n)))
+    }
 }
 
 impl<T: AsyncRead + AsyncWrite + Unpin> AsRef<T> for UotStream<T> {
@@ -89,44 +515,108 @@ where
         buf: &mut ReadBuf<'_>,
     ) -> Poll<Result<()>> {
         let this = self.get_mut();
+        if this.conf.small_buffer != SmallBuffer::Continue {
+            return this.poll_read_whole(cx, buf);
+        }
+        if let State::Len(_) | State::Skip(_) = this.rd {
+            ready!(this.poll_read_len(cx))?;
+        }
+        // only a frame larger than the buffer is continued
+        match this.rd {
+            State::Data(length) if this.frame.is_empty() && buf.remaining() < length => {}
+            _ => return this.poll_read_whole(cx, buf),
+        }
+        let start = buf.filled().len();
 
         loop {
             match this.rd {
-                State::Len => {
-                    let mut read_buf =
-                        ReadBuf::new(buf.initialize_unfilled_to(2 - buf.filled().len()));
-                    let n = ready!(Pin::new(&mut this.buf).poll_read(cx, &mut read_buf))
-                        .map(|_| read_buf.filled().len())?;
-                    if n == 0 {
-                        this.rd = State::Fin;
-                        buf.clear();
-                        return Poll::Ready(Ok(()));
-                    }
-                    buf.advance(n);
-                    if buf.filled().len() < 2 {
-                        continue;
-                    }
-                    this.rd = State::Data(u16::from_be_bytes(buf.filled().try_into().unwrap()));
-                    buf.clear();
+                State::Data(0) => {
+                    this.rd = State::Len(0);
+                    return Poll::Ready(Ok(()));
                 }
+                State::Data(_) if buf.remaining() == 0 => return Poll::Ready(Ok(())),
                 State::Data(length) => {
-                    let mut read_buf = ReadBuf::new(buf.initialize_unfilled_to(length as usize));
-                    let n = ready!(Pin::new(&mut this.buf).poll_read(cx, &mut read_buf))
-                        .map(|_| read_buf.filled().len())?;
+                    let to_read = std::cmp::min(length, buf.remaining());
+                    let mut read_buf = ReadBuf::new(buf.initialize_unfilled_to(to_read));
+                    let n = match Pin::new(&mut this.buf).poll_read(cx, &mut read_buf) {
+                        Poll::Ready(x) => x.map(|_| read_buf.filled().len())?,
+                        // the frame is split anyway, return what we have got
+                        Poll::Pending if buf.filled().len() > start => return Poll::Ready(Ok(())),
+                        Poll::Pending => return Poll::Pending,
+                    };
                     if n == 0 {
                         this.rd = State::Fin;
-                        buf.clear();
+                        buf.set_filled(start);
                         return Poll::Ready(Ok(()));
                     }
                     buf.advance(n);
-                    if n != length as usize {
-                        this.rd = State::Data(length - n as u16);
-                        continue;
+                    this.rd = State::Data(length - n);
+                }
+                State::Len(_) | State::Skip(_) | State::Full => unreachable!(),
+                State::Fin => return Poll::Ready(Ok(())),
+            }
+        }
+    }
+}
+
+impl<T> DatagramStream for UotStream<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_recv(&mut self, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<Result<()>> {
+        loop {
+            if !ready!(self.poll_fill_frame(cx))? {
+                return Poll::Ready(Ok(()));
+            }
+            // an empty datagram is indistinguishable from EOF
+            if self.frame.is_empty() {
+                self.consume_frame();
+                continue;
+            }
+            // truncate
+            let n = std::cmp::min(self.frame.len(), buf.remaining());
+            buf.put_slice(&self.frame[..n]);
+            self.consume_frame();
+            return Poll::Ready(Ok(()));
+        }
+    }
+
+    fn poll_send(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<()>> {
+        if buf.len() > self.conf.max_frame() {
+            return Poll::Ready(Err(Error::new(ErrorKind::InvalidInput, "datagram too large")));
+        }
+        if let State::Len(0) = self.wr {
+            ready!(self.poll_flush_frames(cx))?;
+        }
+
+        loop {
+            let n = match self.wr {
+                State::Len(cursor) => {
+                    let (size, n) = ready!(self.poll_write_frame(cx, cursor as usize, buf))?;
+                    if cursor as usize + n < size {
+                        self.wr = State::Len(cursor + n as u8);
+                    } else {
+                        self.wr = State::Data(buf.len() + size - cursor as usize - n);
                     }
-                    this.rd = State::Len;
+                    n
+                }
+                State::Data(0) => {
+                    self.wr = State::Len(0);
                     return Poll::Ready(Ok(()));
                 }
-                State::Fin => return Poll::Ready(Ok(())),
+                State::Data(left) => {
+                    let data = &buf[buf.len() - left..];
+                    let n = ready!(Pin::new(&mut self.buf).poll_write(cx, data))?;
+                    self.wr = State::Data(left - n);
+                    n
+                }
+                State::Skip(_) | State::Full => unreachable!(),
+                State::Fin => return Poll::Ready(Err(ErrorKind::WriteZero.into())),
+            };
+
+            if n == 0 {
+                // EOF
+                self.wr = State::Fin;
             }
         }
     }
@@ -137,55 +627,67 @@ where
     T: AsyncRead + AsyncWrite + Unpin,
 {
     fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
-        assert!(buf.len() <= MAX_DATAGRAM_PAYLOAD);
-
         let this = self.get_mut();
+        if buf.len() > this.conf.max_frame() {
+            return Poll::Ready(Err(Error::new(ErrorKind::InvalidInput, "datagram too large")));
+        }
+        if let State::Len(0) = this.wr {
+            ready!(this.poll_flush_frames(cx))?;
+        }
 
         loop {
             match this.wr {
-                State::Len => {
-                    unreachable!();
-                }
-                State::Data(cursor) => {
-                    let (n, written_data) = if cursor < 2 {
-                        let len_be = &(buf.len() as u16).to_be_bytes()[cursor as usize..];
-                        let iovec = &mut [IoSlice::new(len_be), IoSlice::new(buf)][..];
-                        let n = ready!(Pin::new(&mut this.buf).poll_write_vectored(cx, iovec))?;
-                        if n as u16 + cursor < 2 {
-                            (n, 0)
-                        } else {
-                            (n, cursor as usize + n - 2)
-                        }
-                    } else {
-                        let n = ready!(Pin::new(&mut this.buf).poll_write(cx, buf))?;
-                        (n, n)
-                    };
-
+                State::Len(cursor) => {
+                    let (size, n) = ready!(this.poll_write_frame(cx, cursor as usize, buf))?;
                     if n == 0 {
                         // EOF
                         this.wr = State::Fin;
                         return Poll::Ready(Ok(0));
                     }
+                    if cursor as usize + n < size {
+                        this.wr = State::Len(cursor + n as u8);
+                        continue;
+                    }
 
-                    this.wr = State::Data(cursor + n as u16);
-                    if written_data > 0 {
-                        if written_data == buf.len() {
-                            this.wr = State::Data(0);
-                        }
+                    let written_data = cursor as usize + n - size;
+                    this.wr = match buf.len() - written_data {
+                        0 => State::Len(0),
+                        left => State::Data(left),
+                    };
+                    if written_data > 0 || buf.is_empty() {
                         return Poll::Ready(Ok(written_data));
                     }
                 }
+                State::Data(left) => {
+                    let data = &buf[..std::cmp::min(left, buf.len())];
+                    let n = ready!(Pin::new(&mut this.buf).poll_write(cx, data))?;
+                    if n == 0 {
+                        // EOF
+                        this.wr = State::Fin;
+                        return Poll::Ready(Ok(0));
+                    }
+                    this.wr = match left - n {
+                        0 => State::Len(0),
+                        left => State::Data(left),
+                    };
+                    return Poll::Ready(Ok(n));
+                }
+                State::Skip(_) | State::Full => unreachable!(),
                 State::Fin => return Poll::Ready(Ok(0)),
             }
         }
     }
 
     fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
-        Pin::new(&mut self.get_mut().buf).poll_flush(cx)
+        let this = self.get_mut();
+        ready!(this.poll_flush_frames(cx))?;
+        Pin::new(&mut this.buf).poll_flush(cx)
     }
 
     fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
-        Pin::new(&mut self.get_mut().buf).poll_shutdown(cx)
+        let this = self.get_mut();
+        ready!(this.poll_flush_frames(cx))?;
+        Pin::new(&mut this.buf).poll_shutdown(cx)
     }
 }
 
@@ -298,4 +800,449 @@ mod test {
             limit_at(i).await;
         }
     }
+
+    #[tokio::test]
+    async fn write_oversized() {
+        let mut stream = UotStream::new(SlowStream {
+            buf: Vec::new(),
+            rlimit: 0,
+            wlimit: 3,
+            cursor: 0,
+        });
+        let oversized = vec![0u8; MAX_DATAGRAM_PAYLOAD + 1];
+        let err = stream.write(&oversized).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert!(stream.buf.get_ref().buf.is_empty());
+
+        // still usable
+        stream.write_all(b"Ciallo").await.unwrap();
+        assert_eq!(&stream.buf.get_ref().buf, b"\x00\x06Ciallo");
+    }
+
+    #[tokio::test]
+    async fn recv_framed() {
+        async fn limit_at(rlimit: usize) {
+            let dummy = vec![b'r'; 1024];
+            let mut buf: Vec<u8> = Vec::with_capacity(MAX_DATAGRAM_PAYLOAD);
+            for i in 0..=512 {
+                <_ as Write>::write(&mut buf, &(i as u16).to_be_bytes()).unwrap();
+                <_ as Write>::write(&mut buf, &dummy[..i]).unwrap();
+            }
+            let mut stream = UotStream::new(SlowStream {
+                buf,
+                rlimit,
+                wlimit: 0,
+                cursor: 0,
+            });
+            // truncated
+            let mut buf = vec![0u8; 256];
+            for i in 1..=512 {
+                let n = stream.recv(&mut buf).await.unwrap();
+                assert_eq!(n, std::cmp::min(i, 256));
+                assert_eq!(&buf[..n], &dummy[..n]);
+            }
+            // EOF
+            assert_eq!(stream.recv(&mut buf).await.unwrap(), 0);
+        }
+        for i in 1..=512 {
+            limit_at(i).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn send_framed() {
+        async fn limit_at(wlimit: usize) {
+            let dummy = vec![b'w'; 1024];
+            let mut stream = UotStream::new(SlowStream {
+                buf: Vec::with_capacity(MAX_DATAGRAM_PAYLOAD),
+                rlimit: 0,
+                wlimit,
+                cursor: 0,
+            });
+            for i in 1..=512 {
+                let prev = stream.buf.get_ref().buf.len();
+                stream.send(&dummy[..i]).await.unwrap();
+                let next = stream.buf.get_ref().buf.len();
+                let buf = &stream.buf.get_ref().buf;
+                assert_eq!(next - prev, i + 2);
+                assert_eq!(u16::from_be_bytes([buf[prev], buf[prev + 1]]), i as u16);
+                assert_eq!(&buf[prev + 2..next], &dummy[..i]);
+            }
+            let oversized = vec![0u8; MAX_DATAGRAM_PAYLOAD + 1];
+            let err = stream.send(&oversized).await.unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        }
+        for i in 1..=512 {
+            limit_at(i).await;
+        }
+    }
+
+    #[test]
+    fn length_prefix() {
+        let mut out = [0u8; 8];
+        // examples from RFC 9000, appendix A.1
+        for (len, expect) in [
+            (37usize, &[0x25][..]),
+            (15293, &[0x7b, 0xbd][..]),
+            (494878333, &[0x9d, 0x7f, 0x3e, 0x7d][..]),
+            (151288809941952652, &[0xc2, 0x19, 0x7c, 0x5e, 0xff, 0x14, 0xe8, 0x8c][..]),
+        ] {
+            let size = LengthPrefix::Varint.encode(len, &mut out);
+            assert_eq!(&out[..size], expect);
+            assert_eq!(LengthPrefix::Varint.size(out[0]), size);
+            assert_eq!(LengthPrefix::Varint.decode(&out[..size]), len as u64);
+        }
+
+        let size = LengthPrefix::U32(ByteOrder::Little).encode(0x12345, &mut out);
+        assert_eq!(&out[..size], &[0x45, 0x23, 0x01, 0x00]);
+        let size = LengthPrefix::U16(ByteOrder::Big).encode(0x1234, &mut out);
+        assert_eq!(&out[..size], &[0x12, 0x34]);
+    }
+
+    #[tokio::test]
+    async fn large_framed() {
+        let dummy: Vec<u8> = (0..200_000u32).map(|x| x as u8).collect();
+        let sizes = [0x3f, 0x40, 0x3fff, 0x4000, 65535, 65536, 200_000];
+        for prefix in [
+            LengthPrefix::U32(ByteOrder::Big),
+            LengthPrefix::U32(ByteOrder::Little),
+            LengthPrefix::Varint,
+        ] {
+            let conf = FrameConfig {
+                prefix,
+                max_frame: 200_000,
+                ..FrameConfig::default()
+            };
+            let mut stream = UotStream::with_config(
+                SlowStream {
+                    buf: Vec::new(),
+                    rlimit: 1000,
+                    wlimit: 1000,
+                    cursor: 0,
+                },
+                conf,
+            );
+            for &n in &sizes {
+                stream.write_all(&dummy[..n]).await.unwrap();
+                stream.send(&dummy[..n]).await.unwrap();
+            }
+
+            let mut buf = vec![0u8; 200_000];
+            for &n in &sizes {
+                stream.read_exact(&mut buf[..n]).await.unwrap();
+                assert_eq!(&buf[..n], &dummy[..n]);
+                assert_eq!(stream.recv(&mut buf).await.unwrap(), n);
+                assert_eq!(&buf[..n], &dummy[..n]);
+            }
+
+            let err = stream.send(&vec![0u8; 200_001]).await.unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[tokio::test]
+    async fn oversized_framed() {
+        let mut stream = UotStream::with_config(
+            SlowStream {
+                buf: vec![0x00, 0x00, 0x10, 0x00, 0],
+                rlimit: 1,
+                wlimit: 0,
+                cursor: 0,
+            },
+            FrameConfig {
+                prefix: LengthPrefix::U32(ByteOrder::Big),
+                max_frame: 0x0fff,
+                ..FrameConfig::default()
+            },
+        );
+        let mut buf = vec![0u8; 16];
+        let err = stream.recv(&mut buf).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn skip_oversized() {
+        async fn limit_at(rlimit: usize) {
+            let mut buf: Vec<u8> = Vec::new();
+            for i in [8usize, 9, 1000, 4, 0x10000, 8] {
+                <_ as Write>::write(&mut buf, &(i as u32).to_le_bytes()).unwrap();
+                <_ as Write>::write(&mut buf, &vec![i as u8; i]).unwrap();
+            }
+            let conf = FrameConfig {
+                prefix: LengthPrefix::U32(ByteOrder::Little),
+                max_frame: 8,
+                oversized: Oversized::Skip,
+                ..FrameConfig::default()
+            };
+            let mut stream = UotStream::with_config(
+                SlowStream {
+                    buf,
+                    rlimit,
+                    wlimit: 0,
+                    cursor: 0,
+                },
+                conf,
+            );
+            let mut buf = vec![0u8; 16];
+            let n = stream.read(&mut buf).await.unwrap();
+            assert_eq!(&buf[..n], &[8u8; 8]);
+            let n = stream.recv(&mut buf).await.unwrap();
+            assert_eq!(&buf[..n], &[4u8; 4]);
+            let n = stream.read(&mut buf).await.unwrap();
+            assert_eq!(&buf[..n], &[8u8; 8]);
+            // EOF
+            assert_eq!(stream.recv(&mut buf).await.unwrap(), 0);
+        }
+        for i in [1, 3, 7, 100, 8192] {
+            limit_at(i).await;
+        }
+    }
+
+    fn frames(sizes: &[usize]) -> Vec<u8> {
+        let mut buf: Vec<u8> = Vec::new();
+        for &i in sizes {
+            <_ as Write>::write(&mut buf, &(i as u16).to_be_bytes()).unwrap();
+            <_ as Write>::write(&mut buf, &vec![i as u8; i]).unwrap();
+        }
+        buf
+    }
+
+    fn small_buffer_stream(
+        sizes: &[usize],
+        rlimit: usize,
+        policy: SmallBuffer,
+    ) -> UotStream<SlowStream> {
+        let conf = FrameConfig {
+            small_buffer: policy,
+            ..FrameConfig::default()
+        };
+        let stream = SlowStream {
+            buf: frames(sizes),
+            rlimit,
+            wlimit: 0,
+            cursor: 0,
+        };
+        UotStream::with_config(stream, conf)
+    }
+
+    #[tokio::test]
+    async fn small_buffer() {
+        for rlimit in [1, 3, 7, 100] {
+            // continue
+            let mut stream = small_buffer_stream(&[20, 5], rlimit, SmallBuffer::Continue);
+            let mut buf = vec![0u8; 8];
+            let mut total = 0;
+            loop {
+                let n = stream.read(&mut buf).await.unwrap();
+                assert_eq!(&buf[..n], &vec![20u8; n][..]);
+                total += n;
+                if stream.frame_remaining() == 0 {
+                    break;
+                }
+            }
+            assert_eq!(total, 20);
+            assert_eq!(stream.read(&mut buf).await.unwrap(), 5);
+
+            // truncate
+            let mut stream = small_buffer_stream(&[20, 5], rlimit, SmallBuffer::Truncate);
+            let n = stream.read(&mut buf).await.unwrap();
+            assert_eq!(&buf[..n], &[20u8; 8]);
+            let n = stream.read(&mut buf).await.unwrap();
+            assert_eq!(&buf[..n], &[5u8; 5]);
+            assert_eq!(stream.read(&mut buf).await.unwrap(), 0);
+
+            // error, then retry with a larger buffer
+            let mut stream = small_buffer_stream(&[20, 5], rlimit, SmallBuffer::Error);
+            let err = stream.read(&mut buf).await.unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+            assert_eq!(stream.frame_remaining(), 20);
+            let mut large = vec![0u8; 32];
+            let n = stream.read(&mut large).await.unwrap();
+            assert_eq!(&large[..n], &[20u8; 20]);
+            let n = stream.read(&mut buf).await.unwrap();
+            assert_eq!(&buf[..n], &[5u8; 5]);
+        }
+    }
+
+    #[tokio::test]
+    async fn read_frame() {
+        for rlimit in [1, 3, 7, 100] {
+            let mut stream = small_buffer_stream(&[20, 0, 5], rlimit, SmallBuffer::Continue);
+            let frame = stream.read_frame().await.unwrap().unwrap();
+            assert_eq!(frame, vec![20u8; 20]);
+            assert_eq!(stream.read_frame().await.unwrap(), Some(Vec::new()));
+            assert_eq!(stream.read_frame().await.unwrap(), Some(vec![5u8; 5]));
+            assert_eq!(stream.read_frame().await.unwrap(), None);
+        }
+    }
+
+    /// Every write returns `Pending` once before it is forwarded.
+    struct Flaky {
+        inner: SlowStream,
+        ready: bool,
+    }
+
+    impl AsyncRead for Flaky {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            Pin::new(&mut self.get_mut().inner).poll_read(cx, buf)
+        }
+    }
+
+    impl AsyncWrite for Flaky {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<Result<usize>> {
+            let this = self.get_mut();
+            this.ready = !this.ready;
+            if !this.ready {
+                return Pin::new(&mut this.inner).poll_write(cx, buf);
+            }
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    /// Every read returns `Pending` once before it is forwarded.
+    struct FlakyRead {
+        inner: SlowStream,
+        ready: bool,
+    }
+
+    impl AsyncRead for FlakyRead {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            let this = self.get_mut();
+            this.ready = !this.ready;
+            if !this.ready {
+                return Pin::new(&mut this.inner).poll_read(cx, buf);
+            }
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+
+    impl AsyncWrite for FlakyRead {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<Result<usize>> {
+            Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn read_pending_mid_frame() {
+        for rlimit in [1, 3, 7] {
+            let mut stream = UotStream::new(FlakyRead {
+                inner: SlowStream {
+                    buf: frames(&[20, 0, 5]),
+                    rlimit,
+                    wlimit: 0,
+                    cursor: 0,
+                },
+                ready: false,
+            });
+            let mut buf = vec![0u8; 32];
+            let n = stream.read(&mut buf).await.unwrap();
+            assert_eq!(&buf[..n], &[20u8; 20]);
+            let n = stream.read(&mut buf).await.unwrap();
+            assert_eq!(&buf[..n], &[5u8; 5]);
+            assert_eq!(stream.read(&mut buf).await.unwrap(), 0);
+
+            // split only if the buffer is smaller than the frame
+            let mut stream = UotStream::new(FlakyRead {
+                inner: SlowStream {
+                    buf: frames(&[20]),
+                    rlimit,
+                    wlimit: 0,
+                    cursor: 0,
+                },
+                ready: false,
+            });
+            let mut small = vec![0u8; 8];
+            let n = stream.read(&mut small).await.unwrap();
+            assert!(n > 0 && n <= 8);
+            assert_eq!(stream.frame_remaining(), 20 - n);
+            let n = stream.read(&mut buf).await.unwrap();
+            assert_eq!(stream.frame_remaining(), 0);
+            assert_eq!(&buf[..n], &vec![20u8; n][..]);
+        }
+    }
+
+    #[tokio::test]
+    async fn send_frame_cancelled() {
+        let dummy = vec![b'w'; 1024];
+        let mut stream = UotStream::new(Flaky {
+            inner: SlowStream {
+                buf: Vec::new(),
+                rlimit: 4096,
+                wlimit: 3,
+                cursor: 0,
+            },
+            ready: false,
+        });
+
+        // each send is cancelled after a single poll
+        for i in 1..=64 {
+            tokio::select! {
+                biased;
+                _ = stream.send_frame(dummy[..i].to_vec()) => {},
+                _ = std::future::ready(()) => {},
+            }
+        }
+        assert!(stream.queued_frames() > 0);
+        stream.write_all(&dummy[..65]).await.unwrap();
+        stream.send_frame(dummy[..66].to_vec()).await.unwrap();
+        assert_eq!(stream.queued_frames(), 0);
+
+        let mut buf = vec![0u8; 256];
+        for i in 1..=66 {
+            let n = stream.read(&mut buf).await.unwrap();
+            assert_eq!(n, i);
+            assert_eq!(&buf[..n], &dummy[..n]);
+        }
+    }
+
+    #[tokio::test]
+    async fn send_frame_failed() {
+        let mut stream = UotStream::new(SlowStream {
+            buf: Vec::new(),
+            rlimit: 0,
+            wlimit: 0,
+            cursor: 0,
+        });
+        let err = stream.send_frame(vec![0u8; 8]).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::WriteZero);
+        assert_eq!(stream.queued_frames(), 0);
+        let err = stream.send_frame(vec![0u8; 8]).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::WriteZero);
+        let err = stream.send_frame(vec![0u8; MAX_DATAGRAM_PAYLOAD + 1]).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    }
 }
@@ -0,0 +1,72 @@
+use std::io::Result;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+use std::task::{Context, Poll};
+
+use crate::time::{sleep, Sleep, Instant};
+
+/// NAT keepalive.
+///
+/// The payload is sent after there is no outbound traffic during `interval`.
+/// It does not count as user traffic, and does not reset the read timer.
+#[derive(Debug, Clone)]
+pub struct Keepalive {
+    /// Period of outbound silence before a keepalive is sent.
+    pub interval: Duration,
+    /// Content of a keepalive packet.
+    pub payload: Vec<u8>,
+    /// Drop received packets that are identical to `payload`.
+    pub filter: bool,
+}
+
+impl Keepalive {
+    /// Create with an interval and payload, received keepalives are not filtered.
+    #[inline]
+    pub fn new(interval: Duration, payload: impl Into<Vec<u8>>) -> Self {
+        Self {
+            interval,
+            payload: payload.into(),
+            filter: false,
+        }
+    }
+}
+
+pub(crate) struct KeepaliveTimer {
+    conf: Keepalive,
+    timer: Pin<Box<Sleep>>,
+}
+
+impl KeepaliveTimer {
+    pub fn new(conf: Keepalive) -> Self {
+        let timer = Box::pin(sleep(conf.interval));
+        Self { conf, timer }
+    }
+
+    /// Delay the next keepalive, called after sending user data.
+    #[inline]
+    pub fn reset(&mut self) { self.timer.as_mut().reset(Instant::now() + self.conf.interval); }
+
+    /// Whether a received packet is a keepalive, which should be dropped.
+    #[inline]
+    pub fn is_keepalive(&self, pkt: &[u8]) -> bool { self.conf.filter && pkt == self.conf.payload }
+
+    /// Send a keepalive if it is due.
+    pub fn poll_keepalive<F>(&mut self, cx: &mut Context<'_>, send: F)
+    where
+        F: FnOnce(&mut Context<'_>, &[u8]) -> Poll<Result<usize>>,
+    {
+        if self.timer.as_mut().poll(cx).is_pending() {
+            return;
+        }
+
+        // retry on the next poll
+        if send(cx, &self.conf.payload).is_pending() {
+            return;
+        }
+
+        // register the new deadline
+        self.reset();
+        let _ = self.timer.as_mut().poll(cx);
+    }
+}
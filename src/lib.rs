@@ -25,6 +25,36 @@
 //! }
 //! ```
 //!
+//! ## Relay datagrams
+//!
+//! ```
+//! use tokio::net::TcpStream;
+//! use udpflow::{relay, UdpStreamLocal, UotStream};
+//! async fn handle(stream1: UdpStreamLocal) {
+//!     let stream2 = UotStream::new(TcpStream::connect("127.0.0.1:8080").await.unwrap());
+//!     // datagram boundaries are preserved, traffic in either direction resets the timer
+//!     let stats = relay(stream1, stream2).await;
+//!     println!("{:?}: {:?} {:?}", stats.reason, stats.a_to_b, stats.b_to_a);
+//! }
+//! ```
+//!
+//! ## Reliable stream
+//!
+//! ```
+//! use tokio::net::UdpSocket;
+//! use tokio::io::AsyncWriteExt;
+//! use udpflow::UdpStreamRemote;
+//! use udpflow::arq::{Reliable, ArqConfig};
+//! async {
+//!     let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+//!     let stream = UdpStreamRemote::new(socket, "127.0.0.1:10000".parse().unwrap());
+//!     // lost segments are retransmitted, bytes are delivered in order
+//!     let mut stream = Reliable::new(stream, ArqConfig::default());
+//!     stream.write_all(b"Ciallo").await.unwrap();
+//!     stream.flush().await.unwrap();
+//! };
+//! ```
+//!
 //! ## Send/Recv framed data
 //!
 //! ```
@@ -42,20 +72,96 @@
 //! };
 //! ```
 //!
+//! ## Runtimes
+//!
+//! Runtimes are enabled by cargo features, which are additive:
+//!
+//! - `runtime-tokio` (default): [`UdpSocket`] is tokio's, streams implement tokio's io traits.
+//!   It is the default socket type of [`UdpListener`], [`UdpStreamLocal`] and [`UdpStreamRemote`].
+//! - `runtime-smol` or `runtime-async-std`: `async_io::UdpSocket` is
+//!   `async_io::Async<std::net::UdpSocket>`, [`UdpStreamLocal`] and [`UdpStreamRemote`]
+//!   also implement the `futures-io` traits.
+//!
+//! The backend is selected by the socket type, e.g. `UdpStreamRemote<async_io::UdpSocket>`,
+//! so enabling another runtime does not change the meaning of existing code.
+//! Modules which depend on tokio sockets, such as `unix` and the noise listener,
+//! are only available with `runtime-tokio`.
+//!
+
+macro_rules! ready {
+    ($e:expr $(,)?) => {
+        match $e {
+            std::task::Poll::Ready(t) => t,
+            std::task::Poll::Pending => return std::task::Poll::Pending,
+        }
+    };
+}
+
+#[cfg(not(any(
+    feature = "runtime-tokio",
+    feature = "runtime-async-std",
+    feature = "runtime-smol"
+)))]
+compile_error!("one of `runtime-tokio`, `runtime-async-std` or `runtime-smol` must be enabled");
 
+mod time;
 mod sockmap;
 mod streaml;
 mod streamr;
 mod listener;
+mod datagram;
+mod socket;
+mod relay;
+mod keepalive;
+mod pacing;
+mod sockopt;
+mod meta;
+mod util;
+#[cfg(any(feature = "crypto", feature = "noise"))]
+mod replay;
+#[cfg(test)]
+pub(crate) mod testutil;
 
 pub mod frame;
+pub mod pmtu;
+pub mod frag;
+pub mod arq;
+pub mod fec;
+pub mod seq;
+pub mod obfs;
+#[cfg(any(feature = "lz4", feature = "zstd"))]
+pub mod compress;
+#[cfg(feature = "codec")]
+pub mod codec;
+#[cfg(any(feature = "runtime-async-std", feature = "runtime-smol"))]
+pub mod async_io;
+#[cfg(all(unix, feature = "runtime-tokio"))]
+pub mod unix;
+#[cfg(feature = "sim")]
+pub mod sim;
+#[cfg(all(target_os = "linux", feature = "uring"))]
+pub mod uring;
+#[cfg(feature = "crypto")]
+pub mod crypto;
+#[cfg(feature = "noise")]
+pub mod noise;
 
 pub use listener::UdpListener;
 pub use streaml::UdpStreamLocal;
 pub use streamr::UdpStreamRemote;
 pub use frame::UotStream;
+pub use datagram::DatagramStream;
+pub use socket::DatagramSocket;
+pub use relay::{relay, RelayStats, Traffic, CloseReason};
+pub use keepalive::Keepalive;
+pub use pacing::Pacing;
+pub use sockopt::SockOpts;
+pub use meta::RecvMeta;
+#[cfg(feature = "runtime-tokio")]
+pub use meta::enable_recv_meta;
 
 /// Re-export from tokio-udp.
+#[cfg(feature = "runtime-tokio")]
 pub use tokio::net::UdpSocket;
 
 mod statics {
@@ -1,23 +1,47 @@
 use std::io::Result;
 use std::net::SocketAddr;
+use std::hash::Hash;
 use std::sync::Arc;
 
-use tokio::net::UdpSocket;
 use tokio::sync::mpsc;
 
-use crate::UdpStreamLocal;
+use crate::{UdpStreamLocal, DatagramSocket};
+#[cfg(feature = "runtime-tokio")]
+use crate::{SockOpts, UdpSocket};
+use crate::meta;
 
-use crate::sockmap::{SockMap, Packet};
+use crate::sockmap::{SockMap, Message};
 
 /// Udp packet listener.
-pub struct UdpListener {
-    socket: Arc<UdpSocket>,
-    sockmap: SockMap,
+///
+/// Sessions are keyed by the peer address `A`, and share the socket `S`, which is any
+/// [`DatagramSocket`], tokio's `UdpSocket` by default. See [`unix`](crate::unix) for
+/// a listener keyed by paths.
+pub struct UdpListener<
+    #[cfg(feature = "runtime-tokio")] S = UdpSocket,
+    #[cfg(not(feature = "runtime-tokio"))] S,
+    A = SocketAddr,
+> {
+    socket: Arc<S>,
+    sockmap: SockMap<A, Message>,
 }
 
+#[cfg(feature = "runtime-tokio")]
 impl UdpListener {
+    /// Create a udp socket with options, and bind it to `addr`.
+    #[inline]
+    pub fn bind_with(addr: SocketAddr, opts: &SockOpts) -> Result<Self> {
+        opts.bind(addr).map(Self::new)
+    }
+}
+
+impl<S, A> UdpListener<S, A>
+where
+    S: DatagramSocket<A>,
+    A: Clone + Eq + Hash,
+{
     /// Create from a **bound** udp socket.
-    pub fn new(socket: UdpSocket) -> Self {
+    pub fn new(socket: S) -> Self {
         Self {
             socket: Arc::new(socket),
             sockmap: SockMap::new(),
@@ -31,23 +55,24 @@ impl UdpListener {
     /// When receiving a packet from a known peer, this function does not return,
     /// and the packet will be copied then sent to the associated
     /// [`UdpStreamLocal`](super::UdpStreamLocal).  
-    pub async fn accept(&self, buf: &mut [u8]) -> Result<(UdpStreamLocal, SocketAddr)> {
+    pub async fn accept(&self, buf: &mut [u8]) -> Result<(UdpStreamLocal<S, A>, A)> {
         loop {
-            let (n, addr) = self.socket.recv_from(buf).await?;
+            let (n, addr, meta) = meta::recv_from(&*self.socket, buf).await?;
             debug_assert!(n != 0);
 
             // existed session
             if let Some(tx) = self.sockmap.get(&addr) {
-                let _ = tx.send(Vec::from(&buf[..n])).await;
+                let _ = tx.send((Vec::from(&buf[..n]), meta)).await;
                 continue;
             }
 
             // new session
-            let (tx, rx) = mpsc::channel::<Packet>(32);
-            let _ = tx.send(Vec::from(&buf[..n])).await;
-            self.sockmap.insert(addr, tx);
+            let (tx, rx) = mpsc::channel::<Message>(32);
+            let _ = tx.send((Vec::from(&buf[..n]), meta)).await;
+            self.sockmap.insert(addr.clone(), tx);
 
-            let stream = UdpStreamLocal::new(rx, self.socket.clone(), self.sockmap.clone(), addr);
+            let stream =
+                UdpStreamLocal::new(rx, self.socket.clone(), self.sockmap.clone(), addr.clone());
             return Ok((stream, addr));
         }
     }
@@ -0,0 +1,174 @@
+use std::io::Result;
+use std::net::IpAddr;
+use std::time::SystemTime;
+use std::future::poll_fn;
+
+use tokio::io::ReadBuf;
+#[cfg(feature = "runtime-tokio")]
+use tokio::net::UdpSocket;
+
+use crate::DatagramSocket;
+
+/// Metadata of a received packet.
+///
+/// Fields are captured via control messages on Linux, after they are
+/// enabled by [`enable_recv_meta`] or [`SockOpts::recv_meta`](super::SockOpts::recv_meta).
+/// Otherwise they are left as `None`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RecvMeta {
+    /// Kernel receive timestamp, `SO_TIMESTAMPNS`.
+    pub timestamp: Option<SystemTime>,
+    /// TTL or hop limit, `IP_RECVTTL` or `IPV6_RECVHOPLIMIT`.
+    pub ttl: Option<u8>,
+    /// ECN bits, `IP_RECVTOS` or `IPV6_RECVTCLASS`.
+    pub ecn: Option<u8>,
+    /// Local destination address, `IP_PKTINFO` or `IPV6_RECVPKTINFO`.
+    pub dst: Option<IpAddr>,
+}
+
+/// Ask the kernel to attach [`RecvMeta`] to each received packet.
+#[cfg(feature = "runtime-tokio")]
+pub fn enable_recv_meta(socket: &UdpSocket) -> Result<()> {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        linux::enable(socket, socket.local_addr()?.is_ipv6())
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    {
+        let _ = socket;
+        Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "not supported on this platform"))
+    }
+}
+
+/// Like `recv_from`, also capture metadata.
+pub(crate) async fn recv_from<A, S: DatagramSocket<A> + ?Sized>(
+    socket: &S,
+    buf: &mut [u8],
+) -> Result<(usize, A, RecvMeta)> {
+    let mut buf = ReadBuf::new(buf);
+    let (addr, meta) = poll_fn(|cx| socket.poll_recv_meta(cx, &mut buf)).await?;
+    Ok((buf.filled().len(), addr, meta))
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) mod linux {
+    use std::io::{Result, Error};
+    use std::mem::{size_of, zeroed};
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
+    use std::time::{Duration, SystemTime};
+    use std::os::fd::AsRawFd;
+
+    use tokio::io::ReadBuf;
+
+    use super::RecvMeta;
+    use crate::sockopt::linux::setsockopt;
+
+    pub fn enable<F: AsRawFd>(fd: &F, ipv6: bool) -> Result<()> {
+        setsockopt(fd, libc::SOL_SOCKET, libc::SO_TIMESTAMPNS, 1)?;
+        if ipv6 {
+            setsockopt(fd, libc::IPPROTO_IPV6, libc::IPV6_RECVHOPLIMIT, 1)?;
+            setsockopt(fd, libc::IPPROTO_IPV6, libc::IPV6_RECVTCLASS, 1)?;
+            setsockopt(fd, libc::IPPROTO_IPV6, libc::IPV6_RECVPKTINFO, 1)?;
+        } else {
+            setsockopt(fd, libc::IPPROTO_IP, libc::IP_RECVTTL, 1)?;
+            setsockopt(fd, libc::IPPROTO_IP, libc::IP_RECVTOS, 1)?;
+            setsockopt(fd, libc::IPPROTO_IP, libc::IP_PKTINFO, 1)?;
+        }
+        Ok(())
+    }
+
+    /// Receive a packet into the unfilled part of `buf`, and parse control messages.
+    pub fn recvmsg<F: AsRawFd>(fd: &F, buf: &mut ReadBuf<'_>) -> Result<(SocketAddr, RecvMeta)> {
+        // aligned for cmsghdr
+        let mut control = [0u64; 32];
+        let unfilled = unsafe { buf.unfilled_mut() };
+        let mut iov = libc::iovec {
+            iov_base: unfilled.as_mut_ptr() as *mut libc::c_void,
+            iov_len: unfilled.len(),
+        };
+
+        let mut storage: libc::sockaddr_storage = unsafe { zeroed() };
+        let mut msg: libc::msghdr = unsafe { zeroed() };
+        msg.msg_name = &mut storage as *mut _ as *mut libc::c_void;
+        msg.msg_namelen = size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = size_of::<[u64; 32]>() as _;
+
+        let n = unsafe { libc::recvmsg(fd.as_raw_fd(), &mut msg, 0) };
+        if n < 0 {
+            return Err(Error::last_os_error());
+        }
+        let n = n as usize;
+
+        unsafe { buf.assume_init(n) };
+        buf.advance(n);
+
+        let addr = unsafe { to_socket_addr(&storage) }?;
+        Ok((addr, unsafe { parse(&msg) }))
+    }
+
+    pub unsafe fn to_socket_addr(storage: &libc::sockaddr_storage) -> Result<SocketAddr> {
+        match storage.ss_family as libc::c_int {
+            libc::AF_INET => {
+                let addr = &*(storage as *const _ as *const libc::sockaddr_in);
+                let ip = Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr));
+                Ok(SocketAddr::from((ip, u16::from_be(addr.sin_port))))
+            }
+            libc::AF_INET6 => {
+                let addr = &*(storage as *const _ as *const libc::sockaddr_in6);
+                let ip = Ipv6Addr::from(addr.sin6_addr.s6_addr);
+                Ok(SocketAddrV6::new(
+                    ip,
+                    u16::from_be(addr.sin6_port),
+                    addr.sin6_flowinfo,
+                    addr.sin6_scope_id,
+                )
+                .into())
+            }
+            _ => Err(Error::other("unexpected address family")),
+        }
+    }
+
+    unsafe fn parse(msg: &libc::msghdr) -> RecvMeta {
+        let mut meta = RecvMeta::default();
+        let mut cmsg = libc::CMSG_FIRSTHDR(msg);
+        while !cmsg.is_null() {
+            let data = libc::CMSG_DATA(cmsg);
+            match ((*cmsg).cmsg_level, (*cmsg).cmsg_type) {
+                (libc::SOL_SOCKET, libc::SCM_TIMESTAMPNS) => {
+                    let ts = (data as *const libc::timespec).read_unaligned();
+                    let dur = Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32);
+                    meta.timestamp = Some(SystemTime::UNIX_EPOCH + dur);
+                }
+                (libc::IPPROTO_IP, libc::IP_TTL) => {
+                    meta.ttl = Some((data as *const libc::c_int).read_unaligned() as u8);
+                }
+                (libc::IPPROTO_IP, libc::IP_TOS) => {
+                    meta.ecn = Some(*data & 0b11);
+                }
+                (libc::IPPROTO_IP, libc::IP_PKTINFO) => {
+                    let info = (data as *const libc::in_pktinfo).read_unaligned();
+                    let addr = Ipv4Addr::from(u32::from_be(info.ipi_addr.s_addr));
+                    meta.dst = Some(IpAddr::V4(addr));
+                }
+                (libc::IPPROTO_IPV6, libc::IPV6_HOPLIMIT) => {
+                    meta.ttl = Some((data as *const libc::c_int).read_unaligned() as u8);
+                }
+                (libc::IPPROTO_IPV6, libc::IPV6_TCLASS) => {
+                    let tclass = (data as *const libc::c_int).read_unaligned();
+                    meta.ecn = Some(tclass as u8 & 0b11);
+                }
+                (libc::IPPROTO_IPV6, libc::IPV6_PKTINFO) => {
+                    let info = (data as *const libc::in6_pktinfo).read_unaligned();
+                    let addr = Ipv6Addr::from(info.ipi6_addr.s6_addr);
+                    meta.dst = Some(IpAddr::V6(addr));
+                }
+                _ => {}
+            }
+            cmsg = libc::CMSG_NXTHDR(msg, cmsg);
+        }
+        meta
+    }
+}
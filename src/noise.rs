@@ -0,0 +1,370 @@
+//! Authenticated sessions with the Noise protocol.
+//!
+//! Requires the `noise` feature. Handshakes use `Noise_IK_25519_ChaChaPoly_BLAKE2s`
+//! or `Noise_XX_25519_ChaChaPoly_BLAKE2s`, then session keys encrypt each datagram.
+//!
+//! ## Protocol Specification
+//!
+//! ```text
+//! +------+----------+
+//! | TYPE |   DATA   |
+//! +------+----------+
+//! |  1   | Variable |
+//! +------+----------+
+//! ```
+//! TYPE is `HANDSHAKE(0)` or `TRANSPORT(1)`. DATA of a handshake packet is a Noise
+//! handshake message. DATA of a transport packet is a 64-bit nonce in big endian
+//! byte order, followed by the ciphertext.
+//!
+//! A handshake message is retransmitted until the peer's next message arrives.
+//! The side that finishes first resends its last message, whenever it receives
+//! the peer's previous message again. Handshakes are limited by the idle timeout
+//! of the underlying stream, see [`set_timeout`](crate::set_timeout).
+//!
+
+use std::io::{Result, Error, ErrorKind};
+#[cfg(feature = "runtime-tokio")]
+use std::net::SocketAddr;
+use std::time::Duration;
+use std::task::{Context, Poll};
+
+use tokio::io::ReadBuf;
+#[cfg(feature = "runtime-tokio")]
+use tokio::sync::{mpsc, Mutex};
+#[cfg(feature = "runtime-tokio")]
+use tokio::task::JoinHandle;
+
+use snow::{Builder, HandshakeState, StatelessTransportState};
+use snow::params::NoiseParams;
+
+use crate::time::timeout;
+use crate::DatagramStream;
+#[cfg(feature = "runtime-tokio")]
+use crate::{UdpListener, UdpStreamLocal};
+use crate::replay::ReplayWindow;
+
+const HANDSHAKE: u8 = 0;
+const TRANSPORT: u8 = 1;
+const TAG: usize = 16;
+
+/// Handshake pattern.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pattern {
+    /// The initiator knows the responder's static key, one round trip.
+    IK,
+    /// Static keys are exchanged during the handshake, one and a half round trips.
+    XX,
+}
+
+/// Handshake options.
+#[derive(Debug, Clone)]
+pub struct NoiseConfig {
+    /// Handshake pattern, both sides must use the same one.
+    pub pattern: Pattern,
+    /// Local static private key.
+    pub private_key: Vec<u8>,
+    /// Remote static public key, required by an `IK` initiator.
+    pub remote_public_key: Option<Vec<u8>>,
+    /// Period to wait before a handshake message is retransmitted.
+    pub retransmit: Duration,
+}
+
+impl NoiseConfig {
+    /// Create with a pattern and private key.
+    #[inline]
+    pub fn new(pattern: Pattern, private_key: impl Into<Vec<u8>>) -> Self {
+        Self {
+            pattern,
+            private_key: private_key.into(),
+            remote_public_key: None,
+            retransmit: Duration::from_millis(500),
+        }
+    }
+
+    fn params(&self) -> NoiseParams {
+        match self.pattern {
+            Pattern::IK => "Noise_IK_25519_ChaChaPoly_BLAKE2s",
+            Pattern::XX => "Noise_XX_25519_ChaChaPoly_BLAKE2s",
+        }
+        .parse()
+        .unwrap()
+    }
+
+    fn build(&self, initiator: bool) -> Result<HandshakeState> {
+        let params = self.params();
+        let mut builder = Builder::new(params).local_private_key(&self.private_key);
+        if let Some(key) = self.remote_public_key.as_ref() {
+            builder = builder.remote_public_key(key);
+        }
+        let hs = if initiator { builder.build_initiator() } else { builder.build_responder() };
+        hs.map_err(noise_error)
+    }
+}
+
+/// Transport statistics.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct NoiseStats {
+    /// Packets that are malformed or fail authentication.
+    pub dropped: u64,
+    /// Packets that are replayed or too old.
+    pub replayed: u64,
+}
+
+/// Generate a static keypair, return `(private, public)`.
+pub fn generate_keypair() -> Result<(Vec<u8>, Vec<u8>)> {
+    let params = NoiseConfig::new(Pattern::XX, Vec::new()).params();
+    let keypair = Builder::new(params).generate_keypair().map_err(noise_error)?;
+    Ok((keypair.private, keypair.public))
+}
+
+#[inline]
+fn noise_error(e: snow::Error) -> Error { Error::new(ErrorKind::InvalidData, e) }
+
+/// Perform a handshake as the initiator.
+pub async fn initiate<S: DatagramStream>(inner: S, conf: &NoiseConfig) -> Result<NoiseStream<S>> {
+    handshake(inner, conf.build(true)?, conf.retransmit).await
+}
+
+/// Perform a handshake as the responder.
+pub async fn respond<S: DatagramStream>(inner: S, conf: &NoiseConfig) -> Result<NoiseStream<S>> {
+    handshake(inner, conf.build(false)?, conf.retransmit).await
+}
+
+async fn handshake<S: DatagramStream>(
+    mut inner: S,
+    mut hs: HandshakeState,
+    retransmit: Duration,
+) -> Result<NoiseStream<S>> {
+    let mut buf = vec![0u8; 0x10000];
+    let mut payload = vec![0u8; 0x10000];
+    // last sent message and last received message
+    let mut last = Vec::new();
+    let mut prev = Vec::new();
+
+    while !hs.is_handshake_finished() {
+        if hs.is_my_turn() {
+            buf[0] = HANDSHAKE;
+            let n = hs.write_message(&[], &mut buf[1..]).map_err(noise_error)?;
+            last = buf[..1 + n].to_vec();
+            inner.send(&last).await?;
+            continue;
+        }
+
+        let n = match timeout(retransmit, inner.recv(&mut buf)).await {
+            Ok(n) => n?,
+            Err(_) => {
+                if !last.is_empty() {
+                    inner.send(&last).await?;
+                }
+                continue;
+            }
+        };
+
+        // EOF
+        if n == 0 {
+            return Err(Error::new(ErrorKind::TimedOut, "handshake timed out"));
+        }
+
+        let pkt = &buf[..n];
+        if pkt[0] != HANDSHAKE {
+            continue;
+        }
+
+        // peer has not received our message
+        if pkt == prev {
+            inner.send(&last).await?;
+            continue;
+        }
+
+        // invalid messages are ignored, the state is not changed
+        if hs.read_message(&pkt[1..], &mut payload).is_ok() {
+            prev = pkt.to_vec();
+        }
+    }
+
+    Ok(NoiseStream {
+        inner,
+        transport: hs.into_stateless_transport_mode().map_err(noise_error)?,
+        nonce: 0,
+        last,
+        prev,
+        resend: false,
+        wbuf: Vec::new(),
+        wbuf_pending: false,
+        rbuf: vec![0u8; 0x10000],
+        plain: vec![0u8; 0x10000],
+        window: ReplayWindow::new(),
+        stats: NoiseStats::default(),
+    })
+}
+
+/// An authenticated and encrypted datagram stream, after a Noise handshake.
+///
+/// Lost handshake messages are resent when reading, best effort, so the
+/// stream should be read after the handshake, even if nothing is expected.
+/// A resend is deferred while a write is pending, and completed before the next write.
+pub struct NoiseStream<S> {
+    inner: S,
+    transport: StatelessTransportState,
+    // handshake
+    last: Vec<u8>,
+    prev: Vec<u8>,
+    resend: bool,
+    // send
+    nonce: u64,
+    wbuf: Vec<u8>,
+    wbuf_pending: bool,
+    // recv
+    rbuf: Vec<u8>,
+    plain: Vec<u8>,
+    window: ReplayWindow,
+    stats: NoiseStats,
+}
+
+impl<S: DatagramStream> NoiseStream<S> {
+    /// Get the peer's static public key.
+    #[inline]
+    pub fn remote_static(&self) -> Option<&[u8]> { self.transport.get_remote_static() }
+
+    /// Get transport statistics.
+    #[inline]
+    pub const fn stats(&self) -> NoiseStats { self.stats }
+
+    /// Get inner stream.
+    #[inline]
+    pub const fn get_ref(&self) -> &S { &self.inner }
+
+    /// Get inner stream.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut S { &mut self.inner }
+
+    /// Resend the last handshake message if the peer asks for it.
+    ///
+    /// It waits for a pending transport packet, which must be sent first.
+    fn poll_resend(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        if !self.resend || self.wbuf_pending {
+            return Poll::Ready(Ok(()));
+        }
+        let res = ready!(self.inner.poll_send(cx, &self.last));
+        self.resend = false;
+        Poll::Ready(res)
+    }
+}
+
+impl<S: DatagramStream> DatagramStream for NoiseStream<S> {
+    fn poll_recv(&mut self, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<Result<()>> {
+        loop {
+            // retried on the next poll if pending
+            if let Poll::Ready(Err(e)) = self.poll_resend(cx) {
+                return Poll::Ready(Err(e));
+            }
+
+            let mut rbuf = ReadBuf::new(&mut self.rbuf);
+            ready!(self.inner.poll_recv(cx, &mut rbuf))?;
+            let n = rbuf.filled().len();
+
+            // EOF
+            if n == 0 {
+                return Poll::Ready(Ok(()));
+            }
+
+            let pkt = &self.rbuf[..n];
+            if pkt[0] == HANDSHAKE && pkt == self.prev {
+                self.resend = true;
+                continue;
+            }
+
+            if pkt[0] != TRANSPORT || n < 9 + TAG {
+                self.stats.dropped += 1;
+                continue;
+            }
+
+            let nonce = u64::from_be_bytes(pkt[1..9].try_into().unwrap());
+            if !self.window.check(nonce) {
+                self.stats.replayed += 1;
+                continue;
+            }
+            let m = match self.transport.read_message(nonce, &pkt[9..], &mut self.plain) {
+                Ok(m) => m,
+                Err(_) => {
+                    self.stats.dropped += 1;
+                    continue;
+                }
+            };
+            self.window.update(nonce);
+
+            // truncate
+            let m = std::cmp::min(m, buf.remaining());
+            buf.put_slice(&self.plain[..m]);
+            return Poll::Ready(Ok(()));
+        }
+    }
+
+    fn poll_send(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<()>> {
+        ready!(self.poll_resend(cx))?;
+
+        if !self.wbuf_pending {
+            self.wbuf.clear();
+            self.wbuf.push(TRANSPORT);
+            self.wbuf.extend_from_slice(&self.nonce.to_be_bytes());
+            self.wbuf.resize(9 + buf.len() + TAG, 0);
+            if let Err(e) = self.transport.write_message(self.nonce, buf, &mut self.wbuf[9..]) {
+                return Poll::Ready(Err(Error::new(ErrorKind::InvalidInput, e)));
+            }
+            self.nonce += 1;
+            self.wbuf_pending = true;
+        }
+
+        let res = ready!(self.inner.poll_send(cx, &self.wbuf));
+        self.wbuf_pending = false;
+        Poll::Ready(res)
+    }
+
+    #[inline]
+    fn reset_timeout(&mut self) { self.inner.reset_timeout() }
+}
+
+/// Accept streams that complete the handshake, as the responder.
+///
+/// A background task continuously polls the listener, and performs handshakes.
+/// It is aborted when the listener is dropped. Requires the tokio runtime.
+#[cfg(feature = "runtime-tokio")]
+pub struct NoiseListener {
+    rx: Mutex<mpsc::Receiver<(NoiseStream<UdpStreamLocal>, SocketAddr)>>,
+    task: JoinHandle<()>,
+}
+
+#[cfg(feature = "runtime-tokio")]
+impl NoiseListener {
+    /// Create from a udp listener, must be called inside a tokio runtime.
+    pub fn new(listener: UdpListener, conf: NoiseConfig) -> Self {
+        let (tx, rx) = mpsc::channel(32);
+        let task = tokio::spawn(async move {
+            let mut buf = vec![0u8; 0x10000];
+            while let Ok((stream, addr)) = listener.accept(&mut buf).await {
+                let tx = tx.clone();
+                let conf = conf.clone();
+                tokio::spawn(async move {
+                    if let Ok(stream) = respond(stream, &conf).await {
+                        let _ = tx.send((stream, addr)).await;
+                    }
+                });
+            }
+        });
+        Self {
+            rx: Mutex::new(rx),
+            task,
+        }
+    }
+
+    /// Accept a new stream, after its handshake is completed.
+    pub async fn accept(&self) -> Result<(NoiseStream<UdpStreamLocal>, SocketAddr)> {
+        let mut rx = self.rx.lock().await;
+        rx.recv().await.ok_or_else(|| Error::new(ErrorKind::BrokenPipe, "listener is closed"))
+    }
+}
+
+#[cfg(feature = "runtime-tokio")]
+impl Drop for NoiseListener {
+    fn drop(&mut self) { self.task.abort(); }
+}
@@ -0,0 +1,429 @@
+//! Traffic obfuscation.
+//!
+//! Datagrams are transformed before they are sent, and restored after they are
+//! received, both sides must use the same transform. A custom transform could be
+//! plugged in by implementing [`Transform`].
+//!
+//! ## Built-in Transform
+//!
+//! With padding enabled, a datagram is wrapped as below, then filled with random bytes.
+//!
+//! ```text
+//! +-----+----------+----------+
+//! | LEN |   DATA   | PADDING  |
+//! +-----+----------+----------+
+//! |  2  | Variable | Variable |
+//! +-----+----------+----------+
+//! ```
+//! LEN is a 16-bit unsigned integer in big endian byte order.
+//!
+//! Then it may be wrapped in a fake header:
+//!
+//! - DNS: a standard query with one `TXT` question, the payload is split into labels of
+//!   the query name.
+//! - QUIC: a 1-RTT packet with a short header and a fixed destination connection id.
+//!
+//! Note that this only defeats simple pattern matching, it does not provide any secrecy.
+//!
+
+use std::io::{Result, Error, ErrorKind};
+use std::ops::Range;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::task::{Context, Poll};
+
+use tokio::io::ReadBuf;
+
+use crate::DatagramStream;
+
+const DNS_HEADER: usize = 12;
+const DNS_LABEL: usize = 63;
+const DNS_TYPE_TXT: u16 = 16;
+const DNS_CLASS_IN: u16 = 1;
+
+/// A reversible transform of datagrams.
+pub trait Transform {
+    /// Transform a datagram, write the result to `out`, which is empty.
+    fn encode(&mut self, buf: &[u8], out: &mut Vec<u8>);
+
+    /// Restore a datagram in place, return its range in `pkt`,
+    /// or `None` if the packet is malformed.
+    fn decode(&mut self, pkt: &mut [u8]) -> Option<Range<usize>>;
+}
+
+/// Padding policy.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum Padding {
+    /// No padding.
+    #[default]
+    None,
+    /// Append `0..=max` random bytes, fewer if the padded datagram would exceed 65535 bytes.
+    Random(usize),
+    /// Pad to the smallest bucket that fits, buckets should be sorted.
+    /// Datagrams larger than all buckets are not padded.
+    Buckets(Vec<usize>),
+}
+
+/// Fake header.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum Mimic {
+    /// No header.
+    #[default]
+    None,
+    /// DNS query.
+    Dns,
+    /// QUIC short header, with a destination connection id of this length, up to 20.
+    Quic(usize),
+}
+
+/// Options of the built-in transform.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ObfsConfig {
+    pub padding: Padding,
+    pub mimic: Mimic,
+}
+
+/// Built-in transform, see the module level documentation.
+pub struct Obfs {
+    conf: ObfsConfig,
+    rng: u64,
+    dcid: Vec<u8>,
+}
+
+impl Obfs {
+    /// Create with options.
+    pub fn new(conf: ObfsConfig) -> Self {
+        let mut rng = RandomState::new().build_hasher().finish() | 1;
+        let dcid = match conf.mimic {
+            Mimic::Quic(n) => {
+                assert!(n <= 20);
+                (0..n).map(|_| xorshift(&mut rng) as u8).collect()
+            }
+            _ => Vec::new(),
+        };
+        Self { conf, rng, dcid }
+    }
+
+    #[inline]
+    fn random(&mut self) -> u64 { xorshift(&mut self.rng) }
+
+    /// Size of the fake header and trailer.
+    fn overhead(&self, len: usize) -> usize {
+        match self.conf.mimic {
+            Mimic::None => 0,
+            // one more byte for each label, a terminator, type and class
+            Mimic::Dns => DNS_HEADER + len.div_ceil(DNS_LABEL) + 1 + 4,
+            Mimic::Quic(n) => 1 + n,
+        }
+    }
+
+    /// Wrap `body` in a fake header.
+    fn wrap(&mut self, body: &[u8], out: &mut Vec<u8>) {
+        match self.conf.mimic {
+            Mimic::None => out.extend_from_slice(body),
+            Mimic::Dns => {
+                let id = self.random() as u16;
+                out.extend_from_slice(&id.to_be_bytes());
+                // standard query, recursion desired, one question
+                out.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+                for label in body.chunks(DNS_LABEL) {
+                    out.push(label.len() as u8);
+                    out.extend_from_slice(label);
+                }
+                out.push(0);
+                out.extend_from_slice(&DNS_TYPE_TXT.to_be_bytes());
+                out.extend_from_slice(&DNS_CLASS_IN.to_be_bytes());
+            }
+            Mimic::Quic(_) => {
+                // header form 0, fixed bit 1, the rest is protected
+                out.push(0x40 | (self.random() as u8 & 0x3f));
+                out.extend_from_slice(&self.dcid);
+                out.extend_from_slice(body);
+            }
+        }
+    }
+
+    /// Strip the fake header in place, return the range of the body.
+    fn unwrap(&self, pkt: &mut [u8]) -> Option<Range<usize>> {
+        match self.conf.mimic {
+            Mimic::None => Some(0..pkt.len()),
+            Mimic::Dns => {
+                if pkt.len() < DNS_HEADER + 5 {
+                    return None;
+                }
+                // join labels of the query name
+                let (mut r, mut w) = (DNS_HEADER, DNS_HEADER);
+                loop {
+                    let len = *pkt.get(r)? as usize;
+                    if len == 0 {
+                        break;
+                    }
+                    if r + 1 + len > pkt.len() {
+                        return None;
+                    }
+                    pkt.copy_within(r + 1..r + 1 + len, w);
+                    r += 1 + len;
+                    w += len;
+                }
+                // type and class
+                if r + 5 != pkt.len() {
+                    return None;
+                }
+                Some(DNS_HEADER..w)
+            }
+            Mimic::Quic(n) => {
+                if pkt.len() < 1 + n || pkt[0] & 0xc0 != 0x40 {
+                    return None;
+                }
+                Some(1 + n..pkt.len())
+            }
+        }
+    }
+
+    /// Number of padding bytes to reach the smallest bucket.
+    fn bucket(&self, buckets: &[usize], len: usize) -> usize {
+        let total = |pad: usize| len + pad + self.overhead(len + pad);
+        let Some(&x) = buckets.iter().find(|&&x| x >= total(0)) else {
+            return 0;
+        };
+        // dns overhead grows with padding
+        let mut pad = x - total(0);
+        while pad > 0 && total(pad) > x {
+            pad -= 1;
+        }
+        pad
+    }
+}
+
+impl Transform for Obfs {
+    fn encode(&mut self, buf: &[u8], out: &mut Vec<u8>) {
+        let pad = match &self.conf.padding {
+            Padding::None => {
+                self.wrap(buf, out);
+                return;
+            }
+            Padding::Random(max) => {
+                let max = std::cmp::min(*max, (u16::MAX as usize).saturating_sub(2 + buf.len()));
+                (self.random() % (max as u64 + 1)) as usize
+            }
+            Padding::Buckets(buckets) => self.bucket(buckets, 2 + buf.len()),
+        };
+
+        let mut body = Vec::with_capacity(2 + buf.len() + pad);
+        body.extend_from_slice(&(buf.len() as u16).to_be_bytes());
+        body.extend_from_slice(buf);
+        body.extend((0..pad).map(|_| self.random() as u8));
+        self.wrap(&body, out);
+    }
+
+    fn decode(&mut self, pkt: &mut [u8]) -> Option<Range<usize>> {
+        let body = self.unwrap(pkt)?;
+        if self.conf.padding == Padding::None {
+            return Some(body);
+        }
+        let len = u16::from_be_bytes(pkt.get(body.start..body.start + 2)?.try_into().unwrap());
+        let start = body.start + 2;
+        let end = start + len as usize;
+        (end <= body.end).then_some(start..end)
+    }
+}
+
+#[inline]
+fn xorshift(x: &mut u64) -> u64 {
+    *x ^= *x << 13;
+    *x ^= *x >> 7;
+    *x ^= *x << 17;
+    *x
+}
+
+/// Apply a [`Transform`] to each datagram, e.g. [`Obfs`].
+///
+/// Sent datagrams are transformed, received ones are restored by the reverse transform.
+/// Packets which could not be restored are silently dropped. `poll_send` fails with
+/// an `InvalidInput` error if a datagram is larger than 65535 bytes.
+pub struct Obfuscated<S, T> {
+    inner: S,
+    transform: T,
+    wbuf: Vec<u8>,
+    wbuf_pending: bool,
+    rbuf: Vec<u8>,
+    malformed: u64,
+}
+
+impl<S: DatagramStream, T: Transform> Obfuscated<S, T> {
+    /// Create from a datagram stream and a transform.
+    pub fn new(inner: S, transform: T) -> Self {
+        Self {
+            inner,
+            transform,
+            wbuf: Vec::new(),
+            wbuf_pending: false,
+            rbuf: vec![0u8; 0x10000],
+            malformed: 0,
+        }
+    }
+
+    /// Number of dropped packets, which could not be restored.
+    #[inline]
+    pub const fn malformed(&self) -> u64 { self.malformed }
+
+    /// Get inner stream.
+    #[inline]
+    pub const fn get_ref(&self) -> &S { &self.inner }
+
+    /// Get inner stream.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut S { &mut self.inner }
+
+    /// Unwrap inner stream.
+    #[inline]
+    pub fn into_inner(self) -> S { self.inner }
+}
+
+impl<S: DatagramStream, T: Transform> DatagramStream for Obfuscated<S, T> {
+    fn poll_recv(&mut self, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<Result<()>> {
+        loop {
+            let mut rbuf = ReadBuf::new(&mut self.rbuf);
+            ready!(self.inner.poll_recv(cx, &mut rbuf))?;
+            let n = rbuf.filled().len();
+
+            // EOF
+            if n == 0 {
+                return Poll::Ready(Ok(()));
+            }
+
+            // truncate
+            match self.transform.decode(&mut self.rbuf[..n]) {
+                Some(range) => {
+                    let n = std::cmp::min(range.len(), buf.remaining());
+                    buf.put_slice(&self.rbuf[range.start..range.start + n]);
+                    return Poll::Ready(Ok(()));
+                }
+                None => self.malformed += 1,
+            }
+        }
+    }
+
+    fn poll_send(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<()>> {
+        if !self.wbuf_pending {
+            // could not be carried by a udp or uot datagram, even before encoding
+            if buf.len() > u16::MAX as usize {
+                return Poll::Ready(Err(Error::new(ErrorKind::InvalidInput, "datagram too large")));
+            }
+            self.wbuf.clear();
+            self.transform.encode(buf, &mut self.wbuf);
+            self.wbuf_pending = true;
+        }
+
+        let res = ready!(self.inner.poll_send(cx, &self.wbuf));
+        self.wbuf_pending = false;
+        Poll::Ready(res)
+    }
+
+    #[inline]
+    fn reset_timeout(&mut self) { self.inner.reset_timeout() }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testutil::Queue;
+
+    fn obfuscated(padding: Padding, mimic: Mimic) -> Obfuscated<Queue, Obfs> {
+        Obfuscated::new(Queue::new(), Obfs::new(ObfsConfig { padding, mimic }))
+    }
+
+    #[tokio::test]
+    async fn roundtrip() {
+        let mut buf = vec![0u8; 0x10000];
+        let paddings = [Padding::None, Padding::Random(64), Padding::Buckets(vec![128, 512, 1400])];
+        let mimics = [Mimic::None, Mimic::Dns, Mimic::Quic(8)];
+        for padding in paddings {
+            for mimic in mimics.clone() {
+                let mut stream = obfuscated(padding.clone(), mimic);
+                for size in [0usize, 1, 63, 64, 200, 1200, 2000] {
+                    let msg: Vec<u8> = (0..size).map(|x| x as u8).collect();
+                    stream.send(&msg).await.unwrap();
+                    let n = stream.recv(&mut buf).await.unwrap();
+                    assert_eq!(&buf[..n], &msg);
+                }
+                assert_eq!(stream.malformed(), 0);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn oversized() {
+        let mut buf = vec![0u8; 0x20000];
+        let mut stream = obfuscated(Padding::Random(64), Mimic::None);
+        let err = stream.send(&buf[..0x10000]).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        assert!(stream.get_ref().0.is_empty());
+
+        // still usable
+        stream.send(&[1u8; 100]).await.unwrap();
+        let n = stream.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], &[1u8; 100]);
+    }
+
+    #[tokio::test]
+    async fn random_max() {
+        let mut buf = vec![0u8; 0x10000];
+        let mut stream = obfuscated(Padding::Random(usize::MAX), Mimic::None);
+        for size in [0usize, 100, 65000, 65533] {
+            let msg = vec![1u8; size];
+            stream.send(&msg).await.unwrap();
+            assert!(stream.get_ref().0[0].len() <= u16::MAX as usize);
+            let n = stream.recv(&mut buf).await.unwrap();
+            assert_eq!(&buf[..n], &msg);
+        }
+    }
+
+    #[tokio::test]
+    async fn bucket() {
+        for mimic in [Mimic::None, Mimic::Dns, Mimic::Quic(8)] {
+            let mut stream = obfuscated(Padding::Buckets(vec![128, 512, 1400]), mimic);
+            for (size, expect) in [(0, 128), (100, 128), (200, 512), (1000, 1400), (1500, 0)] {
+                stream.send(&vec![0u8; size]).await.unwrap();
+                let len = stream.get_mut().0.pop_front().unwrap().len();
+                if expect != 0 {
+                    assert_eq!(len, expect);
+                } else {
+                    assert!(len > size);
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn mimic() {
+        let mut buf = vec![0u8; 0x10000];
+
+        let mut stream = obfuscated(Padding::None, Mimic::Dns);
+        stream.send(&[1u8; 100]).await.unwrap();
+        let pkt = stream.get_ref().0[0].clone();
+        assert_eq!(&pkt[2..12], &[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(pkt[12], 63);
+        assert_eq!(pkt[12 + 64], 37);
+        assert_eq!(&pkt[pkt.len() - 5..], &[0, 0, 16, 0, 1]);
+
+        // truncated label
+        stream.get_mut().0[0].truncate(50);
+        assert_eq!(stream.recv(&mut buf).await.unwrap(), 0);
+        assert_eq!(stream.malformed(), 1);
+
+        let mut stream = obfuscated(Padding::None, Mimic::Quic(8));
+        stream.send(b"Ciallo").await.unwrap();
+        stream.send(b"Ciallo").await.unwrap();
+        let pkt1 = stream.get_ref().0[0].clone();
+        let pkt2 = stream.get_ref().0[1].clone();
+        assert_eq!(pkt1[0] & 0xc0, 0x40);
+        assert_eq!(&pkt1[1..9], &pkt2[1..9]);
+
+        // long header
+        stream.get_mut().0[0][0] |= 0x80;
+        let n = stream.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"Ciallo");
+        assert_eq!(stream.malformed(), 1);
+    }
+}
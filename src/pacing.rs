@@ -0,0 +1,205 @@
+use std::io::{Result, Error, ErrorKind};
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+use std::task::{Context, Poll};
+
+use crate::time::{self, sleep, Sleep, Instant};
+use crate::DatagramSocket;
+
+/// Datagrams scheduled within this period are handed to the kernel with `SO_TXTIME`.
+const TXTIME_HORIZON: Duration = Duration::from_millis(2);
+
+/// Send pacing, with a token bucket.
+///
+/// A write is delayed until there are enough tokens, a datagram
+/// larger than `burst` waits for a full bucket then overdraws it.
+#[derive(Debug, Clone, Copy)]
+pub struct Pacing {
+    /// Bytes per second, `0` turns pacing off.
+    pub rate: u64,
+    /// Size of the bucket in bytes.
+    pub burst: usize,
+    /// Schedule departure with `SO_TXTIME` on Linux, instead of delaying writes.
+    ///
+    /// It falls back to delaying writes if the option could not be set.
+    /// An `fq` or `etf` qdisc is required for the kernel to respect it.
+    pub txtime: bool,
+}
+
+impl Pacing {
+    /// Create with a rate and burst, `SO_TXTIME` is not used.
+    #[inline]
+    pub const fn new(rate: u64, burst: usize) -> Self {
+        Self {
+            rate,
+            burst,
+            txtime: false,
+        }
+    }
+}
+
+pub(crate) struct Pacer {
+    conf: Pacing,
+    tokens: f64,
+    last: Instant,
+    timer: Pin<Box<Sleep>>,
+    txtime: bool,
+}
+
+impl Pacer {
+    /// Return `None` if pacing is off.
+    pub fn new<A, S: DatagramSocket<A> + ?Sized>(conf: Pacing, socket: &S) -> Option<Self> {
+        if conf.rate == 0 {
+            return None;
+        }
+        let txtime = conf.txtime && socket.enable_txtime().is_ok();
+        Some(Self {
+            conf,
+            tokens: conf.burst as f64,
+            last: Instant::now(),
+            timer: Box::pin(sleep(Duration::ZERO)),
+            txtime,
+        })
+    }
+
+    /// Earliest time to send a datagram of `len` bytes.
+    fn departure(&mut self, len: usize, now: Instant) -> Instant {
+        let rate = self.conf.rate as f64;
+        let burst = self.conf.burst as f64;
+        self.tokens = burst.min(self.tokens + (now - self.last).as_secs_f64() * rate);
+        self.last = now;
+
+        let need = std::cmp::min(len, self.conf.burst) as f64;
+        if self.tokens >= need {
+            return now;
+        }
+        now + Duration::from_secs_f64((need - self.tokens) / rate)
+    }
+
+    /// Wait until a datagram could be sent, return its departure time if `SO_TXTIME` is used.
+    pub fn poll_acquire(&mut self, cx: &mut Context<'_>, len: usize) -> Poll<Option<Instant>> {
+        let now = Instant::now();
+        let at = self.departure(len, now);
+        let horizon = if self.txtime { TXTIME_HORIZON } else { Duration::ZERO };
+
+        if at > now + horizon {
+            self.timer.as_mut().reset(at - horizon);
+            if self.timer.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+        }
+        Poll::Ready(self.txtime.then_some(at))
+    }
+
+    /// Consume tokens after a datagram is sent.
+    #[inline]
+    pub fn consume(&mut self, len: usize) { self.tokens -= len as f64; }
+}
+
+/// Send a datagram, reject it if it is larger than `max_size`, and delay it with `pacer`.
+pub(crate) fn poll_send_limited<A, S: DatagramSocket<A> + ?Sized>(
+    socket: &S,
+    pacer: &mut Option<Pacer>,
+    max_size: Option<usize>,
+    cx: &mut Context<'_>,
+    buf: &[u8],
+    addr: &A,
+) -> Poll<Result<usize>> {
+    if max_size.is_some_and(|x| buf.len() > x) {
+        return Poll::Ready(Err(Error::new(ErrorKind::InvalidInput, "datagram too large")));
+    }
+    let at = match pacer.as_mut() {
+        Some(pacer) => ready!(pacer.poll_acquire(cx, buf.len())),
+        None => None,
+    };
+    let n = ready!(poll_send_to(socket, cx, buf, addr, at))?;
+    if let Some(pacer) = pacer.as_mut() {
+        pacer.consume(n);
+    }
+    Poll::Ready(Ok(n))
+}
+
+/// Send a datagram, optionally at the given time.
+fn poll_send_to<A, S: DatagramSocket<A> + ?Sized>(
+    socket: &S,
+    cx: &mut Context<'_>,
+    buf: &[u8],
+    addr: &A,
+    at: Option<Instant>,
+) -> Poll<Result<usize>> {
+    match at {
+        Some(at) => socket.poll_send_at(cx, buf, addr, time::into_std(at)),
+        None => socket.poll_send_to(cx, buf, addr),
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) mod linux {
+    use std::io::{Result, Error};
+    use std::mem::{size_of, zeroed};
+    use std::net::SocketAddr;
+    use std::time::Instant;
+    use std::os::fd::AsRawFd;
+
+    pub fn enable<F: AsRawFd>(fd: &F) -> Result<()> {
+        let conf = libc::sock_txtime {
+            clockid: libc::CLOCK_MONOTONIC,
+            flags: 0,
+        };
+        let ret = unsafe {
+            libc::setsockopt(
+                fd.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_TXTIME,
+                &conf as *const _ as *const libc::c_void,
+                size_of::<libc::sock_txtime>() as libc::socklen_t,
+            )
+        };
+        if ret != 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Convert to `CLOCK_MONOTONIC` nanoseconds.
+    fn monotonic_ns(at: Instant) -> u64 {
+        let mut ts: libc::timespec = unsafe { zeroed() };
+        unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts) };
+        let now = ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64;
+        now + at.saturating_duration_since(Instant::now()).as_nanos() as u64
+    }
+
+    /// Send a packet with `SCM_TXTIME`.
+    pub fn sendmsg<F: AsRawFd>(fd: &F, buf: &[u8], addr: SocketAddr, at: Instant) -> Result<usize> {
+        // aligned for cmsghdr
+        let mut control = [0u64; 4];
+        let mut iov = libc::iovec {
+            iov_base: buf.as_ptr() as *mut libc::c_void,
+            iov_len: buf.len(),
+        };
+
+        let addr = socket2::SockAddr::from(addr);
+        let mut msg: libc::msghdr = unsafe { zeroed() };
+        msg.msg_name = addr.as_ptr() as *mut libc::c_void;
+        msg.msg_namelen = addr.len();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = unsafe { libc::CMSG_SPACE(size_of::<u64>() as u32) } as _;
+
+        unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_TXTIME;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(size_of::<u64>() as u32) as _;
+            std::ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut u64, monotonic_ns(at));
+        }
+
+        let n = unsafe { libc::sendmsg(fd.as_raw_fd(), &msg, 0) };
+        if n < 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(n as usize)
+    }
+}
@@ -0,0 +1,251 @@
+//! Path MTU.
+//!
+//! The path MTU is maintained by the kernel, it is learnt from ICMP
+//! `Fragmentation Needed` or `Packet Too Big` messages, after the DF bit
+//! is set via [`SockOpts::dont_fragment`](super::SockOpts::dont_fragment).
+//! Only Linux is supported.
+//!
+
+use std::io::Result;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use crate::DatagramSocket;
+
+const IPV4_HEADER: usize = 20;
+const IPV6_HEADER: usize = 40;
+const UDP_HEADER: usize = 8;
+
+/// Max udp payload that fits in the MTU.
+#[inline]
+pub const fn max_payload(mtu: usize, ipv6: bool) -> usize {
+    let header = if ipv6 { IPV6_HEADER } else { IPV4_HEADER } + UDP_HEADER;
+    mtu.saturating_sub(header)
+}
+
+/// Get the current path MTU towards `peer`, from local address `laddr`.
+pub fn path_mtu(laddr: IpAddr, peer: SocketAddr) -> Result<usize> {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        linux::path_mtu(&linux::connect(laddr, peer)?, peer.is_ipv6())
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    {
+        let _ = (laddr, peer);
+        Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "not supported on this platform"))
+    }
+}
+
+/// Discover the max datagram size that could be sent to `peer`, up to `max`.
+///
+/// Probes of different sizes are sent with the DF bit set, from a dedicated socket,
+/// then it waits for `wait` to collect ICMP feedback. The size allowed by the current
+/// path MTU is tried first, then sizes down to 548 (IPv4) or 1232 (IPv6) bytes, which
+/// are assumed to fit. Probes are filled with zeros, and are delivered to the peer
+/// if they are small enough, so the peer should be able to ignore them.
+///
+/// **Do not target a udpflow listener with this function.** Probes come from a fresh
+/// ephemeral port, so the listener accepts a spurious session full of zero-filled
+/// packets. Use `probe_mtu` of [`UdpStreamLocal`](crate::UdpStreamLocal) or
+/// [`UdpStreamRemote`](crate::UdpStreamRemote) instead, which probe from the session's
+/// own socket.
+///
+/// Note that black holes, where ICMP messages are filtered, could not be detected.
+pub async fn probe_mtu(
+    laddr: IpAddr,
+    peer: SocketAddr,
+    max: usize,
+    wait: Duration,
+) -> Result<usize> {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        linux::probe_mtu(laddr, peer, max, wait).await
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    {
+        let _ = (laddr, peer, max, wait);
+        Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "not supported on this platform"))
+    }
+}
+
+/// Like [`probe_mtu`], but probes are sent from `socket`, which requires its raw fd.
+///
+/// The DF bit of `socket` is set during probing, then restored, also if the future is dropped.
+pub(crate) async fn probe_mtu_from<S: DatagramSocket + ?Sized>(
+    socket: &S,
+    peer: SocketAddr,
+    max: usize,
+    wait: Duration,
+) -> Result<usize> {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        let fd = socket.as_raw_fd().ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::Unsupported, "socket has no raw fd")
+        })?;
+        linux::probe_mtu_from(fd, socket.local_addr()?.ip(), peer, max, wait).await
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    {
+        let _ = (socket, peer, max, wait);
+        Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "not supported on this platform"))
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod linux {
+    use std::io::{Result, ErrorKind};
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+    use std::time::Duration;
+    use std::os::fd::{BorrowedFd, RawFd};
+
+    use socket2::{SockAddr, SockRef};
+
+    use crate::time::sleep;
+
+    use super::max_payload;
+    use crate::sockopt::linux::{getsockopt, setsockopt, set_dont_fragment};
+
+    // payload of the minimum datagram which every host accepts, 576 or 1280 bytes
+    const IPV4_FLOOR: usize = 548;
+    const IPV6_FLOOR: usize = 1232;
+    // a probe is refused when an earlier one has reached a closed port
+    const MAX_REFUSED: usize = 4;
+
+    /// Restore a socket option, when dropped or by `restore`.
+    struct Restore {
+        fd: RawFd,
+        level: i32,
+        name: i32,
+        prev: Option<i32>,
+    }
+
+    impl Restore {
+        fn restore(&mut self) -> Result<()> {
+            match self.prev.take() {
+                Some(prev) => setsockopt(&self.fd, self.level, self.name, prev),
+                None => Ok(()),
+            }
+        }
+    }
+
+    impl Drop for Restore {
+        fn drop(&mut self) { let _ = self.restore(); }
+    }
+
+    /// A connected socket, which is required by `IP_MTU`.
+    pub fn connect(laddr: IpAddr, peer: SocketAddr) -> Result<UdpSocket> {
+        let laddr = match (laddr, peer) {
+            (IpAddr::V4(_), SocketAddr::V4(_)) | (IpAddr::V6(_), SocketAddr::V6(_)) => laddr,
+            (_, SocketAddr::V4(_)) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            (_, SocketAddr::V6(_)) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+        let socket = UdpSocket::bind((laddr, 0))?;
+        socket.connect(peer)?;
+        Ok(socket)
+    }
+
+    pub fn path_mtu(socket: &UdpSocket, ipv6: bool) -> Result<usize> {
+        let mtu = if ipv6 {
+            getsockopt(socket, libc::IPPROTO_IPV6, libc::IPV6_MTU)?
+        } else {
+            getsockopt(socket, libc::IPPROTO_IP, libc::IP_MTU)?
+        };
+        Ok(mtu as usize)
+    }
+
+    pub async fn probe_mtu(
+        laddr: IpAddr,
+        peer: SocketAddr,
+        max: usize,
+        wait: Duration,
+    ) -> Result<usize> {
+        let ipv6 = peer.is_ipv6();
+        let socket = connect(laddr, peer)?;
+        socket.set_nonblocking(true)?;
+        set_dont_fragment(&socket, ipv6, true)?;
+
+        probe(|buf| socket.send(buf).map(|_| ()), &socket, ipv6, max, wait).await
+    }
+
+    pub async fn probe_mtu_from(
+        fd: RawFd,
+        laddr: IpAddr,
+        peer: SocketAddr,
+        max: usize,
+        wait: Duration,
+    ) -> Result<usize> {
+        let ipv6 = peer.is_ipv6();
+        let (level, name) = if ipv6 {
+            (libc::IPPROTO_IPV6, libc::IPV6_MTU_DISCOVER)
+        } else {
+            (libc::IPPROTO_IP, libc::IP_MTU_DISCOVER)
+        };
+        // the path mtu is shared, read it from a connected socket
+        let conn = connect(laddr, peer)?;
+        let prev = getsockopt(&fd, level, name)?;
+        let mut restore = Restore {
+            fd,
+            level,
+            name,
+            prev: Some(prev),
+        };
+        set_dont_fragment(&fd, ipv6, true)?;
+
+        let fd = unsafe { BorrowedFd::borrow_raw(fd) };
+        let socket = SockRef::from(&fd);
+        let peer = SockAddr::from(peer);
+        let res = probe(|buf| socket.send_to(buf, &peer).map(|_| ()), &conn, ipv6, max, wait).await;
+
+        restore.restore()?;
+        res
+    }
+
+    async fn probe<F>(
+        mut send: F,
+        conn: &UdpSocket,
+        ipv6: bool,
+        max: usize,
+        wait: Duration,
+    ) -> Result<usize>
+    where
+        F: FnMut(&[u8]) -> Result<()>,
+    {
+        let probe = vec![0u8; max];
+        let mut hi = std::cmp::min(max, max_payload(path_mtu(conn, ipv6)?, ipv6));
+        let mut lo = std::cmp::min(hi, if ipv6 { IPV6_FLOOR } else { IPV4_FLOOR });
+        let mut refused = 0;
+
+        // the known path mtu is likely right
+        let mut mid = hi;
+        while lo < hi {
+            match send(&probe[..mid]) {
+                Ok(_) => {}
+                // exceeds the known path mtu
+                Err(e) if e.raw_os_error() == Some(libc::EMSGSIZE) => {
+                    hi = mid - 1;
+                    mid = (lo + hi).div_ceil(2);
+                    continue;
+                }
+                // a previous probe has reached the peer, retry
+                Err(e) if e.kind() == ErrorKind::ConnectionRefused && refused < MAX_REFUSED => {
+                    refused += 1;
+                    continue;
+                }
+                // peer is not reachable, or the socket buffer is full
+                Err(e) => return Err(e),
+            }
+
+            // collect icmp feedback
+            sleep(wait).await;
+            let limit = max_payload(path_mtu(conn, ipv6)?, ipv6);
+            if mid <= limit {
+                lo = mid;
+            }
+            hi = std::cmp::min(hi, limit);
+            lo = std::cmp::min(lo, hi);
+            mid = (lo + hi).div_ceil(2);
+        }
+
+        Ok(lo)
+    }
+}
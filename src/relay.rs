@@ -0,0 +1,146 @@
+use std::io::Error;
+use std::future::{Future, poll_fn};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::io::ReadBuf;
+
+use crate::time::{sleep, Sleep, Instant};
+use crate::{get_timeout, DatagramStream};
+
+const BUFFER_SIZE: usize = 0x10000;
+
+/// Traffic of one direction.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Traffic {
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+/// Why a relay is closed.
+#[derive(Debug)]
+pub enum CloseReason {
+    /// No traffic in either direction during a period of time.
+    Timeout,
+    /// `a` reached `EOF`.
+    EofA,
+    /// `b` reached `EOF`.
+    EofB,
+    /// An io error occurred.
+    Error(Error),
+}
+
+/// Result of [`relay`].
+#[derive(Debug)]
+pub struct RelayStats {
+    pub a_to_b: Traffic,
+    pub b_to_a: Traffic,
+    pub reason: CloseReason,
+}
+
+struct Half {
+    buf: Box<[u8]>,
+    len: Option<usize>,
+    traffic: Traffic,
+}
+
+impl Half {
+    fn new() -> Self {
+        Self {
+            buf: vec![0u8; BUFFER_SIZE].into_boxed_slice(),
+            len: None,
+            traffic: Traffic::default(),
+        }
+    }
+
+    /// Move datagrams from `src` to `dst`, return whether there is any progress.
+    fn copy<A, B>(
+        &mut self,
+        cx: &mut Context<'_>,
+        src: &mut A,
+        dst: &mut B,
+        eof: CloseReason,
+    ) -> Result<bool, CloseReason>
+    where
+        A: DatagramStream,
+        B: DatagramStream,
+    {
+        let mut progress = false;
+        loop {
+            let n = match self.len {
+                Some(n) => n,
+                None => {
+                    let mut buf = ReadBuf::new(&mut self.buf);
+                    match src.poll_recv(cx, &mut buf) {
+                        Poll::Ready(Ok(())) if buf.filled().is_empty() => return Err(eof),
+                        Poll::Ready(Ok(())) => buf.filled().len(),
+                        Poll::Ready(Err(e)) => return Err(CloseReason::Error(e)),
+                        Poll::Pending => break,
+                    }
+                }
+            };
+            self.len = Some(n);
+
+            match dst.poll_send(cx, &self.buf[..n]) {
+                Poll::Ready(Ok(())) => {
+                    self.len = None;
+                    self.traffic.packets += 1;
+                    self.traffic.bytes += n as u64;
+                    progress = true;
+                }
+                Poll::Ready(Err(e)) => return Err(CloseReason::Error(e)),
+                Poll::Pending => break,
+            }
+        }
+        Ok(progress)
+    }
+}
+
+/// Relay datagrams between two streams, until either side is closed.
+///
+/// Datagram boundaries are preserved. Both directions share the same
+/// idle timeout (see [`set_timeout`](super::set_timeout)), which is reset by
+/// any traffic in either direction, so that a one-way flow is kept alive.
+pub async fn relay<A, B>(mut a: A, mut b: B) -> RelayStats
+where
+    A: DatagramStream,
+    B: DatagramStream,
+{
+    let mut timeout: Pin<Box<Sleep>> = Box::pin(sleep(get_timeout()));
+    let mut a_to_b = Half::new();
+    let mut b_to_a = Half::new();
+
+    let reason = poll_fn(|cx| {
+        // the shared timer always expires ahead of streams' own ones
+        if timeout.as_mut().poll(cx).is_ready() {
+            return Poll::Ready(CloseReason::Timeout);
+        }
+
+        let progress = match a_to_b.copy(cx, &mut a, &mut b, CloseReason::EofA) {
+            Ok(x) => x,
+            Err(reason) => return Poll::Ready(reason),
+        } | match b_to_a.copy(cx, &mut b, &mut a, CloseReason::EofB) {
+            Ok(x) => x,
+            Err(reason) => return Poll::Ready(reason),
+        };
+
+        if progress {
+            // reset timer
+            timeout.as_mut().reset(Instant::now() + get_timeout());
+            a.reset_timeout();
+            b.reset_timeout();
+
+            // register the new deadline
+            let _ = timeout.as_mut().poll(cx);
+        }
+
+        Poll::Pending
+    })
+    .await;
+
+    RelayStats {
+        a_to_b: a_to_b.traffic,
+        b_to_a: b_to_a.traffic,
+        reason,
+    }
+}
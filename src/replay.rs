@@ -0,0 +1,69 @@
+const WINDOW: u64 = 1024;
+
+/// Sliding window of received counters.
+pub(crate) struct ReplayWindow {
+    top: Option<u64>,
+    bits: [u64; (WINDOW / 64) as usize],
+}
+
+impl ReplayWindow {
+    pub fn new() -> Self {
+        Self {
+            top: None,
+            bits: [0; (WINDOW / 64) as usize],
+        }
+    }
+
+    #[inline]
+    fn bit(n: u64) -> (usize, u64) { (((n % WINDOW) / 64) as usize, 1 << (n % 64)) }
+
+    /// Whether a counter is neither seen nor too old.
+    pub fn check(&self, n: u64) -> bool {
+        match self.top {
+            None => true,
+            Some(top) if n > top => true,
+            Some(top) if top - n >= WINDOW => false,
+            Some(_) => {
+                let (i, mask) = Self::bit(n);
+                self.bits[i] & mask == 0
+            }
+        }
+    }
+
+    /// Mark a counter, which has passed `check`.
+    pub fn update(&mut self, n: u64) {
+        match self.top {
+            Some(top) if n <= top => {}
+            Some(top) if n - top < WINDOW => {
+                for x in top + 1..n {
+                    let (i, mask) = Self::bit(x);
+                    self.bits[i] &= !mask;
+                }
+                self.top = Some(n);
+            }
+            _ => {
+                self.bits = [0; (WINDOW / 64) as usize];
+                self.top = Some(n);
+            }
+        }
+        let (i, mask) = Self::bit(n);
+        self.bits[i] |= mask;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn replay_window() {
+        let mut window = ReplayWindow::new();
+        for n in [100, 98, 99, 2000, 1999, 977] {
+            assert!(window.check(n));
+            window.update(n);
+            assert!(!window.check(n));
+        }
+        assert!(!window.check(976));
+        assert!(window.check(1000));
+    }
+}
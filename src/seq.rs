@@ -0,0 +1,320 @@
+//! In-order delivery without retransmission.
+//!
+//! ## Protocol Specification
+//!
+//! ```text
+//! +-----+----------+
+//! | SEQ |   DATA   |
+//! +-----+----------+
+//! |  4  | Variable |
+//! +-----+----------+
+//! ```
+//! SEQ is a 32-bit unsigned integer in big endian byte order, which starts from 0
+//! and increases by 1 for each datagram.
+//!
+
+use std::io::Result;
+use std::collections::{BTreeMap, VecDeque};
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+use std::task::{Context, Poll};
+
+use tokio::io::ReadBuf;
+
+use crate::time::{sleep, Sleep, Instant};
+use crate::DatagramStream;
+use crate::util::extend_seq;
+
+const HEADER: usize = 4;
+
+/// Reordering options.
+#[derive(Debug, Clone, Copy)]
+pub struct SeqConfig {
+    /// Max distance between the next expected datagram and the buffered ones.
+    pub window: usize,
+    /// Give up waiting for a missing datagram after this period.
+    pub timeout: Duration,
+}
+
+impl Default for SeqConfig {
+    fn default() -> Self {
+        Self {
+            window: 64,
+            timeout: Duration::from_millis(50),
+        }
+    }
+}
+
+/// Sequencing statistics.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SeqStats {
+    /// Datagrams skipped, which did not arrive in time.
+    pub lost: u64,
+    /// Datagrams that arrived out of order, and were buffered.
+    pub reordered: u64,
+    /// Datagrams dropped as duplicates.
+    pub duplicate: u64,
+    /// Datagrams dropped since they arrived after being skipped.
+    pub late: u64,
+    /// Malformed packets.
+    pub malformed: u64,
+}
+
+/// Stamp a sequence number on each datagram, deliver them in order.
+///
+/// Duplicated and late datagrams are dropped. Out of order datagrams are held
+/// until the missing ones arrive, or they are skipped after a timeout, or when
+/// the window is exceeded. Buffered datagrams are flushed on EOF.
+pub struct Sequenced<S> {
+    inner: S,
+    conf: SeqConfig,
+    // send
+    seq: u32,
+    wbuf: Vec<u8>,
+    // recv
+    rbuf: Vec<u8>,
+    next: u64,
+    buffer: BTreeMap<u64, (Instant, Vec<u8>)>,
+    skipped: VecDeque<u64>,
+    timer: Pin<Box<Sleep>>,
+    eof: bool,
+    stats: SeqStats,
+}
+
+impl<S: DatagramStream> Sequenced<S> {
+    /// Create from a datagram stream.
+    pub fn new(inner: S, conf: SeqConfig) -> Self {
+        assert!(conf.window > 0);
+        Self {
+            inner,
+            conf,
+            seq: 0,
+            wbuf: Vec::new(),
+            rbuf: vec![0u8; 0x10000],
+            next: 0,
+            buffer: BTreeMap::new(),
+            skipped: VecDeque::new(),
+            timer: Box::pin(sleep(Duration::ZERO)),
+            eof: false,
+            stats: SeqStats::default(),
+        }
+    }
+
+    /// Get sequencing statistics.
+    #[inline]
+    pub const fn stats(&self) -> SeqStats { self.stats }
+
+    /// Get inner stream.
+    #[inline]
+    pub const fn get_ref(&self) -> &S { &self.inner }
+
+    /// Get inner stream.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut S { &mut self.inner }
+
+    /// Unwrap inner stream.
+    #[inline]
+    pub fn into_inner(self) -> S { self.inner }
+
+    /// Skip missing datagrams before `seq`.
+    fn skip_to(&mut self, seq: u64) {
+        while self.next < seq {
+            if !self.buffer.contains_key(&self.next) {
+                self.stats.lost += 1;
+                self.skipped.push_back(self.next);
+                if self.skipped.len() > self.conf.window {
+                    self.skipped.pop_front();
+                }
+            }
+            self.next += 1;
+        }
+    }
+
+    /// Pop the next datagram, if it is available.
+    fn pop(&mut self) -> Option<Vec<u8>> {
+        let (_, pkt) = self.buffer.remove(&self.next)?;
+        self.next += 1;
+        Some(pkt)
+    }
+
+    /// Store a datagram, return it if it is the next one.
+    fn on_packet(&mut self, seq: u32, data: &[u8]) -> Option<Vec<u8>> {
+        let seq = match extend_seq(self.next, seq) {
+            Some(seq) if seq >= self.next => seq,
+            Some(seq) if self.skipped.contains(&seq) => {
+                self.stats.late += 1;
+                return None;
+            }
+            _ => {
+                self.stats.duplicate += 1;
+                return None;
+            }
+        };
+
+        if seq == self.next {
+            self.next += 1;
+            return Some(data.to_vec());
+        }
+
+        if self.buffer.contains_key(&seq) {
+            self.stats.duplicate += 1;
+            return None;
+        }
+
+        // exceeds the window
+        if seq - self.next >= self.conf.window as u64 {
+            self.skip_to(seq + 1 - self.conf.window as u64);
+        }
+        self.stats.reordered += 1;
+        self.buffer.insert(seq, (Instant::now(), data.to_vec()));
+        None
+    }
+}
+
+impl<S: DatagramStream> DatagramStream for Sequenced<S> {
+    fn poll_recv(&mut self, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<Result<()>> {
+        loop {
+            // truncate
+            if let Some(pkt) = self.pop() {
+                let n = std::cmp::min(pkt.len(), buf.remaining());
+                buf.put_slice(&pkt[..n]);
+                return Poll::Ready(Ok(()));
+            }
+
+            // flush, then EOF
+            if self.eof {
+                match self.buffer.keys().next() {
+                    Some(&seq) => {
+                        self.skip_to(seq);
+                        continue;
+                    }
+                    None => return Poll::Ready(Ok(())),
+                }
+            }
+
+            // give up the missing datagrams
+            if let Some(oldest) = self.buffer.values().map(|(x, _)| *x).min() {
+                self.timer.as_mut().reset(oldest + self.conf.timeout);
+                if self.timer.as_mut().poll(cx).is_ready() {
+                    let seq = *self.buffer.keys().next().unwrap();
+                    self.skip_to(seq);
+                    continue;
+                }
+            }
+
+            let mut rbuf = ReadBuf::new(&mut self.rbuf);
+            ready!(self.inner.poll_recv(cx, &mut rbuf))?;
+            let n = rbuf.filled().len();
+
+            if n == 0 {
+                self.eof = true;
+                continue;
+            }
+
+            if n < HEADER {
+                self.stats.malformed += 1;
+                continue;
+            }
+
+            let seq = u32::from_be_bytes(self.rbuf[..4].try_into().unwrap());
+            let data = self.rbuf[HEADER..n].to_vec();
+            if let Some(pkt) = self.on_packet(seq, &data) {
+                let n = std::cmp::min(pkt.len(), buf.remaining());
+                buf.put_slice(&pkt[..n]);
+                return Poll::Ready(Ok(()));
+            }
+        }
+    }
+
+    fn poll_send(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<()>> {
+        self.wbuf.clear();
+        self.wbuf.extend_from_slice(&self.seq.to_be_bytes());
+        self.wbuf.extend_from_slice(buf);
+        ready!(self.inner.poll_send(cx, &self.wbuf))?;
+        self.seq = self.seq.wrapping_add(1);
+        Poll::Ready(Ok(()))
+    }
+
+    #[inline]
+    fn reset_timeout(&mut self) { self.inner.reset_timeout() }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::testutil::Queue;
+
+    async fn sequenced(n: u8, order: &[usize]) -> Sequenced<Queue> {
+        let mut stream = Sequenced::new(Queue(VecDeque::new(), false), SeqConfig::default());
+        for i in 0..n {
+            stream.send(&[i]).await.unwrap();
+        }
+        let sent: Vec<Vec<u8>> = stream.get_mut().0.drain(..).collect();
+        stream.get_mut().0.extend(order.iter().map(|&i| sent[i].clone()));
+        stream
+    }
+
+    async fn recv(stream: &mut Sequenced<Queue>) -> u8 {
+        let mut buf = [0u8; 16];
+        let n = stream.recv(&mut buf).await.unwrap();
+        assert_eq!(n, 1);
+        buf[0]
+    }
+
+    #[tokio::test]
+    async fn reorder() {
+        let mut stream = sequenced(4, &[1, 0, 0, 3, 2, 1]).await;
+        for i in 0..4 {
+            assert_eq!(recv(&mut stream).await, i);
+        }
+
+        // nothing left
+        let mut buf = [0u8; 16];
+        let res = tokio::time::timeout(Duration::from_millis(100), stream.recv(&mut buf)).await;
+        assert!(res.is_err());
+
+        let stats = stream.stats();
+        assert_eq!(stats.reordered, 2);
+        assert_eq!(stats.duplicate, 2);
+        assert_eq!(stats.lost, 0);
+    }
+
+    #[tokio::test]
+    async fn skip() {
+        // 2 is late
+        let mut stream = sequenced(5, &[0, 1, 3, 4]).await;
+        for i in [0, 1, 3, 4] {
+            assert_eq!(recv(&mut stream).await, i);
+        }
+        stream.get_mut().0.push_back(vec![0, 0, 0, 2, 2]);
+        stream.get_mut().1 = true;
+        let mut buf = [0u8; 16];
+        assert_eq!(stream.recv(&mut buf).await.unwrap(), 0);
+        assert_eq!(stream.stats().lost, 1);
+        assert_eq!(stream.stats().late, 1);
+
+        // exceeds the window
+        let window = SeqConfig::default().window as u8;
+        let mut order: Vec<usize> = (1..=window as usize).collect();
+        order.push(0);
+        let mut stream = sequenced(window + 1, &order).await;
+        for i in 1..=window {
+            assert_eq!(recv(&mut stream).await, i);
+        }
+        assert_eq!(stream.stats().lost, 1);
+        assert_eq!(stream.stats().late, 0);
+    }
+
+    #[tokio::test]
+    async fn flush_on_eof() {
+        let mut stream = sequenced(4, &[0, 2, 3]).await;
+        stream.get_mut().1 = true;
+        for i in [0, 2, 3] {
+            assert_eq!(recv(&mut stream).await, i);
+        }
+        let mut buf = [0u8; 16];
+        assert_eq!(stream.recv(&mut buf).await.unwrap(), 0);
+        assert_eq!(stream.stats().lost, 1);
+    }
+}
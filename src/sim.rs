@@ -0,0 +1,253 @@
+//! In-memory simulated network.
+//!
+//! Requires the `sim` feature.
+//!
+//! A [`Network`] hosts virtual datagram sockets, which implement [`DatagramSocket`],
+//! so they plug into [`UdpListener`](crate::UdpListener), [`UdpStreamLocal`](crate::UdpStreamLocal)
+//! and [`UdpStreamRemote`](crate::UdpStreamRemote). Packets are delivered by timers of the
+//! runtime, with tokio this works with `tokio::time::pause`. With a fixed seed,
+//! a single-threaded runtime and paused time, a run is deterministic.
+//!
+//! ```
+//! use std::time::Duration;
+//! use udpflow::UdpStreamRemote;
+//! use udpflow::sim::{Network, Link};
+//! async {
+//!     let net = Network::new(42);
+//!     net.set_default_link(Link {
+//!         latency: Duration::from_millis(20),
+//!         loss: 0.01,
+//!         ..Link::default()
+//!     });
+//!     let socket = net.bind("10.0.0.1:5000".parse().unwrap()).unwrap();
+//!     let stream = UdpStreamRemote::new(socket, "10.0.0.2:10000".parse().unwrap());
+//! };
+//! ```
+//!
+
+use std::io::{Result, Error, ErrorKind};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::collections::{BinaryHeap, HashMap};
+use std::cmp::Reverse;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+use std::task::{Context, Poll, Waker};
+
+use tokio::io::ReadBuf;
+
+use crate::time::{sleep, Sleep, Instant};
+use crate::DatagramSocket;
+
+const EPHEMERAL_PORT: u16 = 49152;
+
+/// Conditions of a one-way link.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Link {
+    /// Base one-way delay.
+    pub latency: Duration,
+    /// Extra delay, chosen uniformly from `0..=jitter` for each packet.
+    pub jitter: Duration,
+    /// Probability that a packet is dropped.
+    pub loss: f64,
+    /// Probability that a packet is delivered twice.
+    pub duplicate: f64,
+    /// Probability that a packet is held back by `reorder_delay`,
+    /// so that later packets overtake it.
+    pub reorder: f64,
+    /// Extra delay of reordered packets.
+    pub reorder_delay: Duration,
+}
+
+impl Default for Link {
+    fn default() -> Self {
+        Self {
+            latency: Duration::ZERO,
+            jitter: Duration::ZERO,
+            loss: 0.0,
+            duplicate: 0.0,
+            reorder: 0.0,
+            reorder_delay: Duration::from_millis(10),
+        }
+    }
+}
+
+/// Arrival time, sequence, payload and sender of a packet.
+type Arrival = (Instant, u64, Vec<u8>, SocketAddr);
+
+/// Packets waiting to be received, ordered by arrival time.
+struct Inbox {
+    queue: BinaryHeap<Reverse<Arrival>>,
+    timer: Pin<Box<Sleep>>,
+    waker: Option<Waker>,
+}
+
+struct State {
+    sockets: HashMap<SocketAddr, Arc<Mutex<Inbox>>>,
+    links: HashMap<(SocketAddr, SocketAddr), Link>,
+    default_link: Link,
+    rng: u64,
+    seq: u64,
+}
+
+impl State {
+    #[inline]
+    fn random(&mut self) -> f64 {
+        // xorshift
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Arrival times of a packet, empty if it is lost.
+    fn schedule(&mut self, link: &Link, now: Instant) -> Vec<Instant> {
+        if self.random() < link.loss {
+            return Vec::new();
+        }
+        let copies = if self.random() < link.duplicate { 2 } else { 1 };
+        (0..copies)
+            .map(|_| {
+                let mut delay = link.latency + link.jitter.mul_f64(self.random());
+                if self.random() < link.reorder {
+                    delay += link.reorder_delay;
+                }
+                now + delay
+            })
+            .collect()
+    }
+}
+
+/// A virtual network of datagram sockets.
+///
+/// It is cheap to clone, clones refer to the same network.
+#[derive(Clone)]
+pub struct Network(Arc<Mutex<State>>);
+
+impl Network {
+    /// Create an empty network, `seed` determines the random conditions of links.
+    pub fn new(seed: u64) -> Self {
+        Self(Arc::new(Mutex::new(State {
+            sockets: HashMap::new(),
+            links: HashMap::new(),
+            default_link: Link::default(),
+            rng: seed | 1,
+            seq: 0,
+        })))
+    }
+
+    /// Set conditions of links which are not configured by [`set_link`](Self::set_link).
+    pub fn set_default_link(&self, link: Link) { self.0.lock().unwrap().default_link = link; }
+
+    /// Set conditions of the link from `src` to `dst`, the other direction is not affected.
+    pub fn set_link(&self, src: SocketAddr, dst: SocketAddr, link: Link) {
+        self.0.lock().unwrap().links.insert((src, dst), link);
+    }
+
+    /// Bind a socket to `addr`, an ephemeral port is chosen if the port is 0.
+    pub fn bind(&self, mut addr: SocketAddr) -> Result<SimSocket> {
+        let mut state = self.0.lock().unwrap();
+        if addr.port() == 0 {
+            let port = (EPHEMERAL_PORT..=u16::MAX)
+                .find(|&port| !state.sockets.contains_key(&SocketAddr::new(addr.ip(), port)))
+                .ok_or_else(|| Error::new(ErrorKind::AddrInUse, "no ephemeral port"))?;
+            addr.set_port(port);
+        }
+        if state.sockets.contains_key(&addr) {
+            return Err(Error::new(ErrorKind::AddrInUse, "address in use"));
+        }
+
+        let inbox = Arc::new(Mutex::new(Inbox {
+            queue: BinaryHeap::new(),
+            timer: Box::pin(sleep(Duration::ZERO)),
+            waker: None,
+        }));
+        state.sockets.insert(addr, inbox.clone());
+        Ok(SimSocket {
+            net: self.clone(),
+            addr,
+            inbox,
+        })
+    }
+}
+
+/// A virtual datagram socket, which is unbound when dropped.
+pub struct SimSocket {
+    net: Network,
+    addr: SocketAddr,
+    inbox: Arc<Mutex<Inbox>>,
+}
+
+impl SimSocket {
+    /// Get the network it belongs to.
+    #[inline]
+    pub const fn network(&self) -> &Network { &self.net }
+}
+
+impl Drop for SimSocket {
+    fn drop(&mut self) { self.net.0.lock().unwrap().sockets.remove(&self.addr); }
+}
+
+impl DatagramSocket for SimSocket {
+    fn poll_recv_from(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<SocketAddr>> {
+        let mut inbox = self.inbox.lock().unwrap();
+        let now = Instant::now();
+        let at = match inbox.queue.peek() {
+            Some(Reverse((at, ..))) if *at <= now => {
+                let Reverse((_, _, pkt, from)) = inbox.queue.pop().unwrap();
+
+                // truncate
+                let n = std::cmp::min(pkt.len(), buf.remaining());
+                buf.put_slice(&pkt[..n]);
+                return Poll::Ready(Ok(from));
+            }
+            Some(Reverse((at, ..))) => Some(*at),
+            None => None,
+        };
+
+        // wait for the next arrival, or a new packet
+        if let Some(at) = at {
+            inbox.timer.as_mut().reset(at);
+            if inbox.timer.as_mut().poll(cx).is_ready() {
+                cx.waker().wake_by_ref();
+            }
+        }
+        inbox.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    fn poll_send_to(
+        &self,
+        _: &mut Context<'_>,
+        buf: &[u8],
+        target: &SocketAddr,
+    ) -> Poll<Result<usize>> {
+        let mut state = self.net.0.lock().unwrap();
+        let link = state.links.get(&(self.addr, *target)).copied().unwrap_or(state.default_link);
+
+        // unreachable, silently dropped
+        let Some(inbox) = state.sockets.get(target).cloned() else {
+            return Poll::Ready(Ok(buf.len()));
+        };
+
+        let now = Instant::now();
+        let arrivals = state.schedule(&link, now);
+        let mut inbox = inbox.lock().unwrap();
+        for at in arrivals {
+            state.seq += 1;
+            inbox.queue.push(Reverse((at, state.seq, buf.to_vec(), self.addr)));
+        }
+        if let Some(waker) = inbox.waker.take() {
+            waker.wake();
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    #[inline]
+    fn local_addr(&self) -> Result<SocketAddr> { Ok(self.addr) }
+}
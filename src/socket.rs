@@ -0,0 +1,210 @@
+use std::io::{Result, Error, ErrorKind};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
+use std::task::{Context, Poll};
+#[cfg(unix)]
+use std::os::fd::RawFd;
+
+use tokio::io::ReadBuf;
+
+#[cfg(feature = "runtime-tokio")]
+use tokio::net::UdpSocket;
+
+use crate::RecvMeta;
+
+/// Datagram socket, the backend of [`UdpListener`](super::UdpListener),
+/// [`UdpStreamLocal`](super::UdpStreamLocal) and [`UdpStreamRemote`](super::UdpStreamRemote).
+///
+/// It is implemented for tokio's `UdpSocket`, and async-io's `Async<UdpSocket>` which is
+/// used by smol and async-std, see the `async_io` module. The default is tokio's
+/// [`UdpSocket`](super::UdpSocket). Implement it to plug in another socket, e.g. io_uring,
+/// a userspace stack or a mock.
+///
+/// It is generic over the address type `A`, which is `SocketAddr` for udp sockets,
+/// and `PathBuf` for unix datagram sockets.
+///
+/// Optional capabilities, such as [`RecvMeta`] and `SO_TXTIME` pacing, have default
+/// fallbacks. A backend overrides them if it supports the feature.
+pub trait DatagramSocket<A = SocketAddr> {
+    /// Attempt to receive a datagram, return the sender's address.
+    fn poll_recv_from(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<A>>;
+
+    /// Attempt to send a datagram to `target`, return the number of bytes sent.
+    fn poll_send_to(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+        target: &A,
+    ) -> Poll<Result<usize>>;
+
+    /// Get local sockaddr.
+    fn local_addr(&self) -> Result<A>;
+
+    /// Like `poll_recv_from`, also capture the metadata of the datagram.
+    ///
+    /// The default leaves the metadata empty.
+    #[inline]
+    fn poll_recv_meta(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(A, RecvMeta)>> {
+        self.poll_recv_from(cx, buf).map_ok(|addr| (addr, RecvMeta::default()))
+    }
+
+    /// Prepare the socket for [`poll_send_at`](Self::poll_send_at), e.g. set `SO_TXTIME`.
+    ///
+    /// The default is `Unsupported`, then pacing falls back to delaying writes.
+    #[inline]
+    fn enable_txtime(&self) -> Result<()> {
+        Err(Error::new(ErrorKind::Unsupported, "txtime is not supported"))
+    }
+
+    /// Like `poll_send_to`, but ask the socket to send the datagram at `at`.
+    ///
+    /// This is only called after [`enable_txtime`](Self::enable_txtime) succeeds.
+    /// The default sends it immediately.
+    #[inline]
+    fn poll_send_at(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+        target: &A,
+        at: Instant,
+    ) -> Poll<Result<usize>> {
+        let _ = at;
+        self.poll_send_to(cx, buf, target)
+    }
+
+    /// Get the raw fd, for socket options that are not covered by this trait.
+    ///
+    /// Path MTU probing requires it. The default is `None`.
+    #[cfg(unix)]
+    #[inline]
+    fn as_raw_fd(&self) -> Option<RawFd> { None }
+}
+
+#[cfg(feature = "runtime-tokio")]
+impl DatagramSocket for UdpSocket {
+    #[inline]
+    fn poll_recv_from(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<SocketAddr>> {
+        UdpSocket::poll_recv_from(self, cx, buf)
+    }
+
+    #[inline]
+    fn poll_send_to(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+        target: &SocketAddr,
+    ) -> Poll<Result<usize>> {
+        UdpSocket::poll_send_to(self, cx, buf, *target)
+    }
+
+    #[inline]
+    fn local_addr(&self) -> Result<SocketAddr> { UdpSocket::local_addr(self) }
+
+    #[cfg(unix)]
+    #[inline]
+    fn as_raw_fd(&self) -> Option<RawFd> { Some(std::os::fd::AsRawFd::as_raw_fd(self)) }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn poll_recv_meta(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(SocketAddr, RecvMeta)>> {
+        use tokio::io::Interest;
+        loop {
+            ready!(self.poll_recv_ready(cx))?;
+            match self.try_io(Interest::READABLE, || crate::meta::linux::recvmsg(self, buf)) {
+                Err(e) if e.kind() == ErrorKind::WouldBlock => continue,
+                x => return Poll::Ready(x),
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[inline]
+    fn enable_txtime(&self) -> Result<()> { crate::pacing::linux::enable(self) }
+
+    #[cfg(target_os = "linux")]
+    fn poll_send_at(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+        target: &SocketAddr,
+        at: Instant,
+    ) -> Poll<Result<usize>> {
+        use tokio::io::Interest;
+        loop {
+            ready!(self.poll_send_ready(cx))?;
+            match self.try_io(Interest::WRITABLE, || {
+                crate::pacing::linux::sendmsg(self, buf, *target, at)
+            }) {
+                Err(e) if e.kind() == ErrorKind::WouldBlock => continue,
+                x => return Poll::Ready(x),
+            }
+        }
+    }
+}
+
+impl<A, T: DatagramSocket<A> + ?Sized> DatagramSocket<A> for Arc<T> {
+    #[inline]
+    fn poll_recv_from(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<A>> {
+        (**self).poll_recv_from(cx, buf)
+    }
+
+    #[inline]
+    fn poll_send_to(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+        target: &A,
+    ) -> Poll<Result<usize>> {
+        (**self).poll_send_to(cx, buf, target)
+    }
+
+    #[inline]
+    fn local_addr(&self) -> Result<A> { (**self).local_addr() }
+
+    #[inline]
+    fn poll_recv_meta(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(A, RecvMeta)>> {
+        (**self).poll_recv_meta(cx, buf)
+    }
+
+    #[inline]
+    fn enable_txtime(&self) -> Result<()> { (**self).enable_txtime() }
+
+    #[inline]
+    fn poll_send_at(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+        target: &A,
+        at: Instant,
+    ) -> Poll<Result<usize>> {
+        (**self).poll_send_at(cx, buf, target, at)
+    }
+
+    #[cfg(unix)]
+    #[inline]
+    fn as_raw_fd(&self) -> Option<RawFd> { (**self).as_raw_fd() }
+}
@@ -1,19 +1,29 @@
 use std::net::SocketAddr;
 use std::sync::{Arc, RwLock};
 use std::collections::HashMap;
+use std::hash::Hash;
 
 use tokio::sync::mpsc::Sender;
 
+use crate::RecvMeta;
+
 pub(crate) type Packet = Vec<u8>;
 
-#[derive(Clone)]
-pub(crate) struct SockMap(Arc<RwLock<HashMap<SocketAddr, Sender<Packet>>>>);
+/// A packet with its metadata.
+pub(crate) type Message = (Packet, RecvMeta);
+
+/// Sessions keyed by the peer address.
+pub(crate) struct SockMap<K = SocketAddr, M = Message>(Arc<RwLock<HashMap<K, Sender<M>>>>);
+
+impl<K, M> Clone for SockMap<K, M> {
+    fn clone(&self) -> Self { Self(self.0.clone()) }
+}
 
-impl SockMap {
+impl<K: Eq + Hash, M> SockMap<K, M> {
     pub fn new() -> Self { Self(Arc::new(RwLock::new(HashMap::new()))) }
 
     #[inline]
-    pub fn get(&self, addr: &SocketAddr) -> Option<Sender<Packet>> {
+    pub fn get(&self, addr: &K) -> Option<Sender<M>> {
         // fetch the lock
 
         let sockmap = self.0.read().unwrap();
@@ -24,7 +34,7 @@ impl SockMap {
     }
 
     #[inline]
-    pub fn insert(&self, addr: SocketAddr, tx: Sender<Packet>) {
+    pub fn insert(&self, addr: K, tx: Sender<M>) {
         // fetch the lock
         let mut sockmap = self.0.write().unwrap();
 
@@ -34,7 +44,7 @@ impl SockMap {
     }
 
     #[inline]
-    pub fn remove(&self, addr: &SocketAddr) {
+    pub fn remove(&self, addr: &K) {
         // fetch the lock
         let mut sockmap = self.0.write().unwrap();
 
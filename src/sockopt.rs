@@ -0,0 +1,164 @@
+use std::io::{Result, Error, ErrorKind};
+use std::net::SocketAddr;
+
+use socket2::{Socket, Domain, Type, Protocol};
+
+#[cfg(feature = "runtime-tokio")]
+use crate::UdpSocket;
+
+/// Socket options, which are applied at bind time.
+///
+/// Options left as `None` are not touched. Setting an option that is not
+/// supported on the current platform results in an `Unsupported` error.
+#[derive(Debug, Clone, Default)]
+pub struct SockOpts {
+    /// `SO_RCVBUF`.
+    pub recv_buffer_size: Option<usize>,
+    /// `SO_SNDBUF`.
+    pub send_buffer_size: Option<usize>,
+    /// `IP_TOS` or `IPV6_TCLASS`, DSCP occupies the upper 6 bits.
+    pub tos: Option<u32>,
+    /// `IP_TTL` or `IPV6_UNICAST_HOPS`.
+    pub ttl: Option<u32>,
+    /// Set the DF bit, via `IP_MTU_DISCOVER` or `IPV6_MTU_DISCOVER`.
+    pub dont_fragment: Option<bool>,
+    /// `SO_MARK`, Linux only.
+    pub mark: Option<u32>,
+    /// `SO_BINDTODEVICE`, Linux only.
+    pub bind_device: Option<String>,
+    /// `IPV6_V6ONLY`, ignored for ipv4 sockets.
+    pub only_v6: Option<bool>,
+    /// `SO_BUSY_POLL` in microseconds, Linux only.
+    pub busy_poll: Option<u32>,
+    /// Capture [`RecvMeta`](super::RecvMeta) of each packet, Linux only.
+    pub recv_meta: bool,
+}
+
+/// Apply an option if it is set, the error message carries its name.
+fn set<T>(opt: Option<T>, name: &str, f: impl FnOnce(T) -> Result<()>) -> Result<()> {
+    match opt {
+        Some(x) => f(x).map_err(|e| Error::new(e.kind(), format!("failed to set {}: {}", name, e))),
+        None => Ok(()),
+    }
+}
+
+#[allow(dead_code)]
+fn unsupported<T>(_: T) -> Result<()> {
+    Err(Error::new(ErrorKind::Unsupported, "not supported on this platform"))
+}
+
+impl SockOpts {
+    /// Create a udp socket, apply options then bind it to `addr`.
+    ///
+    /// This must be called within the context of a tokio runtime.
+    /// See `async_io::bind` for smol and async-std.
+    #[cfg(feature = "runtime-tokio")]
+    #[inline]
+    pub fn bind(&self, addr: SocketAddr) -> Result<UdpSocket> {
+        UdpSocket::from_std(self.bind_std(addr)?)
+    }
+
+    /// Create a non-blocking std udp socket, apply options then bind it to `addr`.
+    pub(crate) fn bind_std(&self, addr: SocketAddr) -> Result<std::net::UdpSocket> {
+        let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+        self.apply(&socket, addr.is_ipv6())?;
+        socket.set_nonblocking(true)?;
+        socket.bind(&addr.into())?;
+        Ok(socket.into())
+    }
+
+    fn apply(&self, socket: &Socket, ipv6: bool) -> Result<()> {
+        set(self.recv_buffer_size, "SO_RCVBUF", |x| socket.set_recv_buffer_size(x))?;
+        set(self.send_buffer_size, "SO_SNDBUF", |x| socket.set_send_buffer_size(x))?;
+
+        if ipv6 {
+            set(self.only_v6, "IPV6_V6ONLY", |x| socket.set_only_v6(x))?;
+            set(self.ttl, "IPV6_UNICAST_HOPS", |x| socket.set_unicast_hops_v6(x))?;
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            set(self.tos, "IPV6_TCLASS", |x| socket.set_tclass_v6(x))?;
+            #[cfg(not(any(target_os = "linux", target_os = "android")))]
+            set(self.tos, "IPV6_TCLASS", unsupported)?;
+        } else {
+            set(self.ttl, "IP_TTL", |x| socket.set_ttl_v4(x))?;
+            set(self.tos, "IP_TOS", |x| socket.set_tos_v4(x))?;
+        }
+
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        {
+            set(self.dont_fragment, "IP_MTU_DISCOVER", |x| {
+                linux::set_dont_fragment(socket, ipv6, x)
+            })?;
+            set(self.mark, "SO_MARK", |x| socket.set_mark(x))?;
+            set(self.bind_device.as_ref(), "SO_BINDTODEVICE", |x| {
+                socket.bind_device(Some(x.as_bytes()))
+            })?;
+            set(self.recv_meta.then_some(()), "recv meta", |_| {
+                crate::meta::linux::enable(socket, ipv6)
+            })?;
+        }
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        {
+            set(self.dont_fragment, "IP_MTU_DISCOVER", unsupported)?;
+            set(self.mark, "SO_MARK", unsupported)?;
+            set(self.bind_device.as_ref(), "SO_BINDTODEVICE", unsupported)?;
+            set(self.recv_meta.then_some(()), "recv meta", unsupported)?;
+        }
+
+        #[cfg(target_os = "linux")]
+        set(self.busy_poll, "SO_BUSY_POLL", |x| socket.set_busy_poll(x))?;
+        #[cfg(not(target_os = "linux"))]
+        set(self.busy_poll, "SO_BUSY_POLL", unsupported)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) mod linux {
+    use std::io::{Result, Error};
+    use std::os::fd::AsRawFd;
+
+    pub fn setsockopt<F: AsRawFd>(fd: &F, level: i32, name: i32, value: i32) -> Result<()> {
+        let ret = unsafe {
+            libc::setsockopt(
+                fd.as_raw_fd(),
+                level,
+                name,
+                &value as *const i32 as *const libc::c_void,
+                std::mem::size_of::<i32>() as libc::socklen_t,
+            )
+        };
+        if ret != 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    pub fn getsockopt<F: AsRawFd>(fd: &F, level: i32, name: i32) -> Result<i32> {
+        let mut value = 0i32;
+        let mut len = std::mem::size_of::<i32>() as libc::socklen_t;
+        let ret = unsafe {
+            libc::getsockopt(
+                fd.as_raw_fd(),
+                level,
+                name,
+                &mut value as *mut i32 as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        if ret != 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok(value)
+    }
+
+    pub fn set_dont_fragment<F: AsRawFd>(fd: &F, ipv6: bool, df: bool) -> Result<()> {
+        if ipv6 {
+            let value = if df { libc::IPV6_PMTUDISC_DO } else { libc::IPV6_PMTUDISC_DONT };
+            setsockopt(fd, libc::IPPROTO_IPV6, libc::IPV6_MTU_DISCOVER, value)
+        } else {
+            let value = if df { libc::IP_PMTUDISC_DO } else { libc::IP_PMTUDISC_DONT };
+            setsockopt(fd, libc::IPPROTO_IP, libc::IP_MTU_DISCOVER, value)
+        }
+    }
+}
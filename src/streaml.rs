@@ -1,37 +1,53 @@
-use std::io::Result;
+use std::io::{Result, Error, ErrorKind};
 use std::sync::Arc;
 use std::net::SocketAddr;
-use std::future::Future;
+use std::hash::Hash;
+use std::time::Duration;
+use std::future::{Future, poll_fn};
 use std::pin::Pin;
 use std::task::{Context, Poll};
 
-use tokio::net::UdpSocket;
-use tokio::time::{sleep, Sleep, Instant};
 use tokio::sync::mpsc::Receiver;
 use tokio::io::{ReadBuf, AsyncRead, AsyncWrite};
 
-use crate::sockmap::{SockMap, Packet};
-use crate::get_timeout;
+use crate::time::{sleep, Sleep, Instant};
+use crate::sockmap::{SockMap, Message};
+use crate::{get_timeout, DatagramStream, DatagramSocket};
+#[cfg(feature = "runtime-tokio")]
+use crate::UdpSocket;
+use crate::keepalive::{Keepalive, KeepaliveTimer};
+use crate::pacing::{self, Pacing, Pacer};
+use crate::{pmtu, RecvMeta};
 
 /// Udp stream accepted from local listener.
 ///
+/// The socket `S` and the address type `A` are those of the [`UdpListener`](crate::UdpListener)
+/// which accepted it.
+///
 /// A `Read` call times out when there is no packet received
 /// during a period of time. This is treated as `EOF`, and
 /// a `Ok(0)` will be returned.
-pub struct UdpStreamLocal {
-    rx: Receiver<Packet>,
-    socket: Arc<UdpSocket>,
+pub struct UdpStreamLocal<
+    #[cfg(feature = "runtime-tokio")] S = UdpSocket,
+    #[cfg(not(feature = "runtime-tokio"))] S,
+    A: Eq + Hash = SocketAddr,
+> {
+    rx: Receiver<Message>,
+    socket: Arc<S>,
     timeout: Pin<Box<Sleep>>,
-    sockmap: SockMap,
-    addr: SocketAddr,
+    keepalive: Option<KeepaliveTimer>,
+    pacer: Option<Pacer>,
+    max_size: Option<usize>,
+    sockmap: SockMap<A, Message>,
+    addr: A,
 }
 
-impl UdpStreamLocal {
+impl<S: DatagramSocket<A>, A: Eq + Hash> UdpStreamLocal<S, A> {
     pub(crate) fn new(
-        rx: Receiver<Packet>,
-        socket: Arc<UdpSocket>,
-        sockmap: SockMap,
-        addr: SocketAddr,
+        rx: Receiver<Message>,
+        socket: Arc<S>,
+        sockmap: SockMap<A, Message>,
+        addr: A,
     ) -> Self {
         Self {
             rx,
@@ -39,9 +55,119 @@ impl UdpStreamLocal {
             addr,
             sockmap,
             timeout: Box::pin(sleep(get_timeout())),
+            keepalive: None,
+            pacer: None,
+            max_size: None,
         }
     }
 
+    /// Get peer address.
+    #[inline]
+    pub const fn peer(&self) -> &A { &self.addr }
+
+    /// Get inner udp socket.
+    #[inline]
+    pub const fn inner_socket(&self) -> &Arc<S> { &self.socket }
+
+    /// Send keepalive packets during outbound silence, which is useful
+    /// to maintain the NAT mapping.
+    ///
+    /// Keepalives are sent while the stream is polled, by either reads or writes.
+    /// A stream that is only written to should also have a read pending,
+    /// e.g. in a `select!` or [`relay`](crate::relay).
+    #[inline]
+    pub fn set_keepalive(&mut self, keepalive: Keepalive) {
+        self.keepalive = Some(KeepaliveTimer::new(keepalive));
+    }
+
+    /// Limit the sending rate, writes are delayed until they conform to `pacing`.
+    ///
+    /// A zero rate turns pacing off.
+    #[inline]
+    pub fn set_pacing(&mut self, pacing: Pacing) {
+        self.pacer = Pacer::new(pacing, &*self.socket);
+    }
+
+    /// Reject writes that are larger than `size`, with an `InvalidInput` error.
+    ///
+    /// Usually this is the max payload that fits in the path MTU.
+    #[inline]
+    pub fn set_max_datagram_size(&mut self, size: usize) { self.max_size = Some(size); }
+
+    /// Attempt to receive a datagram, also capture its metadata.
+    ///
+    /// On `EOF`, nothing is filled and the metadata is empty.
+    pub fn poll_recv_meta(
+        &mut self,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<RecvMeta>> {
+        self.poll_keepalive(cx);
+
+        while let Poll::Ready(Some((pkt, meta))) = self.rx.poll_recv(cx) {
+            if self.is_keepalive(&pkt) {
+                continue;
+            }
+
+            // truncate
+            let n = std::cmp::min(pkt.len(), buf.remaining());
+            buf.put_slice(&pkt[..n]);
+
+            // reset timer
+            self.timeout.as_mut().reset(Instant::now() + get_timeout());
+
+            return Poll::Ready(Ok(meta));
+        }
+
+        // EOF
+        if self.timeout.as_mut().poll(cx).is_ready() {
+            return Poll::Ready(Ok(RecvMeta::default()));
+        }
+
+        Poll::Pending
+    }
+
+    /// Receive a datagram, return its length and metadata.
+    pub async fn recv_with_meta(&mut self, buf: &mut [u8]) -> Result<(usize, RecvMeta)> {
+        let mut buf = ReadBuf::new(buf);
+        let meta = poll_fn(|cx| self.poll_recv_meta(cx, &mut buf)).await?;
+        Ok((buf.filled().len(), meta))
+    }
+
+    #[inline]
+    fn is_keepalive(&self, pkt: &[u8]) -> bool {
+        self.keepalive.as_ref().is_some_and(|x| x.is_keepalive(pkt))
+    }
+
+    /// Send a keepalive if it is due, with the same size limit and pacing as user data.
+    fn poll_keepalive(&mut self, cx: &mut Context<'_>) {
+        if let Some(keepalive) = self.keepalive.as_mut() {
+            let (socket, pacer, max_size, addr) =
+                (&*self.socket, &mut self.pacer, self.max_size, &self.addr);
+            keepalive.poll_keepalive(cx, |cx, pkt| {
+                pacing::poll_send_limited(socket, pacer, max_size, cx, pkt, addr)
+            });
+        }
+    }
+
+    fn poll_send_to(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        self.poll_keepalive(cx);
+        let n = ready!(pacing::poll_send_limited(
+            &*self.socket,
+            &mut self.pacer,
+            self.max_size,
+            cx,
+            buf,
+            &self.addr
+        ))?;
+        if let Some(keepalive) = self.keepalive.as_mut() {
+            keepalive.reset();
+        }
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl<S: DatagramSocket> UdpStreamLocal<S> {
     /// Get peer sockaddr.
     #[inline]
     pub const fn peer_addr(&self) -> SocketAddr { self.addr }
@@ -50,55 +176,113 @@ impl UdpStreamLocal {
     #[inline]
     pub fn local_addr(&self) -> SocketAddr { self.socket.local_addr().unwrap() }
 
-    /// Get inner udp socket.
+    /// Get the current path MTU towards the peer, see [`pmtu`](crate::pmtu).
+    #[inline]
+    pub fn path_mtu(&self) -> Result<usize> { pmtu::path_mtu(self.local_addr().ip(), self.addr) }
+
+    /// Discover the max datagram size that could be sent to the peer,
+    /// see [`probe_mtu`](crate::pmtu::probe_mtu).
+    ///
+    /// Probes are sent from the stream's own socket, so the peer receives them
+    /// as zero-filled datagrams of this session. The DF bit of the socket is set
+    /// during probing, which also affects other streams sharing the socket.
+    /// The socket must expose its raw fd.
     #[inline]
-    pub const fn inner_socket(&self) -> &Arc<UdpSocket> { &self.socket }
+    pub async fn probe_mtu(&self, max: usize, wait: Duration) -> Result<usize> {
+        pmtu::probe_mtu_from(&*self.socket, self.addr, max, wait).await
+    }
 }
 
-impl Drop for UdpStreamLocal {
+impl<S, A: Eq + Hash> Drop for UdpStreamLocal<S, A> {
     fn drop(&mut self) {
         self.sockmap.remove(&self.addr);
         // left elements are popped
     }
 }
 
-impl AsyncRead for UdpStreamLocal {
+impl<S: DatagramSocket<A>, A: Eq + Hash> DatagramStream for UdpStreamLocal<S, A> {
+    #[inline]
+    fn poll_recv(&mut self, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<Result<()>> {
+        self.poll_recv_meta(cx, buf).map_ok(|_| ())
+    }
+
+    fn poll_send(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<()>> {
+        let n = ready!(self.poll_send_to(cx, buf))?;
+        if n != buf.len() {
+            return Poll::Ready(Err(Error::new(ErrorKind::WriteZero, "datagram partially sent")));
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    #[inline]
+    fn reset_timeout(&mut self) { self.timeout.as_mut().reset(Instant::now() + get_timeout()); }
+}
+
+impl<S, A> AsyncRead for UdpStreamLocal<S, A>
+where
+    S: DatagramSocket<A> + Unpin,
+    A: Eq + Hash + Unpin,
+{
     fn poll_read(
         self: Pin<&mut Self>,
         cx: &mut Context<'_>,
         buf: &mut ReadBuf<'_>,
     ) -> Poll<Result<()>> {
-        let this = self.get_mut();
-
-        if let Poll::Ready(Some(pkt)) = this.rx.poll_recv(cx) {
-            buf.put_slice(&pkt);
+        self.get_mut().poll_recv(cx, buf)
+    }
+}
 
-            // reset timer
-            this.timeout.as_mut().reset(Instant::now() + get_timeout());
+impl<S, A> AsyncWrite for UdpStreamLocal<S, A>
+where
+    S: DatagramSocket<A> + Unpin,
+    A: Eq + Hash + Unpin,
+{
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        self.get_mut().poll_send_to(cx, buf)
+    }
 
-            return Poll::Ready(Ok(()));
-        }
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
 
-        // EOF
-        if this.timeout.as_mut().poll(cx).is_ready() {
-            return Poll::Ready(Ok(()));
-        }
+    fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<()>> {
+        self.get_mut().rx.close();
+        Poll::Ready(Ok(()))
+    }
+}
 
-        Poll::Pending
+#[cfg(any(feature = "runtime-async-std", feature = "runtime-smol"))]
+impl<S, A> futures_io::AsyncRead for UdpStreamLocal<S, A>
+where
+    S: DatagramSocket<A> + Unpin,
+    A: Eq + Hash + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize>> {
+        let mut buf = ReadBuf::new(buf);
+        ready!(self.get_mut().poll_recv(cx, &mut buf))?;
+        Poll::Ready(Ok(buf.filled().len()))
     }
 }
 
-impl AsyncWrite for UdpStreamLocal {
+#[cfg(any(feature = "runtime-async-std", feature = "runtime-smol"))]
+impl<S, A> futures_io::AsyncWrite for UdpStreamLocal<S, A>
+where
+    S: DatagramSocket<A> + Unpin,
+    A: Eq + Hash + Unpin,
+{
     fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
-        let this = self.get_mut();
-        this.socket.poll_send_to(cx, buf, this.addr)
+        self.get_mut().poll_send_to(cx, buf)
     }
 
     fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<()>> {
         Poll::Ready(Ok(()))
     }
 
-    fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<()>> {
+    fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<()>> {
         self.get_mut().rx.close();
         Poll::Ready(Ok(()))
     }
@@ -1,37 +1,169 @@
-use std::io::Result;
+use std::io::{Result, Error, ErrorKind};
 use std::net::SocketAddr;
-use std::future::Future;
+use std::time::Duration;
+use std::future::{Future, poll_fn};
 use std::pin::Pin;
 use std::task::{Context, Poll};
 
-use tokio::net::UdpSocket;
-use tokio::time::{sleep, Sleep, Instant};
 use tokio::io::{ReadBuf, AsyncRead, AsyncWrite};
 
-use crate::get_timeout;
+use crate::time::{sleep, Sleep, Instant};
+use crate::{get_timeout, DatagramStream, DatagramSocket};
+#[cfg(feature = "runtime-tokio")]
+use crate::{SockOpts, UdpSocket};
+use crate::keepalive::{Keepalive, KeepaliveTimer};
+use crate::pacing::{self, Pacing, Pacer};
+use crate::{pmtu, RecvMeta};
 
 /// Udp stream which is actively established.
 ///
+/// It owns its socket `S`, which is any [`DatagramSocket`] (tokio's `UdpSocket` by default),
+/// and sends to a peer of address type `A`.
+///
 /// A `Read` call times out when there is no packet received
 /// during a period of time. This is treated as `EOF`, and
 /// a `Ok(0)` will be returned.
-pub struct UdpStreamRemote {
-    socket: UdpSocket,
+pub struct UdpStreamRemote<
+    #[cfg(feature = "runtime-tokio")] S = UdpSocket,
+    #[cfg(not(feature = "runtime-tokio"))] S,
+    A = SocketAddr,
+> {
+    socket: S,
     timeout: Pin<Box<Sleep>>,
-    addr: SocketAddr,
+    keepalive: Option<KeepaliveTimer>,
+    pacer: Option<Pacer>,
+    max_size: Option<usize>,
+    addr: A,
 }
 
+#[cfg(feature = "runtime-tokio")]
 impl UdpStreamRemote {
+    /// Create a udp socket with options, and bind it to `laddr`.
+    #[inline]
+    pub fn bind_with(laddr: SocketAddr, addr: SocketAddr, opts: &SockOpts) -> Result<Self> {
+        opts.bind(laddr).map(|socket| Self::new(socket, addr))
+    }
+}
+
+impl<S: DatagramSocket<A>, A> UdpStreamRemote<S, A> {
     /// Create from a **bound** udp socket.
     #[inline]
-    pub fn new(socket: UdpSocket, addr: SocketAddr) -> Self {
+    pub fn new(socket: S, addr: A) -> Self {
         Self {
             socket,
             addr,
             timeout: Box::pin(sleep(get_timeout())),
+            keepalive: None,
+            pacer: None,
+            max_size: None,
+        }
+    }
+
+    /// Get peer address.
+    #[inline]
+    pub const fn peer(&self) -> &A { &self.addr }
+
+    /// Get inner udp socket.
+    #[inline]
+    pub const fn inner_socket(&self) -> &S { &self.socket }
+
+    /// Send keepalive packets during outbound silence, which is useful
+    /// to maintain the NAT mapping.
+    ///
+    /// Keepalives are sent while the stream is polled, by either reads or writes.
+    /// A stream that is only written to should also have a read pending,
+    /// e.g. in a `select!` or [`relay`](crate::relay).
+    #[inline]
+    pub fn set_keepalive(&mut self, keepalive: Keepalive) {
+        self.keepalive = Some(KeepaliveTimer::new(keepalive));
+    }
+
+    /// Limit the sending rate, writes are delayed until they conform to `pacing`.
+    ///
+    /// A zero rate turns pacing off.
+    #[inline]
+    pub fn set_pacing(&mut self, pacing: Pacing) {
+        self.pacer = Pacer::new(pacing, &self.socket);
+    }
+
+    /// Reject writes that are larger than `size`, with an `InvalidInput` error.
+    ///
+    /// Usually this is the max payload that fits in the path MTU.
+    #[inline]
+    pub fn set_max_datagram_size(&mut self, size: usize) { self.max_size = Some(size); }
+
+    /// Attempt to receive a datagram, also capture its metadata.
+    ///
+    /// On `EOF`, nothing is filled and the metadata is empty.
+    pub fn poll_recv_meta(
+        &mut self,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<RecvMeta>> {
+        self.poll_keepalive(cx);
+
+        let start = buf.filled().len();
+        while let Poll::Ready(x) = self.socket.poll_recv_meta(cx, buf) {
+            if x.is_ok() && self.is_keepalive(&buf.filled()[start..]) {
+                buf.set_filled(start);
+                continue;
+            }
+
+            // reset timer
+            self.timeout.as_mut().reset(Instant::now() + get_timeout());
+
+            return Poll::Ready(x.map(|(_, meta)| meta));
+        }
+
+        // EOF
+        if self.timeout.as_mut().poll(cx).is_ready() {
+            return Poll::Ready(Ok(RecvMeta::default()));
+        }
+
+        Poll::Pending
+    }
+
+    /// Receive a datagram, return its length and metadata.
+    pub async fn recv_with_meta(&mut self, buf: &mut [u8]) -> Result<(usize, RecvMeta)> {
+        let mut buf = ReadBuf::new(buf);
+        let meta = poll_fn(|cx| self.poll_recv_meta(cx, &mut buf)).await?;
+        Ok((buf.filled().len(), meta))
+    }
+
+    #[inline]
+    fn is_keepalive(&self, pkt: &[u8]) -> bool {
+        self.keepalive.as_ref().is_some_and(|x| x.is_keepalive(pkt))
+    }
+
+    /// Send a keepalive if it is due, with the same size limit and pacing as user data.
+    fn poll_keepalive(&mut self, cx: &mut Context<'_>) {
+        if let Some(keepalive) = self.keepalive.as_mut() {
+            let (socket, pacer, max_size, addr) =
+                (&self.socket, &mut self.pacer, self.max_size, &self.addr);
+            keepalive.poll_keepalive(cx, |cx, pkt| {
+                pacing::poll_send_limited(socket, pacer, max_size, cx, pkt, addr)
+            });
         }
     }
 
+    fn poll_send_to(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        self.poll_keepalive(cx);
+        let n = ready!(pacing::poll_send_limited(
+            &self.socket,
+            &mut self.pacer,
+            self.max_size,
+            cx,
+            buf,
+            &self.addr
+        ))?;
+        if let Some(keepalive) = self.keepalive.as_mut() {
+            keepalive.reset();
+        }
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl<S: DatagramSocket> UdpStreamRemote<S> {
     /// Get peer sockaddr.
     #[inline]
     pub const fn peer_addr(&self) -> SocketAddr { self.addr }
@@ -40,46 +172,89 @@ impl UdpStreamRemote {
     #[inline]
     pub fn local_addr(&self) -> SocketAddr { self.socket.local_addr().unwrap() }
 
-    /// Get inner udp socket.
+    /// Get the current path MTU towards the peer, see [`pmtu`](crate::pmtu).
+    #[inline]
+    pub fn path_mtu(&self) -> Result<usize> { pmtu::path_mtu(self.local_addr().ip(), self.addr) }
+
+    /// Discover the max datagram size that could be sent to the peer,
+    /// see [`probe_mtu`](crate::pmtu::probe_mtu).
+    ///
+    /// Probes are sent from the stream's own socket, so the peer receives them
+    /// as zero-filled datagrams of this session. The DF bit of the socket is set
+    /// during probing, which also affects other streams sharing the socket.
+    /// The socket must expose its raw fd.
+    #[inline]
+    pub async fn probe_mtu(&self, max: usize, wait: Duration) -> Result<usize> {
+        pmtu::probe_mtu_from(&self.socket, self.addr, max, wait).await
+    }
+}
+
+impl<S: DatagramSocket<A>, A> DatagramStream for UdpStreamRemote<S, A> {
+    #[inline]
+    fn poll_recv(&mut self, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<Result<()>> {
+        self.poll_recv_meta(cx, buf).map_ok(|_| ())
+    }
+
+    fn poll_send(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<()>> {
+        let n = ready!(self.poll_send_to(cx, buf))?;
+        if n != buf.len() {
+            return Poll::Ready(Err(Error::new(ErrorKind::WriteZero, "datagram partially sent")));
+        }
+        Poll::Ready(Ok(()))
+    }
+
     #[inline]
-    pub const fn inner_socket(&self) -> &UdpSocket { &self.socket }
+    fn reset_timeout(&mut self) { self.timeout.as_mut().reset(Instant::now() + get_timeout()); }
 }
 
-impl AsyncRead for UdpStreamRemote {
+impl<S: DatagramSocket<A> + Unpin, A: Unpin> AsyncRead for UdpStreamRemote<S, A> {
     fn poll_read(
         self: Pin<&mut Self>,
         cx: &mut Context<'_>,
         buf: &mut ReadBuf<'_>,
     ) -> Poll<Result<()>> {
-        let this = self.get_mut();
+        self.get_mut().poll_recv(cx, buf)
+    }
+}
 
-        if let Poll::Ready(x) = this.socket.poll_recv_from(cx, buf) {
-            // reset timer
-            this.timeout.as_mut().reset(Instant::now() + get_timeout());
+impl<S: DatagramSocket<A> + Unpin, A: Unpin> AsyncWrite for UdpStreamRemote<S, A> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        self.get_mut().poll_send_to(cx, buf)
+    }
 
-            return Poll::Ready(x.map(|_| ()));
-        }
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
 
-        // EOF
-        if this.timeout.as_mut().poll(cx).is_ready() {
-            return Poll::Ready(Ok(()));
-        }
+    fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
 
-        Poll::Pending
+#[cfg(any(feature = "runtime-async-std", feature = "runtime-smol"))]
+impl<S: DatagramSocket<A> + Unpin, A: Unpin> futures_io::AsyncRead for UdpStreamRemote<S, A> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize>> {
+        let mut buf = ReadBuf::new(buf);
+        ready!(self.get_mut().poll_recv(cx, &mut buf))?;
+        Poll::Ready(Ok(buf.filled().len()))
     }
 }
 
-impl AsyncWrite for UdpStreamRemote {
+#[cfg(any(feature = "runtime-async-std", feature = "runtime-smol"))]
+impl<S: DatagramSocket<A> + Unpin, A: Unpin> futures_io::AsyncWrite for UdpStreamRemote<S, A> {
     fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
-        let this = self.get_mut();
-        this.socket.poll_send_to(cx, buf, this.addr)
+        self.get_mut().poll_send_to(cx, buf)
     }
 
     fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<()>> {
         Poll::Ready(Ok(()))
     }
 
-    fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<()>> {
+    fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<()>> {
         Poll::Ready(Ok(()))
     }
 }
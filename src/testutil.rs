@@ -0,0 +1,35 @@
+//! Fixtures for unit tests.
+
+use std::io::Result;
+use std::collections::VecDeque;
+use std::task::{Context, Poll};
+
+use tokio::io::ReadBuf;
+
+use crate::DatagramStream;
+
+/// Sent datagrams are looped back.
+///
+/// When empty, a read returns `EOF` if `.1` is set, otherwise it is pending.
+pub(crate) struct Queue(pub VecDeque<Vec<u8>>, pub bool);
+
+impl Queue {
+    /// Create an empty queue, which reads `EOF`.
+    pub fn new() -> Self { Self(VecDeque::new(), true) }
+}
+
+impl DatagramStream for Queue {
+    fn poll_recv(&mut self, _: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<Result<()>> {
+        match self.0.pop_front() {
+            Some(pkt) => buf.put_slice(&pkt),
+            None if self.1 => {}
+            None => return Poll::Pending,
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_send(&mut self, _: &mut Context<'_>, buf: &[u8]) -> Poll<Result<()>> {
+        self.0.push_back(buf.to_vec());
+        Poll::Ready(Ok(()))
+    }
+}
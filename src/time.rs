@@ -0,0 +1,163 @@
+//! Timers of the selected runtime.
+//!
+//! With `runtime-tokio`, these are tokio's timers, which work with `tokio::time::pause`.
+//! With `runtime-smol` or `runtime-async-std`, they are backed by `async-io`.
+//! If both are enabled, tokio's timers are used within a tokio runtime,
+//! and `async-io`'s are used elsewhere.
+
+#[cfg(feature = "runtime-tokio")]
+pub(crate) use tokio::time::Instant;
+
+#[cfg(not(feature = "runtime-tokio"))]
+pub(crate) use std::time::Instant;
+
+#[cfg(all(
+    feature = "runtime-tokio",
+    not(any(feature = "runtime-async-std", feature = "runtime-smol"))
+))]
+pub(crate) use tokio::time::{sleep, Sleep};
+
+#[cfg(not(feature = "runtime-tokio"))]
+pub(crate) use async_io_timer::{sleep, Sleep};
+
+#[cfg(all(
+    feature = "runtime-tokio",
+    any(feature = "runtime-async-std", feature = "runtime-smol")
+))]
+pub(crate) use either_timer::{sleep, Sleep};
+
+#[cfg(feature = "noise")]
+pub(crate) use with_timeout::timeout;
+
+/// Convert to std's `Instant`, which is used by [`DatagramSocket`](crate::DatagramSocket).
+#[inline]
+pub(crate) fn into_std(at: Instant) -> std::time::Instant {
+    #[cfg(feature = "runtime-tokio")]
+    {
+        at.into_std()
+    }
+    #[cfg(not(feature = "runtime-tokio"))]
+    {
+        at
+    }
+}
+
+#[cfg(feature = "noise")]
+mod with_timeout {
+    use std::future::{Future, poll_fn};
+    use std::time::Duration;
+    use std::task::Poll;
+
+    use super::sleep;
+
+    /// Returned by [`timeout`] if the future does not complete in time.
+    #[derive(Debug)]
+    pub struct Elapsed;
+
+    /// Run a future with a time limit.
+    pub async fn timeout<F: Future>(dur: Duration, fut: F) -> Result<F::Output, Elapsed> {
+        let mut fut = std::pin::pin!(fut);
+        let mut timer = std::pin::pin!(sleep(dur));
+        poll_fn(|cx| {
+            if let Poll::Ready(x) = fut.as_mut().poll(cx) {
+                return Poll::Ready(Ok(x));
+            }
+            timer.as_mut().poll(cx).map(|_| Err(Elapsed))
+        })
+        .await
+    }
+}
+
+#[cfg(any(feature = "runtime-async-std", feature = "runtime-smol"))]
+mod async_io_timer {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::time::{Duration, Instant};
+    use std::task::{Context, Poll};
+
+    use async_io::Timer;
+
+    /// A resettable timer, which stays ready once the deadline is reached.
+    pub struct Sleep {
+        timer: Timer,
+        deadline: Instant,
+    }
+
+    pub fn sleep(dur: Duration) -> Sleep {
+        let deadline = Instant::now() + dur;
+        Sleep {
+            timer: Timer::at(deadline),
+            deadline,
+        }
+    }
+
+    impl Sleep {
+        pub fn reset(self: Pin<&mut Self>, deadline: Instant) {
+            let this = self.get_mut();
+            this.timer.set_at(deadline);
+            this.deadline = deadline;
+        }
+    }
+
+    impl Future for Sleep {
+        type Output = ();
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            let this = self.get_mut();
+            if Instant::now() >= this.deadline {
+                return Poll::Ready(());
+            }
+            Pin::new(&mut this.timer).poll(cx).map(|_| ())
+        }
+    }
+}
+
+#[cfg(all(
+    feature = "runtime-tokio",
+    any(feature = "runtime-async-std", feature = "runtime-smol")
+))]
+mod either_timer {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::time::Duration;
+    use std::task::{Context, Poll};
+
+    use tokio::time::Instant;
+
+    use super::async_io_timer;
+
+    /// Timer of the runtime where it is created.
+    pub enum Sleep {
+        Tokio(tokio::time::Sleep),
+        AsyncIo(async_io_timer::Sleep),
+    }
+
+    pub fn sleep(dur: Duration) -> Sleep {
+        match tokio::runtime::Handle::try_current() {
+            Ok(_) => Sleep::Tokio(tokio::time::sleep(dur)),
+            Err(_) => Sleep::AsyncIo(async_io_timer::sleep(dur)),
+        }
+    }
+
+    impl Sleep {
+        pub fn reset(self: Pin<&mut Self>, deadline: Instant) {
+            // SAFETY: the inner timer is never moved
+            match unsafe { self.get_unchecked_mut() } {
+                Sleep::Tokio(x) => unsafe { Pin::new_unchecked(x) }.reset(deadline),
+                Sleep::AsyncIo(x) => Pin::new(x).reset(deadline.into_std()),
+            }
+        }
+    }
+
+    impl Future for Sleep {
+        type Output = ();
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            // SAFETY: the inner timer is never moved
+            match unsafe { self.get_unchecked_mut() } {
+                Sleep::Tokio(x) => unsafe { Pin::new_unchecked(x) }.poll(cx),
+                Sleep::AsyncIo(x) => Pin::new(x).poll(cx),
+            }
+        }
+    }
+}
@@ -0,0 +1,79 @@
+//! Unix datagram streams.
+//!
+//! The same listener/stream API as udp, over [`UnixDatagram`] sockets.
+//! Sessions are keyed by the peer's bound path, packets from unnamed
+//! or abstract sockets are dropped, since there is no path to reply to.
+//!
+//! [`UotStream`](crate::UotStream) works unchanged on [`UnixStream`](tokio::net::UnixStream).
+//!
+
+use std::io::{Result, Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::task::{Context, Poll};
+
+use tokio::net::UnixDatagram;
+use tokio::io::ReadBuf;
+
+use crate::{DatagramSocket, UdpListener, UdpStreamLocal, UdpStreamRemote};
+
+/// Unix datagram listener.
+pub type UnixListener = UdpListener<UnixDatagram, PathBuf>;
+
+/// Unix datagram stream accepted from local listener.
+pub type UnixStreamLocal = UdpStreamLocal<UnixDatagram, PathBuf>;
+
+/// Unix datagram stream which is actively established.
+///
+/// The local socket must be bound to a path, so that the peer could reply.
+pub type UnixStreamRemote = UdpStreamRemote<UnixDatagram, PathBuf>;
+
+impl UnixListener {
+    /// Create a unix datagram socket, and bind it to `path`.
+    #[inline]
+    pub fn bind(path: impl AsRef<Path>) -> Result<Self> { UnixDatagram::bind(path).map(Self::new) }
+}
+
+impl UnixStreamLocal {
+    /// Get peer path.
+    #[inline]
+    pub fn peer_path(&self) -> &Path { self.peer() }
+}
+
+impl UnixStreamRemote {
+    /// Get peer path.
+    #[inline]
+    pub fn peer_path(&self) -> &Path { self.peer() }
+}
+
+impl DatagramSocket<PathBuf> for UnixDatagram {
+    fn poll_recv_from(&self, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<Result<PathBuf>> {
+        let start = buf.filled().len();
+        loop {
+            let addr = ready!(UnixDatagram::poll_recv_from(self, cx, buf))?;
+
+            // unnamed peer
+            match addr.as_pathname() {
+                Some(x) => return Poll::Ready(Ok(x.to_path_buf())),
+                None => buf.set_filled(start),
+            }
+        }
+    }
+
+    #[inline]
+    fn poll_send_to(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+        target: &PathBuf,
+    ) -> Poll<Result<usize>> {
+        UnixDatagram::poll_send_to(self, cx, buf, target)
+    }
+
+    fn local_addr(&self) -> Result<PathBuf> {
+        let addr = UnixDatagram::local_addr(self)?;
+        match addr.as_pathname() {
+            Some(x) => Ok(x.to_path_buf()),
+            None => Err(Error::new(ErrorKind::AddrNotAvailable, "socket is not bound to a path")),
+        }
+    }
+}
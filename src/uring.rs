@@ -0,0 +1,500 @@
+//! Udp listener and streams on io_uring.
+//!
+//! Requires the `uring` feature, Linux only. These types must run inside
+//! [`tokio_uring::start`], and they are `!Send`.
+//!
+//! Sessions have the same semantics as [`UdpStreamLocal`](crate::UdpStreamLocal)
+//! and [`UdpStreamRemote`](crate::UdpStreamRemote): packets of a known peer are
+//! dispatched to its stream, and a read which sees no packet for [`get_timeout`]
+//! returns `Ok(0)`. Keepalive, pacing and pmtu probing are not supported.
+//!
+//! io_uring operations own their buffers, so `send` takes an owned buffer and
+//! gives it back, as tokio-uring does. `recv` copies into a borrowed buffer,
+//! because a timed out operation keeps its buffer until the kernel completes it.
+//!
+//! The listener receives with one multishot `recvmsg` into a ring of buffers
+//! registered to the kernel, on an io_uring instance of its own (Linux 6.0+).
+//! A packet stays in its buffer until the stream reads it, then the buffer is
+//! given back to the kernel. Packets larger than a buffer are dropped.
+//! [`UringStreamRemote`] receives with single-shot `recvmsg`.
+//!
+//! ```no_run
+//! use udpflow::uring::UringListener;
+//! tokio_uring::start(async {
+//!     let listener = UringListener::bind("127.0.0.1:5000".parse().unwrap()).await.unwrap();
+//!     loop {
+//!         let (mut stream, addr) = listener.accept().await.unwrap();
+//!         tokio_uring::spawn(async move {
+//!             let mut buf = vec![0u8; 0x2000];
+//!             while let Ok(n @ 1..) = stream.recv(&mut buf).await {
+//!                 let _ = stream.send(buf[..n].to_vec()).await;
+//!             }
+//!         });
+//!     }
+//! });
+//! ```
+//!
+
+use std::io::{Result, Error};
+use std::net::SocketAddr;
+use std::rc::Rc;
+use std::alloc::{self, Layout};
+use std::cell::{Cell, RefCell};
+use std::future::poll_fn;
+use std::mem::{size_of, zeroed};
+use std::ops::Deref;
+use std::os::fd::{AsRawFd, RawFd};
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::task::{Context, Poll, Waker};
+
+use io_uring::{cqueue, opcode, types, IoUring};
+use io_uring::types::{BufRingEntry, RecvMsgOut};
+use tokio::io::unix::AsyncFd;
+use tokio::io::Interest;
+use tokio::sync::mpsc::{self, Receiver};
+use tokio::time::timeout_at;
+use tokio_uring::net::UdpSocket;
+use tokio_uring::buf::IoBuf;
+use tokio_uring::BufResult;
+
+use crate::time::Instant;
+use crate::sockmap::SockMap;
+use crate::meta::linux::to_socket_addr;
+use crate::{get_timeout, SockOpts};
+
+/// Default number of buffers of a listener.
+pub const RING_ENTRIES: u16 = 256;
+
+/// Default size of a listener buffer, including the `recvmsg` header.
+pub const RING_BUF_SIZE: usize = 0x2000;
+
+// recvmsg header and sockaddr_storage, before the payload
+const RING_BUF_HEADER: usize = 16 + size_of::<libc::sockaddr_storage>();
+const BGID: u16 = 0;
+
+fn into_uring(socket: crate::UdpSocket) -> Result<UdpSocket> {
+    Ok(UdpSocket::from_std(socket.into_std()?))
+}
+
+/// Udp packet listener on io_uring.
+pub struct UringListener {
+    socket: Rc<UdpSocket>,
+    ring: RefCell<RecvRing>,
+    sockmap: SockMap<SocketAddr, RingPacket>,
+}
+
+impl UringListener {
+    /// Create from a **bound** udp socket, with [`RING_ENTRIES`] buffers
+    /// of [`RING_BUF_SIZE`] bytes.
+    #[inline]
+    pub fn new(socket: UdpSocket) -> Result<Self> {
+        Self::with_buffers(socket, RING_ENTRIES, RING_BUF_SIZE)
+    }
+
+    /// Create from a **bound** udp socket, with `entries` buffers of `size` bytes.
+    ///
+    /// `entries` must be a power of two, no more than 32768. A buffer holds a header
+    /// of 144 bytes, then the payload. A session holds up to 32 buffers before its
+    /// packets are read; when no buffer is left, the listener stops receiving.
+    pub fn with_buffers(socket: UdpSocket, entries: u16, size: usize) -> Result<Self> {
+        let ring = RecvRing::new(socket.as_raw_fd(), entries, size)?;
+        Ok(Self {
+            socket: Rc::new(socket),
+            ring: RefCell::new(ring),
+            sockmap: SockMap::new(),
+        })
+    }
+
+    /// Create a udp socket, and bind it to `addr`.
+    #[inline]
+    pub async fn bind(addr: SocketAddr) -> Result<Self> {
+        Self::new(UdpSocket::bind(addr).await?)
+    }
+
+    /// Create a udp socket with options, and bind it to `addr`.
+    #[inline]
+    pub fn bind_with(addr: SocketAddr, opts: &SockOpts) -> Result<Self> {
+        Self::new(into_uring(opts.bind(addr)?)?)
+    }
+
+    /// Accept a new stream.
+    ///
+    /// A listener must be continuously polled to recv packets or accept new streams.
+    ///
+    /// When receiving a packet from a known peer, this function does not return,
+    /// and the packet will be sent to the associated [`UringStreamLocal`], without a copy.
+    pub async fn accept(&self) -> Result<(UringStreamLocal, SocketAddr)> {
+        loop {
+            let (pkt, addr) = poll_fn(|cx| self.ring.borrow_mut().poll_recv(cx)).await?;
+
+            // existed session
+            if let Some(tx) = self.sockmap.get(&addr) {
+                let _ = tx.send(pkt).await;
+                continue;
+            }
+
+            // new session
+            let (tx, rx) = mpsc::channel::<RingPacket>(32);
+            let _ = tx.send(pkt).await;
+            self.sockmap.insert(addr, tx);
+
+            let stream = UringStreamLocal {
+                rx,
+                socket: self.socket.clone(),
+                deadline: Instant::now() + get_timeout(),
+                sockmap: self.sockmap.clone(),
+                addr,
+            };
+            return Ok((stream, addr));
+        }
+    }
+}
+
+/// Udp stream accepted from [`UringListener`].
+///
+/// A `recv` call times out when there is no packet received
+/// during a period of time. This is treated as `EOF`, and
+/// a `Ok(0)` will be returned.
+pub struct UringStreamLocal {
+    rx: Receiver<RingPacket>,
+    socket: Rc<UdpSocket>,
+    deadline: Instant,
+    sockmap: SockMap<SocketAddr, RingPacket>,
+    addr: SocketAddr,
+}
+
+impl UringStreamLocal {
+    /// Get peer sockaddr.
+    #[inline]
+    pub const fn peer_addr(&self) -> SocketAddr { self.addr }
+
+    /// Get inner udp socket.
+    #[inline]
+    pub const fn inner_socket(&self) -> &Rc<UdpSocket> { &self.socket }
+
+    /// Receive a packet, which is truncated to the length of `buf`.
+    pub async fn recv(&mut self, buf: &mut [u8]) -> Result<usize> {
+        match timeout_at(self.deadline, self.rx.recv()).await {
+            Ok(Some(pkt)) => {
+                // truncate, then give the buffer back
+                let n = std::cmp::min(pkt.len(), buf.len());
+                buf[..n].copy_from_slice(&pkt[..n]);
+                drop(pkt);
+
+                // reset timer
+                self.reset_timeout();
+                Ok(n)
+            }
+            // EOF
+            _ => Ok(0),
+        }
+    }
+
+    /// Send a packet to the peer.
+    #[inline]
+    pub async fn send<T: IoBuf>(&self, buf: T) -> BufResult<usize, T> {
+        self.socket.send_to(buf, self.addr).await
+    }
+
+    /// Postpone the read timeout, as if a packet was just received.
+    #[inline]
+    pub fn reset_timeout(&mut self) { self.deadline = Instant::now() + get_timeout(); }
+}
+
+impl Drop for UringStreamLocal {
+    fn drop(&mut self) {
+        self.sockmap.remove(&self.addr);
+        // left elements are popped
+    }
+}
+
+/// Udp stream on io_uring which is actively established.
+///
+/// A `recv` call times out when there is no packet received
+/// during a period of time. This is treated as `EOF`, and
+/// a `Ok(0)` will be returned.
+pub struct UringStreamRemote {
+    socket: UdpSocket,
+    rbuf: Vec<u8>,
+    deadline: Instant,
+    addr: SocketAddr,
+}
+
+impl UringStreamRemote {
+    /// Create from a **bound** udp socket.
+    #[inline]
+    pub fn new(socket: UdpSocket, addr: SocketAddr) -> Self {
+        Self {
+            socket,
+            addr,
+            rbuf: Vec::new(),
+            deadline: Instant::now() + get_timeout(),
+        }
+    }
+
+    /// Create a udp socket with options, and bind it to `laddr`.
+    #[inline]
+    pub fn bind_with(laddr: SocketAddr, addr: SocketAddr, opts: &SockOpts) -> Result<Self> {
+        into_uring(opts.bind(laddr)?).map(|socket| Self::new(socket, addr))
+    }
+
+    /// Get peer sockaddr.
+    #[inline]
+    pub const fn peer_addr(&self) -> SocketAddr { self.addr }
+
+    /// Get inner udp socket.
+    #[inline]
+    pub const fn inner_socket(&self) -> &UdpSocket { &self.socket }
+
+    /// Receive a packet, which is truncated to the length of `buf`.
+    pub async fn recv(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let mut rbuf = std::mem::take(&mut self.rbuf);
+        rbuf.clear();
+        rbuf.reserve(buf.len());
+
+        // the buffer is held by the kernel if timed out
+        let Ok((res, rbuf)) = timeout_at(self.deadline, self.socket.recv_from(rbuf)).await else {
+            // EOF
+            return Ok(0);
+        };
+        let (n, _) = res?;
+
+        // truncate
+        let n = std::cmp::min(n, buf.len());
+        buf[..n].copy_from_slice(&rbuf[..n]);
+        self.rbuf = rbuf;
+
+        // reset timer
+        self.reset_timeout();
+        Ok(n)
+    }
+
+    /// Send a packet to the peer.
+    #[inline]
+    pub async fn send<T: IoBuf>(&self, buf: T) -> BufResult<usize, T> {
+        self.socket.send_to(buf, self.addr).await
+    }
+
+    /// Postpone the read timeout, as if a packet was just received.
+    #[inline]
+    pub fn reset_timeout(&mut self) { self.deadline = Instant::now() + get_timeout(); }
+}
+
+/// Buffers registered to the kernel, which are given back by [`RingPacket`].
+struct BufRing {
+    ring: *mut BufRingEntry,
+    ring_layout: Layout,
+    bufs: *mut u8,
+    bufs_layout: Layout,
+    entries: u16,
+    size: usize,
+    tail: Cell<u16>,
+    // buffers owned by the kernel
+    available: Cell<u16>,
+    // listener waiting for buffers
+    waker: Cell<Option<Waker>>,
+}
+
+impl BufRing {
+    fn new(entries: u16, size: usize) -> Self {
+        assert!(entries.is_power_of_two() && entries <= 0x8000);
+        assert!(size > RING_BUF_HEADER && size <= u32::MAX as usize);
+
+        // page aligned
+        let ring_layout =
+            Layout::from_size_align(entries as usize * size_of::<BufRingEntry>(), 4096).unwrap();
+        let bufs_layout = Layout::array::<u8>(entries as usize * size).unwrap();
+        let ring = unsafe { alloc::alloc_zeroed(ring_layout) } as *mut BufRingEntry;
+        if ring.is_null() {
+            alloc::handle_alloc_error(ring_layout);
+        }
+        let bufs = unsafe { alloc::alloc(bufs_layout) };
+        if bufs.is_null() {
+            alloc::handle_alloc_error(bufs_layout);
+        }
+
+        let this = Self {
+            ring,
+            ring_layout,
+            bufs,
+            bufs_layout,
+            entries,
+            size,
+            tail: Cell::new(0),
+            available: Cell::new(0),
+            waker: Cell::new(None),
+        };
+        for bid in 0..entries {
+            this.push(bid);
+        }
+        this
+    }
+
+    /// Provide a buffer to the kernel.
+    fn push(&self, bid: u16) {
+        let tail = self.tail.get();
+        // SAFETY: the kernel does not read entries after the tail
+        unsafe {
+            let entry = &mut *self.ring.add((tail & (self.entries - 1)) as usize);
+            entry.set_addr(self.bufs.add(bid as usize * self.size) as u64);
+            entry.set_len(self.size as u32);
+            entry.set_bid(bid);
+            let shared = BufRingEntry::tail(self.ring) as *const AtomicU16;
+            (*shared).store(tail.wrapping_add(1), Ordering::Release);
+        }
+        self.tail.set(tail.wrapping_add(1));
+        self.available.set(self.available.get() + 1);
+    }
+
+    /// A buffer is taken by a completion.
+    fn take(self: &Rc<Self>, bid: u16, len: usize) -> RingPacket {
+        self.available.set(self.available.get() - 1);
+        RingPacket {
+            bufs: self.clone(),
+            bid,
+            off: 0,
+            len,
+        }
+    }
+
+    fn give_back(&self, bid: u16) {
+        self.push(bid);
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl Drop for BufRing {
+    fn drop(&mut self) {
+        unsafe {
+            alloc::dealloc(self.ring as *mut u8, self.ring_layout);
+            alloc::dealloc(self.bufs, self.bufs_layout);
+        }
+    }
+}
+
+/// A received packet, which stays in its buffer until dropped.
+struct RingPacket {
+    bufs: Rc<BufRing>,
+    bid: u16,
+    off: usize,
+    len: usize,
+}
+
+impl Deref for RingPacket {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        let start = self.bid as usize * self.bufs.size + self.off;
+        // SAFETY: the kernel has filled it, and does not own it until dropped
+        unsafe { std::slice::from_raw_parts(self.bufs.bufs.add(start), self.len) }
+    }
+}
+
+impl Drop for RingPacket {
+    fn drop(&mut self) { self.bufs.give_back(self.bid); }
+}
+
+/// Multishot `recvmsg` on an io_uring instance, which is woken by tokio.
+struct RecvRing {
+    // deregistered before the ring is closed
+    fd: AsyncFd<RawFd>,
+    // closed before the buffers are freed
+    ring: IoUring,
+    msghdr: Box<libc::msghdr>,
+    bufs: Rc<BufRing>,
+    socket: RawFd,
+    armed: bool,
+}
+
+impl RecvRing {
+    fn new(socket: RawFd, entries: u16, size: usize) -> Result<Self> {
+        // each completion takes a buffer, plus the final one
+        let ring = IoUring::builder()
+            .setup_cqsize(entries as u32 * 2)
+            .build(4)?;
+        let bufs = Rc::new(BufRing::new(entries, size));
+        ring.submitter().register_buf_ring(bufs.ring as u64, entries, BGID)?;
+
+        // only the lengths are used by multishot recvmsg
+        let mut msghdr: Box<libc::msghdr> = Box::new(unsafe { zeroed() });
+        msghdr.msg_namelen = size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+
+        Ok(Self {
+            fd: AsyncFd::with_interest(ring.as_raw_fd(), Interest::READABLE)?,
+            ring,
+            msghdr,
+            bufs,
+            socket,
+            armed: false,
+        })
+    }
+
+    fn arm(&mut self) -> Result<()> {
+        let sqe = opcode::RecvMsgMulti::new(types::Fd(self.socket), &*self.msghdr, BGID).build();
+        // SAFETY: the msghdr and the buffers outlive the ring
+        unsafe { self.ring.submission().push(&sqe) }
+            .map_err(|_| Error::other("submission queue is full"))?;
+        self.ring.submit()?;
+        self.armed = true;
+        Ok(())
+    }
+
+    fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Result<(RingPacket, SocketAddr)>> {
+        loop {
+            if !self.armed && self.bufs.available.get() > 0 {
+                self.arm()?;
+            }
+
+            if let Some(cqe) = self.ring.completion().next() {
+                // the request is done, and is submitted again
+                if !cqueue::more(cqe.flags()) {
+                    self.armed = false;
+                }
+                let res = cqe.result();
+                if res < 0 {
+                    // out of buffers, until packets are dropped
+                    if res == -libc::ENOBUFS {
+                        continue;
+                    }
+                    return Poll::Ready(Err(Error::from_raw_os_error(-res)));
+                }
+                let Some(bid) = cqueue::buffer_select(cqe.flags()) else {
+                    continue;
+                };
+
+                let mut pkt = self.bufs.take(bid, res as usize);
+                let Ok(msg) = RecvMsgOut::parse(&pkt, &self.msghdr) else {
+                    continue;
+                };
+                // larger than the buffer
+                if msg.is_payload_truncated() || msg.is_name_data_truncated() {
+                    continue;
+                }
+                let mut storage: libc::sockaddr_storage = unsafe { zeroed() };
+                let name = msg.name_data();
+                unsafe {
+                    let dst = &mut storage as *mut _ as *mut u8;
+                    std::ptr::copy_nonoverlapping(name.as_ptr(), dst, name.len());
+                }
+                let Ok(addr) = (unsafe { to_socket_addr(&storage) }) else {
+                    continue;
+                };
+                let payload = msg.payload_data();
+                let (off, len) = (payload.as_ptr() as usize - pkt.as_ptr() as usize, payload.len());
+                pkt.off = off;
+                pkt.len = len;
+                return Poll::Ready(Ok((pkt, addr)));
+            }
+
+            // out of buffers
+            if !self.armed {
+                self.bufs.waker.set(Some(cx.waker().clone()));
+                return Poll::Pending;
+            }
+            let mut guard = ready!(self.fd.poll_read_ready(cx))?;
+            guard.clear_ready();
+        }
+    }
+}
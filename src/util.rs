@@ -0,0 +1,8 @@
+//! Helpers shared by protocol layers.
+
+/// Restore a 64-bit sequence number from its low 32 bits, which is close to `base`.
+#[inline]
+pub(crate) fn extend_seq(base: u64, seq: u32) -> Option<u64> {
+    let diff = seq.wrapping_sub(base as u32) as i32 as i64;
+    u64::try_from(base as i64 + diff).ok()
+}
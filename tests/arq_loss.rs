@@ -0,0 +1,121 @@
+use std::io::Result;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{ReadBuf, AsyncReadExt, AsyncWriteExt};
+use tokio::time::timeout;
+use udpflow::{UdpSocket, UdpStreamRemote, DatagramStream};
+use udpflow::arq::{Reliable, ArqConfig};
+
+const BIND: &str = "127.0.0.1:10000";
+const SENDER: &str = "127.0.0.1:5000";
+const SPLIT_BIND: &str = "127.0.0.1:10001";
+const SPLIT_SENDER: &str = "127.0.0.1:5001";
+const SIZE: usize = 0x100000;
+
+// drop about 1/8 of sent datagrams
+struct Lossy<S> {
+    inner: S,
+    seed: u32,
+}
+
+impl<S: DatagramStream> DatagramStream for Lossy<S> {
+    fn poll_recv(&mut self, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<Result<()>> {
+        self.inner.poll_recv(cx, buf)
+    }
+
+    fn poll_send(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<()>> {
+        self.seed = self.seed.wrapping_mul(1103515245).wrapping_add(12345);
+        if (self.seed >> 16).is_multiple_of(8) {
+            return Poll::Ready(Ok(()));
+        }
+        self.inner.poll_send(cx, buf)
+    }
+}
+
+async fn reliable(laddr: &str, raddr: &str, seed: u32) -> Reliable<Lossy<UdpStreamRemote>> {
+    let socket = UdpSocket::bind(laddr).await.unwrap();
+    let inner = UdpStreamRemote::new(socket, raddr.parse().unwrap());
+    Reliable::new(Lossy { inner, seed }, ArqConfig::default())
+}
+
+#[tokio::test]
+async fn arq_loss() {
+    let client = reliable(SENDER, BIND, 1).await;
+    let server = reliable(BIND, SENDER, 2).await;
+    tokio::select! {
+        _ = run_client(client) => {},
+        _ = run_server(server) => panic!("server exited")
+    };
+}
+
+// the reader is woken by data which arrives after the writer task is done
+#[tokio::test]
+async fn arq_loss_split_tasks() {
+    let client = reliable(SPLIT_SENDER, SPLIT_BIND, 3).await;
+    let mut server = reliable(SPLIT_BIND, SPLIT_SENDER, 4).await;
+    let msg: Vec<u8> = (0..4096).map(|x| (x % 251) as u8).collect();
+
+    let len = msg.len();
+    tokio::spawn(async move {
+        let mut buf = vec![0u8; len];
+        server.read_exact(&mut buf).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        server.write_all(&buf).await.unwrap();
+        server.flush().await.unwrap();
+        std::future::pending::<()>().await;
+    });
+
+    let (mut rd, mut wr) = tokio::io::split(client);
+    let read = tokio::spawn(async move {
+        let mut buf = vec![0u8; len];
+        rd.read_exact(&mut buf).await.unwrap();
+        buf
+    });
+    let write = tokio::spawn(async move {
+        wr.write_all(&msg).await.unwrap();
+        wr.flush().await.unwrap();
+        // poll the stream after the reader
+        tokio::task::yield_now().await;
+        wr.flush().await.unwrap();
+        (wr, msg)
+    });
+
+    let (_wr, msg) = write.await.unwrap();
+    let buf = timeout(Duration::from_secs(5), read).await.expect("reader is not woken");
+    assert_eq!(buf.unwrap(), msg);
+}
+
+async fn run_client(stream: Reliable<Lossy<UdpStreamRemote>>) {
+    let msg: Vec<u8> = (0..SIZE).map(|x| (x % 251) as u8).collect();
+    let (mut rd, mut wr) = tokio::io::split(stream);
+
+    let write = async {
+        println!("client: send..");
+        wr.write_all(&msg).await.unwrap();
+        wr.shutdown().await.unwrap();
+    };
+    let read = async {
+        let mut buf = Vec::new();
+        rd.read_to_end(&mut buf).await.unwrap();
+        println!("client: recv {} bytes", buf.len());
+        buf
+    };
+
+    let (_, buf) = tokio::join!(write, read);
+    assert_eq!(buf, msg);
+
+    let stats = rd.unsplit(wr).stats();
+    println!("client: {:?}", stats);
+    assert!(stats.retransmitted + stats.fast_retransmitted > 0);
+}
+
+async fn run_server(stream: Reliable<Lossy<UdpStreamRemote>>) {
+    let (mut rd, mut wr) = tokio::io::split(stream);
+    let n = tokio::io::copy(&mut rd, &mut wr).await.unwrap();
+    println!("server: echo {} bytes", n);
+    assert_eq!(n, SIZE as u64);
+    wr.shutdown().await.unwrap();
+
+    // keep acknowledging until the client is done
+    std::future::pending::<()>().await;
+}
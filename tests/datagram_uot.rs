@@ -0,0 +1,118 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::time::sleep;
+use tokio::net::{TcpStream, TcpListener};
+use udpflow::{UdpSocket, UdpListener, UdpStreamLocal, UdpStreamRemote, UotStream};
+use udpflow::DatagramStream;
+
+const RELAY1: &str = "127.0.0.1:10000";
+const RELAY2: &str = "127.0.0.1:15000";
+const SENDER: &str = "127.0.0.1:5000";
+const RECVER: &str = "127.0.0.1:20000";
+const MSG: &[u8] = b"Ciallo";
+const WAIT: Duration = Duration::from_millis(500);
+
+#[tokio::test]
+async fn datagram_uot() {
+    tokio::select! {
+        _ = client() => {},
+        _ = async {
+            tokio::join!(async {
+                tokio::join!(relay_server1(), relay_server2())
+            }, echo_server())
+        } => {}
+    };
+}
+
+async fn client() {
+    sleep(WAIT).await;
+
+    let addr = RELAY1.parse::<SocketAddr>().unwrap();
+    let socket = UdpSocket::bind(SENDER).await.unwrap();
+    let mut buf = [0u8; 64];
+
+    // send a burst, each datagram must arrive as a whole
+    for i in 1..=MSG.len() {
+        println!("client: send[{}]..", i);
+        let n = socket.send_to(&MSG[..i], addr).await.unwrap();
+        assert_eq!(n, i);
+    }
+
+    for i in 1..=MSG.len() {
+        println!("client: recv[{}]..", i);
+        let (n, addr2) = socket.recv_from(&mut buf).await.unwrap();
+        assert_eq!(addr, addr2);
+        assert_eq!(&buf[..n], &MSG[..i]);
+    }
+}
+
+// move whole datagrams, regardless of the stream type
+async fn copy<A: DatagramStream, B: DatagramStream>(mut a: A, mut b: B) {
+    let mut buf1 = vec![0u8; 0x2000];
+    let mut buf2 = vec![0u8; 0x2000];
+    loop {
+        tokio::select! {
+            n = a.recv(&mut buf1) => {
+                let n = n.unwrap();
+                if n == 0 { return; }
+                b.send(&buf1[..n]).await.unwrap();
+            }
+            n = b.recv(&mut buf2) => {
+                let n = n.unwrap();
+                if n == 0 { return; }
+                a.send(&buf2[..n]).await.unwrap();
+            }
+        }
+    }
+}
+
+// udp -> tcp
+async fn relay_server1() {
+    let socket = UdpSocket::bind(RELAY1).await.unwrap();
+    let listener = UdpListener::new(socket);
+
+    let mut buf = vec![0u8; 0x2000];
+
+    while let Ok((stream, addr)) = listener.accept(&mut buf).await {
+        assert_eq!(addr, SENDER.parse().unwrap());
+        tokio::spawn(handle1(stream));
+    }
+}
+
+async fn handle1(stream1: UdpStreamLocal) {
+    let stream2 = UotStream::new(TcpStream::connect(RELAY2).await.unwrap());
+    copy(stream1, stream2).await;
+}
+
+// tcp -> udp
+async fn relay_server2() {
+    let listener = TcpListener::bind(RELAY2).await.unwrap();
+
+    while let Ok((stream, _)) = listener.accept().await {
+        tokio::spawn(handle2(UotStream::new(stream)));
+    }
+}
+
+async fn handle2(stream1: UotStream<TcpStream>) {
+    let socket = UdpSocket::bind("0.0.0.0:0").await.unwrap();
+    let stream2 = UdpStreamRemote::new(socket, RECVER.parse().unwrap());
+    copy(stream1, stream2).await;
+}
+
+async fn echo_server() {
+    let socket = UdpSocket::bind(RECVER).await.unwrap();
+
+    let mut buf = vec![0u8; 32];
+    let mut i = 0;
+
+    loop {
+        println!("server: recv[{}]..", i);
+        let (n, addr) = socket.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], &MSG[..n]);
+
+        println!("server: send[{}]..", i);
+        let n2 = socket.send_to(&buf[..n], addr).await.unwrap();
+        assert_eq!(n, n2);
+        i += 1;
+    }
+}
@@ -0,0 +1,64 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::time::sleep;
+use udpflow::{UdpSocket, UdpListener, UdpStreamLocal, UdpStreamRemote, DatagramStream};
+use udpflow::frag::{Fragmented, FragConfig};
+
+const BIND: &str = "127.0.0.1:10000";
+const SENDER: &str = "127.0.0.1:5000";
+const WAIT: Duration = Duration::from_millis(500);
+const SIZES: [usize; 4] = [1, 1194, 0x8000, 0xffff];
+
+#[tokio::test]
+async fn frag_echo() {
+    tokio::select! {
+        _ = client() => {},
+        _ = server() => {}
+    };
+}
+
+async fn client() {
+    sleep(WAIT).await;
+
+    let addr = BIND.parse::<SocketAddr>().unwrap();
+    let socket = UdpSocket::bind(SENDER).await.unwrap();
+    let mut stream = Fragmented::new(UdpStreamRemote::new(socket, addr), FragConfig::default());
+    let mut buf = vec![0u8; 0x10000];
+
+    for (i, size) in SIZES.into_iter().enumerate() {
+        let msg: Vec<u8> = (0..size).map(|x| x as u8).collect();
+
+        println!("client: send[{}]..", i);
+        stream.send(&msg).await.unwrap();
+
+        println!("client: recv[{}]..", i);
+        let n = stream.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], &msg);
+    }
+}
+
+async fn server() {
+    let socket = UdpSocket::bind(BIND).await.unwrap();
+    let listener = UdpListener::new(socket);
+
+    let mut buf = vec![0u8; 0x2000];
+
+    while let Ok((stream, addr)) = listener.accept(&mut buf).await {
+        assert_eq!(addr, SENDER.parse().unwrap());
+        tokio::spawn(handle(Fragmented::new(stream, FragConfig::default())));
+    }
+}
+
+async fn handle(mut stream: Fragmented<UdpStreamLocal>) {
+    let mut buf = vec![0u8; 0x10000];
+    let mut i = 0;
+    loop {
+        println!("server: recv[{}]..", i);
+        let n = stream.recv(&mut buf).await.unwrap();
+        assert_eq!(n, SIZES[i]);
+
+        println!("server: send[{}]..", i);
+        stream.send(&buf[..n]).await.unwrap();
+        i += 1;
+    }
+}
@@ -0,0 +1,107 @@
+#![cfg(all(feature = "noise", feature = "runtime-tokio"))]
+
+use std::io::Result;
+use std::net::SocketAddr;
+use std::time::Duration;
+use std::task::{Context, Poll};
+use tokio::io::ReadBuf;
+use tokio::time::sleep;
+use udpflow::{UdpSocket, UdpListener, UdpStreamLocal, UdpStreamRemote, DatagramStream};
+use udpflow::noise::{self, NoiseConfig, NoiseListener, NoiseStream, Pattern};
+
+// one pair for each pattern
+const ADDRS: [(&str, &str); 2] =
+    [("127.0.0.1:10000", "127.0.0.1:5000"), ("127.0.0.1:15000", "127.0.0.1:20000")];
+const MSG: &[u8] = b"Ciallo";
+const WAIT: Duration = Duration::from_millis(200);
+const RETRANSMIT: Duration = Duration::from_millis(100);
+
+// drop the first sent packet
+struct DropFirst<S> {
+    inner: S,
+    dropped: bool,
+}
+
+impl<S: DatagramStream> DatagramStream for DropFirst<S> {
+    fn poll_recv(&mut self, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<Result<()>> {
+        self.inner.poll_recv(cx, buf)
+    }
+
+    fn poll_send(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<()>> {
+        if !self.dropped {
+            self.dropped = true;
+            return Poll::Ready(Ok(()));
+        }
+        self.inner.poll_send(cx, buf)
+    }
+}
+
+#[tokio::test]
+async fn noise_echo() {
+    let (server_private, server_public) = noise::generate_keypair().unwrap();
+    let (client_private, client_public) = noise::generate_keypair().unwrap();
+
+    for (pattern, (bind, sender)) in [Pattern::IK, Pattern::XX].into_iter().zip(ADDRS) {
+        let mut server_conf = NoiseConfig::new(pattern, server_private.clone());
+        server_conf.retransmit = RETRANSMIT;
+        let mut client_conf = NoiseConfig::new(pattern, client_private.clone());
+        client_conf.retransmit = RETRANSMIT;
+        if pattern == Pattern::IK {
+            client_conf.remote_public_key = Some(server_public.clone());
+        }
+
+        tokio::select! {
+            _ = client(bind, sender, &client_conf, &server_public) => {},
+            _ = server(bind, sender, server_conf, &client_public) => {}
+        };
+    }
+}
+
+async fn client(bind: &str, sender: &str, conf: &NoiseConfig, server_public: &[u8]) {
+    sleep(WAIT).await;
+
+    let addr = bind.parse::<SocketAddr>().unwrap();
+    let socket = UdpSocket::bind(sender).await.unwrap();
+    let inner = DropFirst {
+        inner: UdpStreamRemote::new(socket, addr),
+        dropped: false,
+    };
+
+    println!("client: handshake..");
+    let mut stream = noise::initiate(inner, conf).await.unwrap();
+    assert_eq!(stream.remote_static(), Some(server_public));
+
+    let mut buf = vec![0u8; 0x2000];
+    for i in 0..3 {
+        println!("client: send[{}]..", i);
+        stream.send(MSG).await.unwrap();
+
+        println!("client: recv[{}]..", i);
+        let n = stream.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], MSG);
+    }
+}
+
+async fn server(bind: &str, sender: &str, conf: NoiseConfig, client_public: &[u8]) {
+    let socket = UdpSocket::bind(bind).await.unwrap();
+    let listener = NoiseListener::new(UdpListener::new(socket), conf);
+
+    let (stream, addr) = listener.accept().await.unwrap();
+    assert_eq!(addr, sender.parse().unwrap());
+    assert_eq!(stream.remote_static(), Some(client_public));
+    handle(stream).await;
+}
+
+async fn handle(mut stream: NoiseStream<UdpStreamLocal>) {
+    let mut buf = vec![0u8; 0x2000];
+    let mut i = 0;
+    loop {
+        println!("server: recv[{}]..", i);
+        let n = stream.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], MSG);
+
+        println!("server: send[{}]..", i);
+        stream.send(&buf[..n]).await.unwrap();
+        i += 1;
+    }
+}
@@ -0,0 +1,92 @@
+#![cfg(any(target_os = "linux", target_os = "android"))]
+
+use std::io::ErrorKind;
+use std::os::fd::AsRawFd;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use udpflow::{UdpSocket, UdpStreamRemote, SockOpts, DatagramStream};
+use udpflow::pmtu::max_payload;
+
+const BIND: &str = "127.0.0.1:10000";
+const SENDER: &str = "127.0.0.1:5000";
+const PROBE_WAIT: Duration = Duration::from_millis(10);
+const CANCEL_BIND: &str = "127.0.0.1:10001";
+const CANCEL_SENDER: &str = "127.0.0.1:5001";
+
+fn mtu_discover(socket: &UdpSocket) -> i32 {
+    let mut value = 0i32;
+    let mut len = std::mem::size_of::<i32>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_IP,
+            libc::IP_MTU_DISCOVER,
+            &mut value as *mut _ as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    assert_eq!(ret, 0);
+    value
+}
+
+#[tokio::test]
+async fn pmtu() {
+    let server = UdpSocket::bind(BIND).await.unwrap();
+
+    let opts = SockOpts {
+        dont_fragment: Some(true),
+        ..Default::default()
+    };
+    let mut stream =
+        UdpStreamRemote::bind_with(SENDER.parse().unwrap(), BIND.parse().unwrap(), &opts)
+            .unwrap();
+
+    // loopback
+    let mtu = stream.path_mtu().unwrap();
+    println!("path mtu: {}", mtu);
+    assert!(mtu >= 1280);
+
+    let size = stream.probe_mtu(2000, PROBE_WAIT).await.unwrap();
+    println!("probed: {}", size);
+    assert_eq!(size, std::cmp::min(2000, max_payload(mtu, false)));
+
+    // probes are sent from the stream's own socket
+    let mut buf = vec![0u8; 2000];
+    let mut probes = 0;
+    while let Ok((_, addr)) = server.try_recv_from(&mut buf) {
+        assert_eq!(addr, SENDER.parse().unwrap());
+        probes += 1;
+    }
+    assert!(probes > 0);
+
+    // oversized writes
+    stream.set_max_datagram_size(size);
+    let buf = vec![0u8; size + 1];
+    let err = stream.write(&buf).await.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    let err = stream.send(&buf).await.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+    // still usable
+    let n = stream.write(&buf[..size]).await.unwrap();
+    assert_eq!(n, size);
+    stream.send(&buf[..size]).await.unwrap();
+}
+
+#[tokio::test]
+async fn pmtu_cancelled() {
+    let _server = UdpSocket::bind(CANCEL_BIND).await.unwrap();
+    let stream = UdpStreamRemote::bind_with(
+        CANCEL_SENDER.parse().unwrap(),
+        CANCEL_BIND.parse().unwrap(),
+        &SockOpts::default(),
+    )
+    .unwrap();
+    let prev = mtu_discover(stream.inner_socket());
+
+    // dropped while waiting for icmp feedback
+    let probe = stream.probe_mtu(2000, Duration::from_secs(10));
+    let res = tokio::time::timeout(PROBE_WAIT, probe).await;
+    assert!(res.is_err());
+    assert_eq!(mtu_discover(stream.inner_socket()), prev);
+}
@@ -0,0 +1,88 @@
+#![cfg(any(target_os = "linux", target_os = "android"))]
+
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, SystemTime};
+use socket2::SockRef;
+use tokio::time::sleep;
+use udpflow::{UdpSocket, UdpListener, UdpStreamLocal, UdpStreamRemote, SockOpts, RecvMeta};
+
+const BIND: &str = "127.0.0.1:10000";
+const SENDER: &str = "127.0.0.1:5000";
+const MSG: &[u8] = b"Ciallo";
+const WAIT: Duration = Duration::from_millis(300);
+const TTL: u32 = 33;
+// ECT(0)
+const ECN: u32 = 0b10;
+
+#[tokio::test]
+async fn recv_meta() {
+    tokio::select! {
+        _ = client() => {},
+        _ = server() => {}
+    };
+}
+
+fn check(meta: RecvMeta) {
+    println!("meta: {:?}", meta);
+    assert_eq!(meta.ttl, Some(TTL as u8));
+    assert_eq!(meta.ecn, Some(ECN as u8));
+    assert_eq!(meta.dst, Some("127.0.0.1".parse::<IpAddr>().unwrap()));
+    let elapsed = SystemTime::now().duration_since(meta.timestamp.unwrap()).unwrap();
+    assert!(elapsed < Duration::from_secs(1));
+}
+
+async fn client() {
+    sleep(WAIT).await;
+
+    let opts = SockOpts {
+        ttl: Some(TTL),
+        tos: Some(ECN),
+        recv_meta: true,
+        ..Default::default()
+    };
+    let mut stream =
+        UdpStreamRemote::bind_with(SENDER.parse().unwrap(), BIND.parse().unwrap(), &opts)
+            .unwrap();
+    let mut buf = [0u8; 32];
+
+    for i in 0..3 {
+        println!("client: send[{}]..", i);
+        udpflow::DatagramStream::send(&mut stream, MSG).await.unwrap();
+
+        println!("client: recv[{}]..", i);
+        let (n, meta) = stream.recv_with_meta(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], MSG);
+        check(meta);
+    }
+}
+
+async fn server() {
+    let opts = SockOpts {
+        recv_meta: true,
+        ..Default::default()
+    };
+    let listener = UdpListener::bind_with(BIND.parse().unwrap(), &opts).unwrap();
+
+    let mut buf = vec![0u8; 0x2000];
+
+    while let Ok((stream, addr)) = listener.accept(&mut buf).await {
+        assert_eq!(addr, SENDER.parse::<SocketAddr>().unwrap());
+        tokio::spawn(handle(stream));
+    }
+}
+
+async fn handle(mut stream: UdpStreamLocal) {
+    // reply with the same ttl and ecn
+    let socket: &UdpSocket = stream.inner_socket();
+    SockRef::from(socket).set_ttl_v4(TTL).unwrap();
+    SockRef::from(socket).set_tos_v4(ECN).unwrap();
+
+    let mut buf = [0u8; 32];
+    loop {
+        let (n, meta) = stream.recv_with_meta(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], MSG);
+        check(meta);
+
+        udpflow::DatagramStream::send(&mut stream, &buf[..n]).await.unwrap();
+    }
+}
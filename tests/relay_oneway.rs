@@ -0,0 +1,77 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::time::sleep;
+use udpflow::{UdpSocket, UdpListener, UdpStreamLocal, UdpStreamRemote};
+use udpflow::{relay, CloseReason};
+
+const BIND: &str = "127.0.0.1:10000";
+const SENDER: &str = "127.0.0.1:5000";
+const RECVER: &str = "127.0.0.1:15000";
+const MSG: &[u8] = b"Ciallo";
+const WAIT: Duration = Duration::from_millis(200);
+const TIMEOUT: Duration = Duration::from_millis(500);
+
+#[tokio::test]
+async fn relay_oneway() {
+    udpflow::set_timeout(TIMEOUT);
+    tokio::select! {
+        _ = async { tokio::join!(client(), relay_server()) } => {},
+        _ = recv_server() => {}
+    };
+}
+
+// one-way flow, which lasts longer than the timeout
+async fn client() {
+    sleep(WAIT).await;
+
+    let addr = BIND.parse::<SocketAddr>().unwrap();
+    let socket = UdpSocket::bind(SENDER).await.unwrap();
+
+    for i in 1..=6 {
+        println!("client: send[{}]..", i);
+        let n = socket.send_to(&MSG[..i], addr).await.unwrap();
+        assert_eq!(n, i);
+        sleep(WAIT).await;
+    }
+}
+
+async fn relay_server() {
+    let socket = UdpSocket::bind(BIND).await.unwrap();
+    let listener = UdpListener::new(socket);
+
+    let mut buf = vec![0u8; 0x2000];
+
+    let (stream, addr) = listener.accept(&mut buf).await.unwrap();
+    assert_eq!(addr, SENDER.parse().unwrap());
+
+    tokio::select! {
+        _ = handle(stream) => {},
+        _ = listener.accept(&mut buf) => unreachable!(),
+    }
+}
+
+async fn handle(stream1: UdpStreamLocal) {
+    let socket = UdpSocket::bind("0.0.0.0:0").await.unwrap();
+    let stream2 = UdpStreamRemote::new(socket, RECVER.parse().unwrap());
+    let stats = relay(stream1, stream2).await;
+    println!("relay: {:?}", stats);
+
+    assert!(matches!(stats.reason, CloseReason::Timeout));
+    assert_eq!(stats.a_to_b.packets, 6);
+    assert_eq!(stats.a_to_b.bytes, (1..=6).sum());
+    assert_eq!(stats.b_to_a.packets, 0);
+}
+
+async fn recv_server() {
+    let socket = UdpSocket::bind(RECVER).await.unwrap();
+
+    let mut buf = vec![0u8; 32];
+
+    for i in 1..=6 {
+        println!("server: recv[{}]..", i);
+        let (n, _) = socket.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], &MSG[..i]);
+    }
+
+    std::future::pending::<()>().await;
+}
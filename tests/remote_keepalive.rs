@@ -0,0 +1,75 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::time::sleep;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use udpflow::{UdpSocket, UdpStreamRemote, Keepalive};
+
+const BIND: &str = "127.0.0.1:10000";
+const SENDER: &str = "127.0.0.1:5000";
+const MSG: &[u8] = b"Ciallo";
+const PING: &[u8] = b"ping";
+const WAIT: Duration = Duration::from_millis(300);
+const INTERVAL: Duration = Duration::from_millis(100);
+const TIMEOUT: Duration = Duration::from_millis(600);
+
+static PINGS: AtomicUsize = AtomicUsize::new(0);
+
+#[tokio::test]
+async fn remote_keepalive() {
+    udpflow::set_timeout(TIMEOUT);
+    tokio::select! {
+        _ = client() => {},
+        _ = server() => {}
+    };
+    println!("pings: {}", PINGS.load(Ordering::Relaxed));
+    assert!(PINGS.load(Ordering::Relaxed) >= 3);
+}
+
+async fn client() {
+    sleep(WAIT).await;
+
+    let addr = BIND.parse::<SocketAddr>().unwrap();
+    let socket = UdpSocket::bind(SENDER).await.unwrap();
+    let mut stream = UdpStreamRemote::new(socket, addr);
+    let mut keepalive = Keepalive::new(INTERVAL, PING);
+    keepalive.filter = true;
+    stream.set_keepalive(keepalive);
+    let mut buf = [0u8; 32];
+
+    println!("client: send..");
+    let n = stream.write(MSG).await.unwrap();
+    assert_eq!(n, MSG.len());
+
+    println!("client: recv..");
+    let n = stream.read(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], MSG);
+
+    // echoed keepalives are dropped, and do not reset the timer
+    println!("client: timeout..");
+    let n = stream.read(&mut buf).await.unwrap();
+    assert_eq!(n, 0);
+    println!("client: recv.. EOF");
+}
+
+async fn server() {
+    let socket = UdpSocket::bind(BIND).await.unwrap();
+
+    let mut buf = vec![0u8; 32];
+
+    loop {
+        let (n, addr) = socket.recv_from(&mut buf).await.unwrap();
+        assert_eq!(addr, SENDER.parse().unwrap());
+
+        if &buf[..n] == PING {
+            println!("server: ping..");
+            PINGS.fetch_add(1, Ordering::Relaxed);
+        } else {
+            assert_eq!(&buf[..n], MSG);
+        }
+
+        // echo everything
+        let n2 = socket.send_to(&buf[..n], addr).await.unwrap();
+        assert_eq!(n, n2);
+    }
+}
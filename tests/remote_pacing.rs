@@ -0,0 +1,68 @@
+use std::time::Duration;
+use tokio::time::Instant;
+use tokio::io::AsyncWriteExt;
+use udpflow::{UdpSocket, UdpStreamRemote, Pacing};
+
+const BIND: &str = "127.0.0.1:10000";
+const SENDER: &str = "127.0.0.1:5000";
+const OFF_BIND: &str = "127.0.0.1:10001";
+const OFF_SENDER: &str = "127.0.0.1:5001";
+const RATE: u64 = 100_000;
+const BURST: usize = 10_000;
+const MSG: [u8; 1000] = [0u8; 1000];
+const COUNT: usize = 30;
+
+#[tokio::test]
+async fn remote_pacing() {
+    let server = UdpSocket::bind(BIND).await.unwrap();
+
+    for txtime in [false, true] {
+        let socket = UdpSocket::bind(SENDER).await.unwrap();
+        let mut stream = UdpStreamRemote::new(socket, BIND.parse().unwrap());
+        stream.set_pacing(Pacing {
+            txtime,
+            ..Pacing::new(RATE, BURST)
+        });
+
+        // the first burst is sent immediately, the rest takes 200ms
+        let start = Instant::now();
+        for i in 0..COUNT {
+            println!("client: send[{}]..", i);
+            let n = stream.write(&MSG).await.unwrap();
+            assert_eq!(n, MSG.len());
+        }
+        let elapsed = start.elapsed();
+        println!("txtime: {}, elapsed: {:?}", txtime, elapsed);
+        assert!(elapsed >= Duration::from_millis(180));
+        assert!(elapsed < Duration::from_millis(1000));
+
+        let mut buf = vec![0u8; 0x2000];
+        for i in 0..COUNT {
+            println!("server: recv[{}]..", i);
+            let n = server.recv(&mut buf).await.unwrap();
+            assert_eq!(n, MSG.len());
+        }
+    }
+}
+
+#[tokio::test]
+async fn remote_pacing_off() {
+    let server = UdpSocket::bind(OFF_BIND).await.unwrap();
+    let socket = UdpSocket::bind(OFF_SENDER).await.unwrap();
+    let mut stream = UdpStreamRemote::new(socket, OFF_BIND.parse().unwrap());
+    stream.set_pacing(Pacing::new(RATE, BURST));
+    stream.set_pacing(Pacing::new(0, BURST));
+
+    let start = Instant::now();
+    for _ in 0..COUNT {
+        let n = stream.write(&MSG).await.unwrap();
+        assert_eq!(n, MSG.len());
+    }
+    assert!(start.elapsed() < Duration::from_millis(100));
+
+    let mut buf = vec![0u8; 0x2000];
+    for _ in 0..COUNT {
+        let n = server.recv(&mut buf).await.unwrap();
+        assert_eq!(n, MSG.len());
+    }
+}
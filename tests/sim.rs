@@ -0,0 +1,134 @@
+#![cfg(feature = "sim")]
+
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::time::{sleep, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use udpflow::{UdpListener, UdpStreamLocal, UdpStreamRemote, DatagramSocket};
+use udpflow::sim::{Network, Link, SimSocket};
+
+const BIND: &str = "10.0.0.1:10000";
+const SENDER: &str = "10.0.0.2:5000";
+const MSG: &[u8] = b"Ciallo";
+const LATENCY: Duration = Duration::from_millis(50);
+const WAIT: Duration = Duration::from_millis(500);
+
+#[tokio::test(start_paused = true)]
+async fn sim_echo() {
+    let net = Network::new(1);
+    net.set_default_link(Link {
+        latency: LATENCY,
+        ..Link::default()
+    });
+
+    tokio::select! {
+        _ = client(&net) => {},
+        _ = server(&net) => {}
+    };
+}
+
+async fn client(net: &Network) {
+    sleep(WAIT).await;
+
+    let addr = BIND.parse::<SocketAddr>().unwrap();
+    let socket = net.bind(SENDER.parse().unwrap()).unwrap();
+    let mut stream = UdpStreamRemote::new(socket, addr);
+    let mut buf = [0u8; 32];
+
+    let start = Instant::now();
+    for i in 0..5 {
+        println!("client: send[{}]..", i);
+        let n = stream.write(MSG).await.unwrap();
+        assert_eq!(n, MSG.len());
+
+        println!("client: recv[{}]..", i);
+        let n = stream.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], MSG);
+    }
+    assert_eq!(start.elapsed(), LATENCY * 10);
+}
+
+async fn server(net: &Network) {
+    let listener = UdpListener::new(net.bind(BIND.parse().unwrap()).unwrap());
+
+    let mut buf = vec![0u8; 0x2000];
+
+    while let Ok((stream, addr)) = listener.accept(&mut buf).await {
+        assert_eq!(addr, SENDER.parse().unwrap());
+        tokio::spawn(handle(stream));
+    }
+}
+
+async fn handle(mut stream: UdpStreamLocal<SimSocket>) {
+    let mut buf = [0u8; 32];
+    let mut i = 0;
+    loop {
+        println!("server: recv[{}]..", i);
+        let n = stream.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], MSG);
+
+        println!("server: send[{}]..", i);
+        let n = stream.write(&buf[..n]).await.unwrap();
+        assert_eq!(n, MSG.len());
+        i += 1;
+    }
+}
+
+// send 1000 packets over a bad link, return received sequence numbers
+async fn transfer(seed: u64) -> Vec<u16> {
+    let net = Network::new(seed);
+    let a = net.bind(SENDER.parse().unwrap()).unwrap();
+    let b = net.bind(BIND.parse().unwrap()).unwrap();
+    net.set_link(
+        a.local_addr().unwrap(),
+        b.local_addr().unwrap(),
+        Link {
+            latency: LATENCY,
+            jitter: Duration::from_millis(5),
+            loss: 0.1,
+            duplicate: 0.1,
+            reorder: 0.1,
+            reorder_delay: Duration::from_millis(20),
+        },
+    );
+
+    let mut a = UdpStreamRemote::new(a, BIND.parse().unwrap());
+    let mut b = UdpStreamRemote::new(b, SENDER.parse().unwrap());
+    for i in 0..1000u16 {
+        a.write_all(&i.to_be_bytes()).await.unwrap();
+        sleep(Duration::from_millis(1)).await;
+    }
+
+    let mut recv = Vec::new();
+    let mut buf = [0u8; 32];
+    let wait = tokio::time::timeout(Duration::from_secs(1), async {
+        loop {
+            let n = b.read(&mut buf).await.unwrap();
+            recv.push(u16::from_be_bytes([buf[0], buf[1]]));
+            assert_eq!(n, 2);
+        }
+    });
+    let _ = wait.await;
+    recv
+}
+
+#[tokio::test(start_paused = true)]
+async fn sim_conditions() {
+    let recv = transfer(7).await;
+    println!("received: {}", recv.len());
+    assert!(recv.len() > 900 && recv.len() < 1100);
+
+    let mut unique = recv.clone();
+    unique.sort_unstable();
+    unique.dedup();
+    let lost = 1000 - unique.len();
+    let duplicated = recv.len() - unique.len();
+    let reordered = recv.windows(2).filter(|x| x[1] < x[0]).count();
+    println!("lost: {}, duplicated: {}, reordered: {}", lost, duplicated, reordered);
+    assert!((50..150).contains(&lost));
+    assert!((50..150).contains(&duplicated));
+    assert!(reordered > 50);
+
+    // deterministic
+    assert_eq!(transfer(7).await, recv);
+}
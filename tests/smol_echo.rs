@@ -0,0 +1,63 @@
+#![cfg(feature = "runtime-smol")]
+
+use std::time::Duration;
+use smol::Timer;
+use smol::future::race;
+use smol::io::{AsyncReadExt, AsyncWriteExt};
+use udpflow::{SockOpts, UdpListener, UdpStreamLocal, UdpStreamRemote};
+use udpflow::async_io::{self, UdpSocket};
+
+const BIND: &str = "127.0.0.1:10000";
+const SENDER: &str = "127.0.0.1:5000";
+const MSG: &[u8] = b"Ciallo";
+const WAIT: Duration = Duration::from_millis(500);
+
+#[test]
+fn smol_echo() {
+    smol::block_on(race(client(), server()));
+}
+
+async fn client() {
+    Timer::after(WAIT).await;
+
+    let socket = async_io::bind(SENDER.parse().unwrap(), &SockOpts::default()).unwrap();
+    let mut stream = UdpStreamRemote::new(socket, BIND.parse().unwrap());
+    let mut buf = [0u8; 32];
+
+    for i in 0..5 {
+        println!("client: send[{}]..", i);
+        let n = stream.write(MSG).await.unwrap();
+        assert_eq!(n, MSG.len());
+
+        println!("client: recv[{}]..", i);
+        let n = stream.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], MSG);
+    }
+}
+
+async fn server() {
+    let socket = async_io::bind(BIND.parse().unwrap(), &SockOpts::default()).unwrap();
+    let listener = UdpListener::new(socket);
+
+    let mut buf = vec![0u8; 0x2000];
+
+    while let Ok((stream, addr)) = listener.accept(&mut buf).await {
+        assert_eq!(addr, SENDER.parse().unwrap());
+        smol::spawn(handle(stream)).detach();
+    }
+}
+
+async fn handle(mut stream: UdpStreamLocal<UdpSocket>) {
+    let mut buf = [0u8; 32];
+    let mut i = 0;
+    loop {
+        println!("server: recv[{}]..", i);
+        let n = stream.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], MSG);
+
+        println!("server: send[{}]..", i);
+        let n = stream.write(&buf[..n]).await.unwrap();
+        assert_eq!(n, MSG.len());
+        i += 1;
+    }
+}
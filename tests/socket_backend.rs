@@ -0,0 +1,123 @@
+use std::io::Result;
+use std::net::SocketAddr;
+use std::time::Duration;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{Context, Poll};
+use tokio::time::sleep;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, ReadBuf};
+use udpflow::{UdpSocket, UdpListener, UdpStreamLocal, UdpStreamRemote, DatagramSocket, RecvMeta};
+
+const BIND: &str = "127.0.0.1:10000";
+const SENDER: &str = "127.0.0.1:5000";
+const MSG: &[u8] = b"Ciallo";
+const WAIT: Duration = Duration::from_millis(500);
+
+// a custom backend, which counts sent and received packets
+struct Counted {
+    inner: UdpSocket,
+    sent: AtomicUsize,
+    recv: AtomicUsize,
+}
+
+impl Counted {
+    async fn bind(addr: &str) -> Self {
+        Self {
+            inner: UdpSocket::bind(addr).await.unwrap(),
+            sent: AtomicUsize::new(0),
+            recv: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl DatagramSocket for Counted {
+    fn poll_recv_from(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<SocketAddr>> {
+        self.inner.poll_recv_from(cx, buf)
+    }
+
+    fn poll_send_to(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+        target: &SocketAddr,
+    ) -> Poll<Result<usize>> {
+        let res = self.inner.poll_send_to(cx, buf, *target);
+        if res.is_ready() {
+            self.sent.fetch_add(1, Ordering::Relaxed);
+        }
+        res
+    }
+
+    fn local_addr(&self) -> Result<SocketAddr> { self.inner.local_addr() }
+
+    // streams receive through this capability
+    fn poll_recv_meta(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(SocketAddr, RecvMeta)>> {
+        let res = self.inner.poll_recv_meta(cx, buf);
+        if res.is_ready() {
+            self.recv.fetch_add(1, Ordering::Relaxed);
+        }
+        res
+    }
+}
+
+#[tokio::test]
+async fn socket_backend() {
+    tokio::select! {
+        _ = client() => {},
+        _ = server() => {}
+    };
+}
+
+async fn client() {
+    sleep(WAIT).await;
+
+    let addr = BIND.parse::<SocketAddr>().unwrap();
+    let mut stream = UdpStreamRemote::new(Counted::bind(SENDER).await, addr);
+    assert_eq!(stream.local_addr(), SENDER.parse().unwrap());
+    let mut buf = [0u8; 32];
+
+    for i in 0..5 {
+        println!("client: send[{}]..", i);
+        let n = stream.write(MSG).await.unwrap();
+        assert_eq!(n, MSG.len());
+
+        println!("client: recv[{}]..", i);
+        let n = stream.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], MSG);
+    }
+    assert_eq!(stream.inner_socket().sent.load(Ordering::Relaxed), 5);
+    assert_eq!(stream.inner_socket().recv.load(Ordering::Relaxed), 5);
+}
+
+async fn server() {
+    let listener = UdpListener::new(Counted::bind(BIND).await);
+
+    let mut buf = vec![0u8; 0x2000];
+
+    while let Ok((stream, addr)) = listener.accept(&mut buf).await {
+        assert_eq!(addr, SENDER.parse().unwrap());
+        tokio::spawn(handle(stream));
+    }
+}
+
+async fn handle(mut stream: UdpStreamLocal<Counted>) {
+    let mut buf = [0u8; 32];
+    let mut i = 0;
+    loop {
+        println!("server: recv[{}]..", i);
+        let n = stream.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], MSG);
+
+        println!("server: send[{}]..", i);
+        let n = stream.write(&buf[..n]).await.unwrap();
+        assert_eq!(n, MSG.len());
+        i += 1;
+    }
+}
@@ -0,0 +1,39 @@
+use socket2::SockRef;
+use udpflow::{UdpListener, UdpStreamRemote, SockOpts};
+
+const ANY: &str = "127.0.0.1:0";
+const PEER: &str = "127.0.0.1:10000";
+
+#[tokio::test]
+async fn sockopt() {
+    let opts = SockOpts {
+        recv_buffer_size: Some(0x10000),
+        send_buffer_size: Some(0x10000),
+        tos: Some(0x10),
+        ttl: Some(42),
+        ..Default::default()
+    };
+
+    let stream = UdpStreamRemote::bind_with(ANY.parse().unwrap(), PEER.parse().unwrap(), &opts)
+        .unwrap();
+    let socket = SockRef::from(stream.inner_socket());
+    assert_eq!(socket.ttl_v4().unwrap(), 42);
+    assert_eq!(socket.tos_v4().unwrap(), 0x10);
+    // the kernel may double the value
+    assert!(socket.recv_buffer_size().unwrap() >= 0x10000);
+    assert!(socket.send_buffer_size().unwrap() >= 0x10000);
+
+    let _ = UdpListener::bind_with(ANY.parse().unwrap(), &opts).unwrap();
+}
+
+#[tokio::test]
+async fn sockopt_error() {
+    let opts = SockOpts {
+        bind_device: Some(String::from("udpflow-none")),
+        ..Default::default()
+    };
+
+    let err = UdpListener::bind_with(ANY.parse().unwrap(), &opts).err().unwrap();
+    println!("{}", err);
+    assert!(err.to_string().contains("SO_BINDTODEVICE"));
+}
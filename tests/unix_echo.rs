@@ -0,0 +1,90 @@
+#![cfg(unix)]
+
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::time::sleep;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{UnixDatagram, UnixStream};
+use udpflow::{UotStream, DatagramStream};
+use udpflow::unix::{UnixListener, UnixStreamLocal, UnixStreamRemote};
+
+const MSG: &[u8] = b"Ciallo";
+const WAIT: Duration = Duration::from_millis(500);
+
+fn path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("udpflow-{}-{}.sock", std::process::id(), name));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+#[tokio::test]
+async fn unix_echo() {
+    let bind = path("bind");
+    let sender = path("sender");
+    tokio::select! {
+        _ = client(&bind, &sender) => {},
+        _ = server(&bind, &sender) => {}
+    };
+    let _ = std::fs::remove_file(&bind);
+    let _ = std::fs::remove_file(&sender);
+}
+
+async fn client(bind: &Path, sender: &Path) {
+    sleep(WAIT).await;
+
+    let socket = UnixDatagram::bind(sender).unwrap();
+    let mut stream = UnixStreamRemote::new(socket, bind.to_path_buf());
+    let mut buf = [0u8; 32];
+
+    for i in 0..5 {
+        println!("client: send[{}]..", i);
+        let n = stream.write(MSG).await.unwrap();
+        assert_eq!(n, MSG.len());
+
+        println!("client: recv[{}]..", i);
+        let n = stream.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], MSG);
+    }
+}
+
+async fn server(bind: &Path, sender: &Path) {
+    let listener = UnixListener::bind(bind).unwrap();
+
+    let mut buf = vec![0u8; 0x2000];
+
+    while let Ok((stream, path)) = listener.accept(&mut buf).await {
+        assert_eq!(&path, sender);
+        tokio::spawn(handle(stream));
+    }
+}
+
+async fn handle(mut stream: UnixStreamLocal) {
+    let mut buf = [0u8; 32];
+    let mut i = 0;
+    loop {
+        println!("server: recv[{}]..", i);
+        let n = stream.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], MSG);
+
+        println!("server: send[{}]..", i);
+        stream.send(&buf[..n]).await.unwrap();
+        i += 1;
+    }
+}
+
+#[tokio::test]
+async fn unix_uot() {
+    let (a, b) = UnixStream::pair().unwrap();
+    let mut a = UotStream::new(a);
+    let mut b = UotStream::new(b);
+    let mut buf = [0u8; 32];
+
+    for i in 0..5 {
+        println!("client: send[{}]..", i);
+        a.send(MSG).await.unwrap();
+
+        println!("server: recv[{}]..", i);
+        let n = b.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], MSG);
+    }
+}
@@ -0,0 +1,100 @@
+#![cfg(all(target_os = "linux", feature = "uring"))]
+
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::time::sleep;
+use tokio_uring::net::UdpSocket;
+use udpflow::uring::{UringListener, UringStreamLocal, UringStreamRemote};
+
+const BIND: &str = "127.0.0.1:10000";
+const SENDER: &str = "127.0.0.1:5000";
+const BURST_BIND: &str = "127.0.0.1:10001";
+const BURST_SENDER: &str = "127.0.0.1:5001";
+const MSG: &[u8] = b"Ciallo";
+const WAIT: Duration = Duration::from_millis(500);
+
+#[test]
+fn uring_echo() {
+    tokio_uring::start(async {
+        tokio::select! {
+            _ = client() => {},
+            _ = server() => {}
+        };
+    });
+}
+
+// more packets than buffers, which are given back after read
+#[test]
+fn uring_burst() {
+    tokio_uring::start(async {
+        let socket = UdpSocket::bind(BURST_BIND.parse().unwrap()).await.unwrap();
+        let listener = UringListener::with_buffers(socket, 4, 512).unwrap();
+        let sender = UdpSocket::bind(BURST_SENDER.parse().unwrap()).await.unwrap();
+        let bind: SocketAddr = BURST_BIND.parse().unwrap();
+
+        // dropped, larger than a buffer
+        let (res, _) = sender.send_to(vec![0u8; 1000], bind).await;
+        res.unwrap();
+        for i in 0..64u8 {
+            let (res, _) = sender.send_to(vec![i; 100], bind).await;
+            res.unwrap();
+        }
+
+        let (mut stream, addr) = listener.accept().await.unwrap();
+        assert_eq!(addr, BURST_SENDER.parse::<SocketAddr>().unwrap());
+        tokio_uring::spawn(async move {
+            loop {
+                let _ = listener.accept().await;
+            }
+        });
+
+        let mut buf = [0u8; 512];
+        for i in 0..64u8 {
+            let n = stream.recv(&mut buf).await.unwrap();
+            assert_eq!(&buf[..n], &[i; 100]);
+        }
+    });
+}
+
+async fn client() {
+    sleep(WAIT).await;
+
+    let socket = UdpSocket::bind(SENDER.parse().unwrap()).await.unwrap();
+    let mut stream = UringStreamRemote::new(socket, BIND.parse().unwrap());
+    let mut buf = [0u8; 32];
+
+    for i in 0..5 {
+        println!("client: send[{}]..", i);
+        let (res, _) = stream.send(MSG).await;
+        assert_eq!(res.unwrap(), MSG.len());
+
+        println!("client: recv[{}]..", i);
+        let n = stream.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], MSG);
+    }
+}
+
+async fn server() {
+    let listener = UringListener::bind(BIND.parse().unwrap()).await.unwrap();
+
+    loop {
+        let (stream, addr) = listener.accept().await.unwrap();
+        assert_eq!(addr, SENDER.parse::<SocketAddr>().unwrap());
+        tokio_uring::spawn(handle(stream));
+    }
+}
+
+async fn handle(mut stream: UringStreamLocal) {
+    let mut buf = [0u8; 32];
+    let mut i = 0;
+    loop {
+        println!("server: recv[{}]..", i);
+        let n = stream.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], MSG);
+
+        println!("server: send[{}]..", i);
+        let (res, _) = stream.send(buf[..n].to_vec()).await;
+        assert_eq!(res.unwrap(), MSG.len());
+        i += 1;
+    }
+}